@@ -0,0 +1,450 @@
+use std::io;
+use state::{Reader, State, Writer};
+
+/// The four duty cycles of the square channels, one bit per step.
+const DUTIES: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// The base periods of the noise channel, in clocks, before the shift.
+const DIVISORS: [i32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Silences a channel once it counts down to zero, if enabled by NRx4 bit 6.
+#[derive(Default)]
+pub(super) struct Length {
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    #[inline]
+    fn set(&mut self, max: u16, value: u8) {
+        self.counter = max - value as u16;
+    }
+
+    #[inline]
+    fn trigger(&mut self, max: u16) {
+        if self.counter == 0 {
+            self.counter = max;
+        }
+    }
+
+    /// Counts down, returning `true` when the channel should turn off.
+    #[inline]
+    pub(super) fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+}
+
+impl State for Length {
+    fn save(&self, writer: &mut Writer) {
+        writer.u16(self.counter);
+        writer.bool(self.enabled);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.counter = reader.u16()?.min(256);
+        self.enabled = reader.bool()?;
+        Ok(())
+    }
+}
+
+/// The volume envelope of NRx2: a starting volume and a step up or down every 1-7 ticks.
+#[derive(Default)]
+pub(super) struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    /// Whether the DAC is on, which NRx2 decides for every channel but the wave channel.
+    #[inline]
+    fn dac(&self) -> bool {
+        (self.register & 0xF8) != 0
+    }
+
+    #[inline]
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.register & 0x07;
+    }
+
+    pub(super) fn clock(&mut self) {
+        let period = self.register & 0x07;
+        if period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = period;
+            if (self.register & 0x08) != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if (self.register & 0x08) == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+impl State for Envelope {
+    fn save(&self, writer: &mut Writer) {
+        writer.bytes(&[self.register, self.volume, self.timer]);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        let mut bytes = [0; 3];
+        reader.bytes(&mut bytes)?;
+        self.register = bytes[0];
+        self.volume = bytes[1] & 0x0F;
+        self.timer = bytes[2] & 0x07;
+        Ok(())
+    }
+}
+
+/// Channels 1 and 2. Only channel 1 has the frequency sweep of NR10.
+#[derive(Default)]
+pub(super) struct Square {
+    pub(super) enabled: bool,
+    duty: u8,
+    position: u8,
+    frequency: u16,
+    timer: i32,
+    pub(super) length: Length,
+    pub(super) envelope: Envelope,
+    sweep: u8,
+    shadow: u16,
+    sweep_timer: u8,
+    sweeping: bool,
+}
+
+impl Square {
+    #[inline]
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 4
+    }
+
+    /// Advances the frequency timer by `clocks` 4 MHz clocks.
+    pub(super) fn tick(&mut self, clocks: i32) {
+        self.timer -= clocks;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.position = (self.position + 1) & 0x07;
+        }
+    }
+
+    /// The current output level, 0-15.
+    #[inline]
+    pub(super) fn output(&self) -> u8 {
+        if self.enabled && ((DUTIES[self.duty as usize] >> (7 - self.position)) & 0x01) != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    #[inline]
+    pub(super) fn dac(&self) -> bool {
+        self.envelope.dac()
+    }
+
+    /// The next frequency of the sweep, turning the channel off if it overflows.
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.shadow >> (self.sweep & 0x07);
+        let frequency = if (self.sweep & 0x08) != 0 { self.shadow - delta } else { self.shadow + delta };
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        frequency
+    }
+
+    pub(super) fn clock_sweep(&mut self) {
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer > 0 {
+            return;
+        }
+        let period = (self.sweep >> 4) & 0x07;
+        self.sweep_timer = if period == 0 { 8 } else { period };
+        if self.sweeping && period != 0 {
+            let frequency = self.sweep_frequency();
+            if frequency <= 2047 && (self.sweep & 0x07) != 0 {
+                self.frequency = frequency;
+                self.shadow = frequency;
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    pub(super) fn read(&self, register: usize) -> u8 {
+        match register {
+            0 => 0x80 | self.sweep,
+            1 => 0x3F | (self.duty << 6),
+            2 => self.envelope.register,
+            3 => 0xFF,
+            _ => 0xBF | if self.length.enabled { 0x40 } else { 0x00 },
+        }
+    }
+
+    pub(super) fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => self.sweep = value & 0x7F,
+            1 => {
+                self.duty = value >> 6;
+                self.length.set(64, value & 0x3F);
+            }
+            2 => {
+                self.envelope.register = value;
+                self.enabled &= self.dac();
+            }
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            _ => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length.enabled = (value & 0x40) != 0;
+                if (value & 0x80) != 0 {
+                    self.trigger();
+                }
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac();
+        self.length.trigger(64);
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.shadow = self.frequency;
+        let period = (self.sweep >> 4) & 0x07;
+        self.sweep_timer = if period == 0 { 8 } else { period };
+        self.sweeping = period != 0 || (self.sweep & 0x07) != 0;
+        if (self.sweep & 0x07) != 0 {
+            self.sweep_frequency();
+        }
+    }
+}
+
+impl State for Square {
+    fn save(&self, writer: &mut Writer) {
+        writer.bool(self.enabled);
+        writer.bytes(&[self.duty, self.position, self.sweep, self.sweep_timer]);
+        writer.u16(self.frequency);
+        writer.u32(self.timer as u32);
+        self.length.save(writer);
+        self.envelope.save(writer);
+        writer.u16(self.shadow);
+        writer.bool(self.sweeping);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.enabled = reader.bool()?;
+        let mut bytes = [0; 4];
+        reader.bytes(&mut bytes)?;
+        self.duty = bytes[0] & 0x03;
+        self.position = bytes[1] & 0x07;
+        self.sweep = bytes[2] & 0x7F;
+        self.sweep_timer = bytes[3] & 0x0F;
+        self.frequency = reader.u16()? & 0x07FF;
+        self.timer = (reader.u32()? as i32).max(0).min(self.period());
+        self.length.load(reader)?;
+        self.envelope.load(reader)?;
+        self.shadow = reader.u16()? & 0x07FF;
+        self.sweeping = reader.bool()?;
+        Ok(())
+    }
+}
+
+/// Channel 3, playing the 32 4-bit samples of wave RAM.
+#[derive(Default)]
+pub(super) struct Wave {
+    pub(super) enabled: bool,
+    dac: bool,
+    volume: u8,
+    position: u8,
+    frequency: u16,
+    timer: i32,
+    pub(super) length: Length,
+    pub(super) ram: [u8; 16],
+}
+
+impl Wave {
+    #[inline]
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 2
+    }
+
+    pub(super) fn tick(&mut self, clocks: i32) {
+        self.timer -= clocks;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.position = (self.position + 1) & 0x1F;
+        }
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled || self.volume == 0 {
+            return 0;
+        }
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if (self.position & 0x01) == 0 { byte >> 4 } else { byte & 0x0F };
+        sample >> (self.volume - 1)
+    }
+
+    #[inline]
+    pub(super) fn dac(&self) -> bool {
+        self.dac
+    }
+
+    pub(super) fn read(&self, register: usize) -> u8 {
+        match register {
+            0 => 0x7F | if self.dac { 0x80 } else { 0x00 },
+            1 | 3 => 0xFF,
+            2 => 0x9F | (self.volume << 5),
+            _ => 0xBF | if self.length.enabled { 0x40 } else { 0x00 },
+        }
+    }
+
+    pub(super) fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                self.dac = (value & 0x80) != 0;
+                self.enabled &= self.dac;
+            }
+            1 => self.length.set(256, value),
+            2 => self.volume = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            _ => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length.enabled = (value & 0x40) != 0;
+                if (value & 0x80) != 0 {
+                    self.enabled = self.dac;
+                    self.length.trigger(256);
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            }
+        }
+    }
+}
+
+impl State for Wave {
+    fn save(&self, writer: &mut Writer) {
+        writer.bool(self.enabled);
+        writer.bool(self.dac);
+        writer.bytes(&[self.volume, self.position]);
+        writer.u16(self.frequency);
+        writer.u32(self.timer as u32);
+        self.length.save(writer);
+        writer.bytes(&self.ram);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.enabled = reader.bool()?;
+        self.dac = reader.bool()?;
+        let mut bytes = [0; 2];
+        reader.bytes(&mut bytes)?;
+        self.volume = bytes[0] & 0x03;
+        self.position = bytes[1] & 0x1F;
+        self.frequency = reader.u16()? & 0x07FF;
+        self.timer = (reader.u32()? as i32).max(0).min(self.period());
+        self.length.load(reader)?;
+        reader.bytes(&mut self.ram)
+    }
+}
+
+/// Channel 4, a linear feedback shift register clocked at a rate NR43 picks.
+pub(super) struct Noise {
+    pub(super) enabled: bool,
+    polynomial: u8,
+    lfsr: u16,
+    timer: i32,
+    pub(super) length: Length,
+    pub(super) envelope: Envelope,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise { enabled: false, polynomial: 0, lfsr: 0x7FFF, timer: 8, length: Length::default(), envelope: Envelope::default() }
+    }
+}
+
+impl Noise {
+    #[inline]
+    fn period(&self) -> i32 {
+        DIVISORS[(self.polynomial & 0x07) as usize] << (self.polynomial >> 4)
+    }
+
+    pub(super) fn tick(&mut self, clocks: i32) {
+        self.timer -= clocks;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+            self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+            // The 7-bit mode feeds back into bit 6 too
+            if (self.polynomial & 0x08) != 0 {
+                self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+            }
+        }
+    }
+
+    #[inline]
+    pub(super) fn output(&self) -> u8 {
+        if self.enabled && (self.lfsr & 0x01) == 0 { self.envelope.volume } else { 0 }
+    }
+
+    #[inline]
+    pub(super) fn dac(&self) -> bool {
+        self.envelope.dac()
+    }
+
+    pub(super) fn read(&self, register: usize) -> u8 {
+        match register {
+            0 | 1 => 0xFF,
+            2 => self.envelope.register,
+            3 => self.polynomial,
+            _ => 0xBF | if self.length.enabled { 0x40 } else { 0x00 },
+        }
+    }
+
+    pub(super) fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {}
+            1 => self.length.set(64, value & 0x3F),
+            2 => {
+                self.envelope.register = value;
+                self.enabled &= self.dac();
+            }
+            3 => self.polynomial = value,
+            _ => {
+                self.length.enabled = (value & 0x40) != 0;
+                if (value & 0x80) != 0 {
+                    self.enabled = self.dac();
+                    self.length.trigger(64);
+                    self.timer = self.period();
+                    self.envelope.trigger();
+                    self.lfsr = 0x7FFF;
+                }
+            }
+        }
+    }
+}
+
+impl State for Noise {
+    fn save(&self, writer: &mut Writer) {
+        writer.bool(self.enabled);
+        writer.u8(self.polynomial);
+        writer.u16(self.lfsr);
+        writer.u32(self.timer as u32);
+        self.length.save(writer);
+        self.envelope.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.enabled = reader.bool()?;
+        self.polynomial = reader.u8()?;
+        self.lfsr = reader.u16()? & 0x7FFF;
+        self.timer = (reader.u32()? as i32).max(0).min(self.period());
+        self.length.load(reader)?;
+        self.envelope.load(reader)
+    }
+}
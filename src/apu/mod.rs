@@ -0,0 +1,515 @@
+#[cfg(test)]
+mod tests;
+mod channels;
+mod resampler;
+
+use std::{io, mem};
+use model::Model;
+use state::{Reader, State, Writer};
+use self::channels::{Noise, Square, Wave};
+use self::resampler::{HighPass, Resampler, Stream};
+
+/// M-cycles per second.
+const CYCLES_PER_SECOND: u64 = 1_048_576;
+
+/// How far `RateControl` moves the sample rate from the real one, at most.
+const MAX_RATE_DEVIATION: f64 = 0.005;
+
+/// Samples each oscilloscope keeps.
+pub const SCOPE_SAMPLES: usize = 1024;
+
+/// The four sound channels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+pub const CHANNELS: [Channel; 4] = [Channel::Square1, Channel::Square2, Channel::Wave, Channel::Noise];
+
+impl Channel {
+    /// The channel named `1`-`4`, `square1`, `square2`, `wave` or `noise`.
+    pub fn parse(name: &str) -> Option<Channel> {
+        match name {
+            "1" | "square1" => Some(Channel::Square1),
+            "2" | "square2" => Some(Channel::Square2),
+            "3" | "wave" => Some(Channel::Wave),
+            "4" | "noise" => Some(Channel::Noise),
+            _ => None,
+        }
+    }
+}
+
+/// NR50, NR51 and NR52 decoded, for a sound debugger.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Mixer {
+    /// NR52 bit 7.
+    pub power: bool,
+    /// Which channels are playing, from the low bits of NR52.
+    pub active: [bool; 4],
+    /// Which channels NR51 sends to each side.
+    pub left: [bool; 4],
+    pub right: [bool; 4],
+    /// The master volumes of NR50, 0-7.
+    pub left_volume: u8,
+    pub right_volume: u8,
+    /// Whether the cartridge's VIN signal is mixed into each side.
+    pub vin_left: bool,
+    pub vin_right: bool,
+}
+
+/// A write to a sound register or wave RAM, at the M-cycle it happened, counted from when
+/// logging started.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SoundWrite {
+    pub cycle: u64,
+    pub address: u16,
+    pub value: u8,
+}
+
+/// Picks the rate to resample at when emulation is paced by another clock than the audio
+/// device's, as GBS playback is by the wall clock, a frame at a time as a display would, so the
+/// two drifting apart doesn't starve or flood the device's queue. The rate rises a little while
+/// the queue is below the target and falls while it's above.
+#[derive(Copy, Clone, Debug)]
+pub struct RateControl {
+    rate: u32,
+    target: usize,
+}
+
+impl RateControl {
+    /// Aims for `target` stereo frames, a left and a right sample each, queued at the device's
+    /// `rate`.
+    pub fn new(rate: u32, target: usize) -> RateControl {
+        RateControl { rate, target: target.max(1) }
+    }
+
+    /// The rate for the next frame of video, given the stereo frames still queued. The rate moves
+    /// in proportion to how far the queue is from the target, by `MAX_RATE_DEVIATION` at most.
+    pub fn update(&self, queued: usize) -> f64 {
+        let target = self.target as f64;
+        let deviation = ((target - queued as f64) / target).clamp(-1.0, 1.0);
+        self.rate as f64 * (1.0 + MAX_RATE_DEVIATION * deviation)
+    }
+}
+
+/// The most recent samples of a channel, from -1.0 to 1.0, for drawing an oscilloscope.
+pub struct Scope {
+    samples: Vec<f32>,
+    next: usize,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope { samples: vec![0.0; SCOPE_SAMPLES], next: 0 }
+    }
+}
+
+impl Scope {
+    #[inline]
+    fn push(&mut self, sample: f32) {
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % SCOPE_SAMPLES;
+    }
+
+    /// The samples, oldest first.
+    pub fn samples(&self) -> Vec<f32> {
+        let (newer, older) = self.samples.split_at(self.next);
+        older.iter().chain(newer).cloned().collect()
+    }
+}
+
+/// The sound controller: two square channels, one with a frequency sweep, the wave channel and
+/// the noise channel, clocked by a frame sequencer that follows DIV.
+///
+/// Once a sample rate is set, the channels are mixed into interleaved stereo samples, and each
+/// channel's own contribution to the mix is kept as a separate stream when asked for. Muting and
+/// soloing only change the mix. Every level change is resampled as a band-limited step, then
+/// the audio goes through the high-pass filter of the model's output capacitor.
+#[derive(Default)]
+pub struct Apu {
+    model: Model,
+    power: bool,
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    nr50: u8,
+    nr51: u8,
+    frame_step: u8,
+    div_bit: bool,
+    sample_rate: u32,
+    resampler: Option<Resampler>,
+    charge: f32,
+    mix: [Stream; 2],
+    mix_filters: [HighPass; 2],
+    samples: Vec<i16>,
+    channel_streams: bool,
+    stems: [[Stream; 2]; 4],
+    stem_filters: [[HighPass; 2]; 4],
+    streams: [Vec<i16>; 4],
+    levels: [Stream; 4],
+    scopes: [Scope; 4],
+    muted: [bool; 4],
+    solo: [bool; 4],
+    cycles: u64,
+    log: Option<Vec<SoundWrite>>,
+}
+
+impl Apu {
+    /// An APU with the output filter of `model`.
+    pub fn new(model: Model) -> Apu {
+        Apu { model, ..Apu::default() }
+    }
+
+    /// Returns the sound hardware to its power-on state, keeping the sample rate, the streams
+    /// asked for, what's muted or soloed and the write log.
+    pub(crate) fn reset(&mut self) {
+        let (sample_rate, channel_streams) = (self.sample_rate, self.channel_streams);
+        let (muted, solo) = (self.muted, self.solo);
+        let (cycles, log) = (self.cycles, self.log.take());
+        *self = Apu::new(self.model);
+        self.set_sample_rate(sample_rate);
+        self.set_channel_streams(channel_streams);
+        self.muted = muted;
+        self.solo = solo;
+        self.cycles = cycles;
+        self.log = log;
+    }
+
+    /// Advances the channels by one M-cycle. `div` is the DIV register, whose bit 4 falling
+    /// clocks the frame sequencer at 512 Hz.
+    pub fn tick(&mut self, div: u8) {
+        self.cycles += 1;
+        let div_bit = (div & 0x10) != 0;
+        if self.power {
+            if self.div_bit && !div_bit {
+                self.step_frame();
+            }
+            self.square1.tick(4);
+            self.square2.tick(4);
+            self.wave.tick(4);
+            self.noise.tick(4);
+        }
+        self.div_bit = div_bit;
+        if self.resampler.is_some() {
+            self.draw();
+        }
+    }
+
+    /// Length counters at 256 Hz, the sweep at 128 Hz and envelopes at 64 Hz.
+    fn step_frame(&mut self) {
+        if (self.frame_step & 0x01) == 0 {
+            if self.square1.length.clock() {
+                self.square1.enabled = false;
+            }
+            if self.square2.length.clock() {
+                self.square2.enabled = false;
+            }
+            if self.wave.length.clock() {
+                self.wave.enabled = false;
+            }
+            if self.noise.length.clock() {
+                self.noise.enabled = false;
+            }
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+        self.frame_step = (self.frame_step + 1) & 0x07;
+    }
+
+    /// Each channel's DAC output, from -1.0 to 1.0, or 0.0 with its DAC off.
+    fn levels(&self) -> [f32; 4] {
+        let level = |dac: bool, output: u8| if dac { output as f32 / 7.5 - 1.0 } else { 0.0 };
+        [
+            level(self.square1.dac(), self.square1.output()),
+            level(self.square2.dac(), self.square2.output()),
+            level(self.wave.dac(), self.wave.output()),
+            level(self.noise.dac(), self.noise.output()),
+        ]
+    }
+
+    /// Draws this M-cycle's levels into the streams, then takes the samples that are done.
+    fn draw(&mut self) {
+        let mut resampler = match self.resampler.take() {
+            Some(resampler) => resampler,
+            None => return,
+        };
+        let left_volume = ((self.nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0x07) as f32 + 1.0;
+        let soloing = self.solo.iter().any(|&solo| solo);
+        let (mut left, mut right) = (0.0, 0.0);
+        for (channel, &level) in self.levels().iter().enumerate() {
+            resampler.set(&mut self.levels[channel], level);
+            // Each channel gets a quarter of the range, scaled by the master volume
+            let side = |enabled: bool, volume: f32| if enabled { level * volume / 32.0 } else { 0.0 };
+            let channel_left = side((self.nr51 & (0x10 << channel)) != 0, left_volume);
+            let channel_right = side((self.nr51 & (0x01 << channel)) != 0, right_volume);
+            if self.channel_streams {
+                resampler.set(&mut self.stems[channel][0], channel_left);
+                resampler.set(&mut self.stems[channel][1], channel_right);
+            }
+            if !self.muted[channel] && (!soloing || self.solo[channel]) {
+                left += channel_left;
+                right += channel_right;
+            }
+        }
+        resampler.set(&mut self.mix[0], left);
+        resampler.set(&mut self.mix[1], right);
+        resampler.clock();
+        let ready = resampler.ready();
+        if ready > 0 {
+            self.emit(ready);
+            resampler.advance(ready);
+        }
+        self.resampler = Some(resampler);
+    }
+
+    /// Moves finished samples out of the streams, through the high-pass filter.
+    fn emit(&mut self, ready: usize) {
+        let charge = self.charge;
+        for index in 0..ready {
+            for (stream, filter) in self.mix.iter_mut().zip(self.mix_filters.iter_mut()) {
+                self.samples.push(to_i16(filter.filter(stream.read(index), charge)));
+            }
+            for (levels, scope) in self.levels.iter_mut().zip(self.scopes.iter_mut()) {
+                scope.push(levels.read(index));
+            }
+            if self.channel_streams {
+                for channel in 0..4 {
+                    for side in 0..2 {
+                        let sample = self.stems[channel][side].read(index);
+                        let sample = self.stem_filters[channel][side].filter(sample, charge);
+                        self.streams[channel].push(to_i16(sample));
+                    }
+                }
+            }
+        }
+        for stream in self.mix.iter_mut().chain(self.levels.iter_mut()) {
+            stream.advance(ready);
+        }
+        for stems in self.stems.iter_mut() {
+            for stream in stems.iter_mut() {
+                stream.advance(ready);
+            }
+        }
+    }
+
+    /// Samples per second of the mixed and per-channel streams, or 0 to stop sampling.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate;
+        self.resampler = if rate == 0 { None } else { Some(Resampler::new(rate as f64)) };
+        self.mix = Default::default();
+        self.mix_filters = Default::default();
+        self.stems = Default::default();
+        self.stem_filters = Default::default();
+        self.levels = Default::default();
+        self.charge = if rate == 0 { 0.0 } else { self.charge_per_clock().powf(4_194_304.0 / rate as f64) as f32 };
+    }
+
+    /// How much of its charge the output capacitor keeps each 4 MHz clock, which is less on
+    /// the Game Boy Pocket and Color.
+    #[inline]
+    fn charge_per_clock(&self) -> f64 {
+        match self.model {
+            Model::Dmg | Model::Sgb => 0.999958,
+            Model::Mgb | Model::Cgb | Model::Agb => 0.998943,
+        }
+    }
+
+    /// Resamples at a slightly different rate from now on, keeping everything already drawn,
+    /// for `RateControl`. The filter stays tuned to the rate set with `set_sample_rate`.
+    pub fn adjust_sample_rate(&mut self, rate: f64) {
+        if let Some(ref mut resampler) = self.resampler {
+            resampler.set_rate(rate);
+        }
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Takes the stereo samples mixed since the last call, left first.
+    #[inline]
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        mem::take(&mut self.samples)
+    }
+
+    /// Starts or stops keeping each channel's part of the mix as a stream of its own.
+    pub fn set_channel_streams(&mut self, enabled: bool) {
+        self.channel_streams = enabled;
+        self.stems = Default::default();
+        self.stem_filters = Default::default();
+        if !enabled {
+            self.streams = Default::default();
+        }
+    }
+
+    /// Takes a channel's stereo samples since the last call, panned and at the master volume
+    /// as in the mix. The four streams add up to the mix without muting or soloing.
+    #[inline]
+    pub fn take_channel_samples(&mut self, channel: Channel) -> Vec<i16> {
+        mem::take(&mut self.streams[channel as usize])
+    }
+
+    /// The recent output of a channel, before panning, the master volume and the high-pass
+    /// filter.
+    #[inline]
+    pub fn scope(&self, channel: Channel) -> &Scope {
+        &self.scopes[channel as usize]
+    }
+
+    #[inline]
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    /// Leaves a channel out of the mix.
+    #[inline]
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    #[inline]
+    pub fn solo(&self, channel: Channel) -> bool {
+        self.solo[channel as usize]
+    }
+
+    /// While any channel is soloed, only soloed channels are mixed.
+    #[inline]
+    pub fn set_solo(&mut self, channel: Channel, solo: bool) {
+        self.solo[channel as usize] = solo;
+    }
+
+    /// Starts or stops logging writes to NR10-NR52 and wave RAM, for `take_writes`.
+    pub fn set_logging(&mut self, enabled: bool) {
+        self.cycles = 0;
+        self.log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Takes the writes logged since the last call.
+    #[inline]
+    pub fn take_writes(&mut self) -> Vec<SoundWrite> {
+        self.log.as_mut().map_or_else(Vec::new, mem::take)
+    }
+
+    /// M-cycles since logging started.
+    #[inline]
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn mixer(&self) -> Mixer {
+        let bits = |value: u8| [(value & 0x01) != 0, (value & 0x02) != 0, (value & 0x04) != 0, (value & 0x08) != 0];
+        Mixer {
+            power: self.power,
+            active: bits(self.nr52() & 0x0F),
+            left: bits(self.nr51 >> 4),
+            right: bits(self.nr51 & 0x0F),
+            left_volume: (self.nr50 >> 4) & 0x07,
+            right_volume: self.nr50 & 0x07,
+            vin_left: (self.nr50 & 0x80) != 0,
+            vin_right: (self.nr50 & 0x08) != 0,
+        }
+    }
+
+    #[inline]
+    fn nr52(&self) -> u8 {
+        let active = [self.square1.enabled, self.square2.enabled, self.wave.enabled, self.noise.enabled];
+        let bits = active.iter().enumerate().fold(0x00, |bits, (i, &on)| if on { bits | (1 << i) } else { bits });
+        0x70 | if self.power { 0x80 } else { 0x00 } | bits
+    }
+
+    /// Reads 0xFF10-0xFF3F. Unused bits and write-only registers read as 1s.
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF10 ..= 0xFF14 => self.square1.read(address as usize - 0xFF10),
+            0xFF16 ..= 0xFF19 => self.square2.read(address as usize - 0xFF15),
+            0xFF1A ..= 0xFF1E => self.wave.read(address as usize - 0xFF1A),
+            0xFF1F ..= 0xFF23 => self.noise.read(address as usize - 0xFF1F),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => self.nr52(),
+            0xFF30 ..= 0xFF3F => self.wave.ram[address as usize - 0xFF30],
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes 0xFF10-0xFF3F. With the power off in NR52, only NR52 and wave RAM can be written.
+    pub fn write(&mut self, address: u16, value: u8) {
+        if let Some(ref mut log) = self.log {
+            if address <= 0xFF26 || address >= 0xFF30 {
+                log.push(SoundWrite { cycle: self.cycles, address, value });
+            }
+        }
+        match address {
+            0xFF26 => {
+                let power = (value & 0x80) != 0;
+                if self.power && !power {
+                    // Turning off clears every register, but not wave RAM
+                    let ram = self.wave.ram;
+                    self.square1 = Square::default();
+                    self.square2 = Square::default();
+                    self.wave = Wave::default();
+                    self.wave.ram = ram;
+                    self.noise = Noise::default();
+                    self.nr50 = 0;
+                    self.nr51 = 0;
+                } else if !self.power && power {
+                    self.frame_step = 0;
+                }
+                self.power = power;
+            }
+            0xFF30 ..= 0xFF3F => self.wave.ram[address as usize - 0xFF30] = value,
+            _ if !self.power => {}
+            0xFF10 ..= 0xFF14 => self.square1.write(address as usize - 0xFF10, value),
+            0xFF16 ..= 0xFF19 => self.square2.write(address as usize - 0xFF15, value),
+            0xFF1A ..= 0xFF1E => self.wave.write(address as usize - 0xFF1A, value),
+            0xFF1F ..= 0xFF23 => self.noise.write(address as usize - 0xFF1F, value),
+            0xFF24 => self.nr50 = value,
+            0xFF25 => self.nr51 = value,
+            _ => {}
+        }
+    }
+}
+
+#[inline]
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * 32767.0) as i16
+}
+
+impl State for Apu {
+    fn save(&self, writer: &mut Writer) {
+        writer.bool(self.power);
+        self.square1.save(writer);
+        self.square2.save(writer);
+        self.wave.save(writer);
+        self.noise.save(writer);
+        writer.u8(self.nr50);
+        writer.u8(self.nr51);
+        writer.u8(self.frame_step);
+        writer.bool(self.div_bit);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.power = reader.bool()?;
+        self.square1.load(reader)?;
+        self.square2.load(reader)?;
+        self.wave.load(reader)?;
+        self.noise.load(reader)?;
+        self.nr50 = reader.u8()?;
+        self.nr51 = reader.u8()?;
+        self.frame_step = reader.u8()? & 0x07;
+        self.div_bit = reader.bool()?;
+        Ok(())
+    }
+}
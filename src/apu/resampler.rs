@@ -0,0 +1,133 @@
+use std::f64::consts::PI;
+
+/// Fractional positions of a step between two output samples the kernel is tabulated for.
+const PHASES: usize = 64;
+
+/// Output samples each step is spread over. Output lags by half of this.
+const TAPS: usize = 16;
+
+/// The low-pass cutoff, as a fraction of the output rate. A little under half, so the
+/// Blackman window's transition band stays clear of aliasing.
+const CUTOFF: f64 = 0.45;
+
+/// Band-limited step synthesis: the channels only change level between M-cycles, so each change
+/// is drawn as a step whose edge is a windowed sinc, placed with sub-sample precision. The
+/// output is free of the aliasing a plain average at the output rate would leave.
+///
+/// The resampler keeps the time, shared by every `Stream` it draws into.
+pub(super) struct Resampler {
+    kernel: Vec<[f32; TAPS]>,
+    /// Output samples per M-cycle.
+    factor: f64,
+    /// The output position of the current M-cycle, from the first sample not yet read.
+    position: f64,
+}
+
+impl Resampler {
+    pub(super) fn new(rate: f64) -> Resampler {
+        let mut kernel = vec![[0.0; TAPS]; PHASES];
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let offset = phase as f64 / PHASES as f64;
+            let mut sum = 0.0;
+            let mut values = [0.0; TAPS];
+            for (tap, value) in values.iter_mut().enumerate() {
+                let x = tap as f64 - (TAPS / 2 - 1) as f64 - offset;
+                let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x) };
+                let u = x / (TAPS / 2) as f64;
+                let window = if u.abs() < 1.0 { 0.42 + 0.5 * (PI * u).cos() + 0.08 * (2.0 * PI * u).cos() } else { 0.0 };
+                *value = sinc * window;
+                sum += *value;
+            }
+            // Each phase adds up to exactly one, so steps settle at the right level
+            for (tap, &value) in taps.iter_mut().zip(values.iter()) {
+                *tap = (value / sum) as f32;
+            }
+        }
+        let mut resampler = Resampler { kernel, factor: 0.0, position: 0.0 };
+        resampler.set_rate(rate);
+        resampler
+    }
+
+    /// Changes the output rate from the next M-cycle, keeping what's been drawn.
+    #[inline]
+    pub(super) fn set_rate(&mut self, rate: f64) {
+        self.factor = rate / super::CYCLES_PER_SECOND as f64;
+    }
+
+    /// Moves on to the next M-cycle.
+    #[inline]
+    pub(super) fn clock(&mut self) {
+        self.position += self.factor;
+    }
+
+    /// Output samples no later change can reach, ready to read.
+    #[inline]
+    pub(super) fn ready(&self) -> usize {
+        self.position as usize
+    }
+
+    /// Sets a stream's level from the current M-cycle on.
+    #[inline]
+    pub(super) fn set(&self, stream: &mut Stream, level: f32) {
+        let delta = level - stream.level;
+        if delta == 0.0 {
+            return;
+        }
+        stream.level = level;
+        let sample = self.position as usize;
+        let phase = ((self.position - sample as f64) * PHASES as f64) as usize;
+        if stream.deltas.len() < sample + TAPS {
+            stream.deltas.resize(sample + TAPS, 0.0);
+        }
+        for (slot, &tap) in stream.deltas[sample..].iter_mut().zip(self.kernel[phase].iter()) {
+            *slot += delta * tap;
+        }
+    }
+
+    /// Forgets the samples every stream has read.
+    #[inline]
+    pub(super) fn advance(&mut self, samples: usize) {
+        self.position -= samples as f64;
+    }
+}
+
+/// A signal drawn by the `Resampler`, as the changes in level each output sample sees.
+#[derive(Default)]
+pub(super) struct Stream {
+    deltas: Vec<f32>,
+    level: f32,
+    sum: f32,
+}
+
+impl Stream {
+    /// The next output sample, once `Resampler::ready` says it's finished.
+    #[inline]
+    pub(super) fn read(&mut self, index: usize) -> f32 {
+        if let Some(&delta) = self.deltas.get(index) {
+            self.sum += delta;
+        }
+        self.sum
+    }
+
+    /// Drops the first `samples`, after they've all been read.
+    #[inline]
+    pub(super) fn advance(&mut self, samples: usize) {
+        let samples = samples.min(self.deltas.len());
+        self.deltas.drain(..samples);
+    }
+}
+
+/// The capacitor in series with the audio output, which lets the DC offset of the DACs fade.
+#[derive(Default)]
+pub(super) struct HighPass {
+    capacitor: f32,
+}
+
+impl HighPass {
+    #[inline]
+    pub(super) fn filter(&mut self, input: f32, charge: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * charge;
+        output
+    }
+}
@@ -0,0 +1,280 @@
+use super::*;
+use model::Model;
+
+/// Runs `cycles` M-cycles, with DIV counting up from `start` as the timer would.
+fn run(apu: &mut Apu, start: u64, cycles: u64) -> u64 {
+    for cycle in start..start + cycles {
+        apu.tick((cycle / 64) as u8);
+    }
+    start + cycles
+}
+
+fn powered() -> Apu {
+    let mut apu = Apu::default();
+    apu.write(0xFF26, 0x80);
+    apu.write(0xFF24, 0x77);
+    apu.write(0xFF25, 0xFF);
+    apu
+}
+
+/// Starts both square channels at full volume with a 50% duty.
+fn squares(apu: &mut Apu) {
+    for &base in &[0xFF10, 0xFF15] {
+        apu.write(base + 1, 0x80);
+        apu.write(base + 2, 0xF0);
+        apu.write(base + 3, 0x00);
+        apu.write(base + 4, 0x87);
+    }
+}
+
+#[test]
+fn registers() {
+    let mut apu = powered();
+    assert_eq!(0xF0, apu.read(0xFF26));
+    apu.write(0xFF10, 0xFF);
+    assert_eq!(0xFF, apu.read(0xFF10));
+    apu.write(0xFF11, 0x80);
+    assert_eq!(0xBF, apu.read(0xFF11));
+    assert_eq!(0xFF, apu.read(0xFF13));
+    assert_eq!(0xFF, apu.read(0xFF15));
+    assert_eq!(0xFF, apu.read(0xFF1F));
+    assert_eq!(0xFF, apu.read(0xFF27));
+    apu.write(0xFF1C, 0x20);
+    assert_eq!(0xBF, apu.read(0xFF1C));
+    assert_eq!(0x77, apu.read(0xFF24));
+    assert_eq!(0xFF, apu.read(0xFF25));
+    apu.write(0xFF30, 0x12);
+    assert_eq!(0x12, apu.read(0xFF30));
+}
+
+#[test]
+fn power() {
+    let mut apu = powered();
+    squares(&mut apu);
+    apu.write(0xFF30, 0x12);
+    assert_eq!(0xF3, apu.read(0xFF26));
+    apu.write(0xFF26, 0x00);
+    assert_eq!(0x70, apu.read(0xFF26));
+    assert_eq!(0x00, apu.read(0xFF24));
+    assert_eq!(0x00, apu.read(0xFF12));
+    apu.write(0xFF24, 0x77);
+    assert_eq!(0x00, apu.read(0xFF24));
+    assert_eq!(0x12, apu.read(0xFF30));
+}
+
+#[test]
+fn length() {
+    let mut apu = powered();
+    apu.write(0xFF16, 0x3E);
+    apu.write(0xFF17, 0xF0);
+    apu.write(0xFF19, 0xC0);
+    assert_eq!(0xF2, apu.read(0xFF26));
+    // Two length clocks, at steps 0 and 2 of the frame sequencer
+    let cycle = run(&mut apu, 0, 2048 * 2);
+    assert_eq!(0xF2, apu.read(0xFF26));
+    run(&mut apu, cycle, 2048 * 2);
+    assert_eq!(0xF0, apu.read(0xFF26));
+}
+
+#[test]
+fn dac() {
+    let mut apu = powered();
+    squares(&mut apu);
+    apu.write(0xFF17, 0x00);
+    assert_eq!(0xF1, apu.read(0xFF26));
+    apu.write(0xFF1A, 0x80);
+    apu.write(0xFF1E, 0x80);
+    assert_eq!(0xF5, apu.read(0xFF26));
+    apu.write(0xFF1A, 0x00);
+    assert_eq!(0xF1, apu.read(0xFF26));
+}
+
+#[test]
+fn streams() {
+    let mut apu = powered();
+    apu.set_sample_rate(48_000);
+    apu.set_channel_streams(true);
+    squares(&mut apu);
+    run(&mut apu, 0, 4096);
+    let mixed = apu.take_samples();
+    assert_eq!(374, mixed.len());
+    assert!(mixed.iter().any(|&sample| sample != 0));
+    let streams = CHANNELS.iter().map(|&channel| apu.take_channel_samples(channel)).collect::<Vec<_>>();
+    assert_eq!(mixed.len(), streams[0].len());
+    assert_eq!(streams[0], streams[1]);
+    assert!(streams[2].iter().chain(&streams[3]).all(|&sample| sample == 0));
+    for (i, &sample) in mixed.iter().enumerate() {
+        assert!((sample as i32 - streams[0][i] as i32 * 2).abs() <= 1);
+    }
+    assert!(apu.take_samples().is_empty());
+    assert!(apu.take_channel_samples(Channel::Square1).is_empty());
+}
+
+#[test]
+fn mute_solo() {
+    let mut apu = powered();
+    apu.set_sample_rate(48_000);
+    apu.set_channel_streams(true);
+    squares(&mut apu);
+    apu.set_muted(Channel::Square2, true);
+    run(&mut apu, 0, 1024);
+    assert_eq!(apu.take_channel_samples(Channel::Square1), apu.take_samples());
+    assert!(!apu.take_channel_samples(Channel::Square2).is_empty());
+
+    apu.set_muted(Channel::Square2, false);
+    assert!(!apu.muted(Channel::Square2));
+
+    let mut apu = powered();
+    apu.set_sample_rate(48_000);
+    apu.set_solo(Channel::Noise, true);
+    squares(&mut apu);
+    run(&mut apu, 0, 1024);
+    assert!(apu.take_samples().iter().all(|&sample| sample == 0));
+    assert!(apu.solo(Channel::Noise));
+}
+
+#[test]
+fn scope() {
+    let mut apu = powered();
+    assert_eq!(vec![0.0; SCOPE_SAMPLES], apu.scope(Channel::Square1).samples());
+    apu.set_sample_rate(48_000);
+    squares(&mut apu);
+    run(&mut apu, 0, 1024);
+    let samples = apu.scope(Channel::Square1).samples();
+    assert_eq!(SCOPE_SAMPLES, samples.len());
+    // The edges ring a little past the levels
+    assert!(samples.iter().all(|&sample| (-1.25..=1.25).contains(&sample)));
+    assert!(samples[SCOPE_SAMPLES - 40..].iter().any(|&sample| sample > 0.5));
+    assert!(samples[SCOPE_SAMPLES - 40..].iter().any(|&sample| sample < -0.5));
+    assert!(apu.scope(Channel::Wave).samples().iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn mixer() {
+    let mut apu = powered();
+    squares(&mut apu);
+    apu.write(0xFF24, 0x85);
+    apu.write(0xFF25, 0x12);
+    assert_eq!(Mixer {
+        power: true,
+        active: [true, true, false, false],
+        left: [true, false, false, false],
+        right: [false, true, false, false],
+        left_volume: 0,
+        right_volume: 5,
+        vin_left: true,
+        vin_right: false,
+    }, apu.mixer());
+    assert_eq!(Some(Channel::Wave), Channel::parse("3"));
+    assert_eq!(Some(Channel::Noise), Channel::parse("noise"));
+    assert_eq!(None, Channel::parse("5"));
+}
+
+#[test]
+fn state() {
+    let mut apu = powered();
+    squares(&mut apu);
+    let cycle = run(&mut apu, 0, 3000);
+    let mut writer = Writer::new();
+    apu.save(&mut writer);
+    let data = writer.into_inner();
+    let mut copy = Apu::default();
+    copy.load(&mut Reader::new(&data)).unwrap();
+    for address in 0xFF10..0xFF40 {
+        assert_eq!(apu.read(address), copy.read(address));
+    }
+    run(&mut apu, cycle, 3000);
+    run(&mut copy, cycle, 3000);
+    assert_eq!(apu.levels(), copy.levels());
+}
+
+#[test]
+fn logging() {
+    let mut apu = Apu::default();
+    apu.write(0xFF26, 0x80);
+    assert!(apu.take_writes().is_empty());
+    apu.set_logging(true);
+    apu.write(0xFF24, 0x77);
+    run(&mut apu, 0, 100);
+    apu.write(0xFF27, 0xFF);
+    apu.write(0xFF3F, 0x12);
+    assert_eq!(vec![
+        SoundWrite { cycle: 0, address: 0xFF24, value: 0x77 },
+        SoundWrite { cycle: 100, address: 0xFF3F, value: 0x12 },
+    ], apu.take_writes());
+    assert!(apu.take_writes().is_empty());
+    assert_eq!(100, apu.cycles());
+    apu.set_logging(false);
+    apu.write(0xFF24, 0x00);
+    assert!(apu.take_writes().is_empty());
+}
+
+/// The root mean square of a stretch of samples.
+fn rms(samples: &[i16]) -> f64 {
+    (samples.iter().map(|&sample| sample as f64 * sample as f64).sum::<f64>() / samples.len() as f64).sqrt()
+}
+
+#[test]
+fn band_limited() {
+    // A 65536 Hz tone, well past what 48 kHz can hold, shouldn't alias down into hearing
+    let mut apu = powered();
+    apu.set_sample_rate(48_000);
+    apu.write(0xFF11, 0x80);
+    apu.write(0xFF12, 0xF0);
+    apu.write(0xFF13, 0xFE);
+    apu.write(0xFF14, 0x87);
+    run(&mut apu, 0, 100_000);
+    let aliased = rms(&apu.take_samples()[4000..]);
+
+    // While one at 1024 Hz comes through
+    let mut apu = powered();
+    apu.set_sample_rate(48_000);
+    apu.write(0xFF11, 0x80);
+    apu.write(0xFF12, 0xF0);
+    apu.write(0xFF13, 0x00);
+    apu.write(0xFF14, 0x87);
+    run(&mut apu, 0, 100_000);
+    let audible = rms(&apu.take_samples()[4000..]);
+    assert!(audible > 4000.0);
+    assert!(aliased < audible / 32.0);
+}
+
+#[test]
+fn high_pass() {
+    // A DAC left on at volume 0 holds the output at a constant offset, which the capacitor
+    // lets fade, faster on the Game Boy Color
+    let fade = |model| {
+        let mut apu = Apu::new(model);
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF25, 0x11);
+        apu.set_sample_rate(48_000);
+        apu.write(0xFF12, 0x08);
+        run(&mut apu, 0, 40_000);
+        apu.take_samples()
+    };
+    let dmg = fade(Model::Dmg);
+    let cgb = fade(Model::Cgb);
+    assert!(dmg[40] < -500);
+    assert_eq!(dmg[40], dmg[41]);
+    assert!(dmg[1800] > dmg[40] / 10 && dmg[1800] <= 0);
+    assert!(cgb[40] > dmg[40] / 2);
+    assert!(cgb[1800].abs() <= 1);
+}
+
+#[test]
+fn rate_control() {
+    let control = RateControl::new(48_000, 4_800);
+    assert_eq!(48_000.0, control.update(4_800));
+    assert!((control.update(0) - 48_240.0).abs() < 1e-6);
+    assert!((control.update(2_400) - 48_120.0).abs() < 1e-6);
+    assert!((control.update(7_200) - 47_880.0).abs() < 1e-6);
+    assert!((control.update(9_600) - 47_760.0).abs() < 1e-6);
+    assert!((control.update(100_000) - 47_760.0).abs() < 1e-6);
+
+    let mut apu = powered();
+    apu.set_sample_rate(48_000);
+    apu.adjust_sample_rate(96_000.0);
+    run(&mut apu, 0, 4096);
+    assert_eq!(750, apu.take_samples().len());
+    assert_eq!(48_000, apu.sample_rate());
+}
@@ -1,6 +1,524 @@
 extern crate sdl2;
 extern crate gb18;
 
+use std::{env, fs, io, process, thread};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use gb18::{Cpu, Hardware, Mbc0, Mbc1, Tracer};
+use gb18::apu::{Apu, RateControl};
+use gb18::capture::{self, AviWriter, FrameSequence, VgmWriter, WavWriter, Y4mWriter};
+use gb18::cheats::Cheats;
+use gb18::debugger::Debugger;
+use gb18::gbs::Player;
+use gb18::joypad;
+use gb18::model::{self, Model};
+use gb18::movie::Movie;
+use gb18::ppu::{Layers, Overlays, Renderer};
+use gb18::sgb::{BORDER_HEIGHT, BORDER_WIDTH};
+
+fn usage() -> ! {
+    eprintln!("usage: gb18 [--debug] [--model <dmg|mgb|sgb|cgb|agb>] [--fifo] [--hide <background,window,objects>] \
+               [--trace <file>] [--record <movie> --input <file>] [--play <movie> [--screenshot <png>] [--capture <avi|y4m|dir>] \
+               [--overlay <objects,window,scroll>] [--vram <dir>]] \
+               [--wav <file>] [--vgm <file>] [--rate <hz>] [--seconds <n>] <rom>");
+    eprintln!("       gb18 --gbs <file> [--track <n>] [--wav <file>] [--vgm <file>] [--rate <hz>] [--seconds <n>]");
+    process::exit(1);
+}
+
 pub fn main() {
+    let mut debug = false;
+    let mut model = None;
+    let mut renderer = Renderer::Scanline;
+    let mut layers = Layers::default();
+    let mut overlays = None;
+    let mut trace = None;
+    let mut record = None;
+    let mut input = None;
+    let mut play = None;
+    let mut screenshot = None;
+    let mut capture = None;
+    let mut vram = None;
+    let mut wav = None;
+    let mut vgm = None;
+    let mut rate = None;
+    let mut seconds = None;
+    let mut gbs = None;
+    let mut track = None;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--model" => model = Some(args.next().and_then(|name| Model::parse(&name)).unwrap_or_else(|| usage())),
+            "--fifo" => renderer = Renderer::Fifo,
+            "--hide" => layers = args.next().and_then(|names| Layers::hiding(&names)).unwrap_or_else(|| usage()),
+            "--overlay" => overlays = Some(args.next().and_then(|names| Overlays::parse(&names)).unwrap_or_else(|| usage())),
+            "--trace" => trace = Some(args.next().unwrap_or_else(|| usage())),
+            "--record" => record = Some(args.next().unwrap_or_else(|| usage())),
+            "--input" => input = Some(args.next().unwrap_or_else(|| usage())),
+            "--play" => play = Some(args.next().unwrap_or_else(|| usage())),
+            "--screenshot" => screenshot = Some(args.next().unwrap_or_else(|| usage())),
+            "--capture" => capture = Some(args.next().unwrap_or_else(|| usage())),
+            "--vram" => vram = Some(args.next().unwrap_or_else(|| usage())),
+            "--wav" => wav = Some(args.next().unwrap_or_else(|| usage())),
+            "--vgm" => vgm = Some(args.next().unwrap_or_else(|| usage())),
+            "--rate" => rate = Some(args.next().and_then(|rate| rate.parse().ok()).filter(|&rate| rate > 0)
+                .unwrap_or_else(|| usage())),
+            "--seconds" => seconds = Some(args.next().and_then(|seconds| seconds.parse().ok()).unwrap_or_else(|| usage())),
+            "--gbs" => gbs = Some(args.next().unwrap_or_else(|| usage())),
+            "--track" => track = Some(args.next().and_then(|track| track.parse().ok()).filter(|&track| track > 0)
+                .unwrap_or_else(|| usage())),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let exporting = wav.is_some() || vgm.is_some();
+    if let Some(gbs) = gbs {
+        if path.is_some() || debug || play.is_some() || trace.is_some() || record.is_some() || input.is_some() {
+            usage();
+        }
+        let rate = rate.unwrap_or(SAMPLE_RATE as u32);
+        let seconds = if exporting { Some(seconds.unwrap_or(DEFAULT_SECONDS)) } else { seconds };
+        return play_gbs(&gbs, track, Export::create(wav, vgm, rate, seconds.unwrap_or(0)), seconds);
+    }
+    let path = path.unwrap_or_else(|| usage());
+    if track.is_some() {
+        usage();
+    }
+    if play.is_none() && (screenshot.is_some() || capture.is_some() || overlays.is_some() || vram.is_some()) {
+        usage();
+    }
+    if record.is_some() != input.is_some() || (record.is_some() && (debug || play.is_some() || exporting)) {
+        usage();
+    }
+    // With no window, a run needs the debugger, a movie, an export or a trace to show for itself,
+    // and a run that only traces stops after `--seconds`
+    let tracing = trace.is_some() && !debug && play.is_none() && record.is_none() && !exporting;
+    if !debug && play.is_none() && record.is_none() && !exporting && !tracing {
+        usage();
+    }
+    if (exporting && (debug || play.is_some())) || (!exporting && rate.is_some()) || (!exporting && !tracing && seconds.is_some()) {
+        usage();
+    }
+    let seconds = seconds.unwrap_or(DEFAULT_SECONDS);
+    let rom = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("gb18: {}: {}", path, err);
+        process::exit(1);
+    });
+    let mut model = model.unwrap_or_else(|| Model::preferred(&rom));
+    if model == Model::Sgb && !gb18::sgb::supported(&rom) {
+        eprintln!("gb18: {}: no Super Game Boy support in the header, playing on a DMG", path);
+        model = Model::Dmg;
+    }
+    let mut cpu = Cpu::default();
+    if let Some(trace) = trace {
+        let file = fs::File::create(&trace).unwrap_or_else(|err| {
+            eprintln!("gb18: {}: {}", trace, err);
+            process::exit(1);
+        });
+        cpu.set_tracer(Some(Tracer::new(BufWriter::new(file))));
+    }
+    let playback = play.map(|play| Playback {
+        movie: File::open(&play).and_then(Movie::read).unwrap_or_else(|err| {
+            eprintln!("gb18: {}: {}", play, err);
+            process::exit(1);
+        }),
+        screenshot,
+        vram,
+        overlays: overlays.unwrap_or_default(),
+        capture: capture.map(|path| Capture::create(&path).unwrap_or_else(|err| {
+            eprintln!("gb18: {}: {}", path, err);
+            process::exit(1);
+        })),
+    });
+    let recording = record.map(|path| {
+        let input = input.unwrap_or_else(|| usage());
+        Recording {
+            path,
+            input: script(&input).unwrap_or_else(|err| {
+                eprintln!("gb18: {}: {}", input, err);
+                process::exit(1);
+            }),
+        }
+    });
+    let export = if exporting {
+        Some(Export::create(wav, vgm, rate.unwrap_or(SAMPLE_RATE as u32), seconds))
+    } else {
+        None
+    };
+
+    match rom.get(0x0147).cloned().unwrap_or(0x00) {
+        0x00 => run(cpu, cheats(Mbc0::with_model(rom, model), &path, renderer, layers), debug, playback, recording, export, seconds),
+        0x01 ..= 0x03 => run(cpu, cheats(Mbc1::with_model(rom, model), &path, renderer, layers), debug, playback, recording, export, seconds),
+        kind => {
+            eprintln!("gb18: {}: unsupported cartridge type ${:02X}", path, kind);
+            process::exit(1);
+        }
+    }
+}
+
+/// Picks the renderer and layers, and applies the cheat file next to the ROM, if there is one.
+fn cheats<M: Hardware>(mut mmu: M, path: &str, renderer: Renderer, layers: Layers) -> Cheats<M> {
+    mmu.board_mut().set_renderer(renderer);
+    mmu.board_mut().set_layers(layers);
+    let mut cheats = Cheats::new(mmu);
+    let file = Path::new(path).with_extension("cht");
+    if file.exists() {
+        if let Err(err) = cheats.load(&file) {
+            eprintln!("gb18: {}: {}", file.display(), err);
+            process::exit(1);
+        }
+    }
+    cheats
+}
+
+/// Audio is captured at this rate, and exported at it unless `--rate` says otherwise.
+const SAMPLE_RATE: u64 = 48_000;
+
+/// Where played frames are captured to, chosen by the file extension.
+enum Capture {
+    Frames(FrameSequence),
+    Y4m(Y4mWriter<BufWriter<File>>, WavWriter<BufWriter<File>>),
+    Avi(AviWriter<BufWriter<File>>),
+}
+
+impl Capture {
+    fn create(path: &str) -> io::Result<Capture> {
+        let path = Path::new(path);
+        let create = |path: &Path| File::create(path).map(BufWriter::new);
+        Ok(match path.extension().and_then(|extension| extension.to_str()) {
+            Some("avi") => Capture::Avi(AviWriter::new(create(path)?, SAMPLE_RATE as u32)?),
+            Some("y4m") => Capture::Y4m(Y4mWriter::new(create(path)?)?,
+                                        WavWriter::new(create(&path.with_extension("wav"))?, SAMPLE_RATE as u32)?),
+            _ => Capture::Frames(FrameSequence::new(path)?),
+        })
+    }
+
+    fn write(&mut self, frame: &[u32], samples: &[i16]) -> io::Result<()> {
+        match *self {
+            Capture::Frames(ref mut sequence) => sequence.write(frame).map(|_| ()),
+            Capture::Y4m(ref mut video, ref mut audio) => video.write(frame).and_then(|_| audio.write(samples)),
+            Capture::Avi(ref mut avi) => avi.write(frame, samples),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Capture::Frames(_) => Ok(()),
+            Capture::Y4m(video, audio) => video.finish().and_then(|_| audio.finish()).map(|_| ()),
+            Capture::Avi(avi) => avi.finish().map(|_| ()),
+        }
+    }
+}
+
+struct Playback {
+    movie: Movie,
+    screenshot: Option<String>,
+    vram: Option<String>,
+    /// Drawn over screenshots and captures, so they need a movie, as there's no window to show them in.
+    overlays: Overlays,
+    capture: Option<Capture>,
+}
+
+/// Plays back the movie without cheats, reporting whether it reproduced the recording.
+fn play<M: Hardware>(mut cpu: Cpu, mut mmu: M, playback: Playback) {
+    let Playback { movie, screenshot, vram, overlays, mut capture } = playback;
+    let mut error = None;
+    if capture.is_some() {
+        mmu.board_mut().apu_mut().set_sample_rate(SAMPLE_RATE as u32);
+    }
+    let result = movie.play_with(&mut cpu, &mut mmu, |mmu| {
+        if let Some(line) = mmu.board_mut().take_unsafe_lcd_off() {
+            warn_unsafe_lcd_off(line);
+        }
+        if let (Some(capture), None) = (capture.as_mut(), error.as_ref()) {
+            let samples = mmu.board_mut().apu_mut().take_samples();
+            error = capture.write(&mmu.board().compose(overlays), &samples).err();
+        }
+    });
+    let error = error.map_or_else(|| capture.map_or(Ok(()), Capture::finish), Err).err();
+    if let Some(err) = error {
+        eprintln!("gb18: capture: {}", err);
+        process::exit(1);
+    }
+    if let Some(path) = screenshot {
+        // In a Super Game Boy, the whole picture with its border
+        let saved = match mmu.board().sgb() {
+            Some(sgb) => File::create(&path).and_then(|file| {
+                capture::write_png(BufWriter::new(file), sgb.frame(), BORDER_WIDTH, BORDER_HEIGHT)
+            }),
+            None => capture::screenshot(&path, &mmu.board().compose(overlays)),
+        };
+        if let Err(err) = saved {
+            eprintln!("gb18: {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+    if let Some(dir) = vram {
+        if let Err(err) = mmu.board().viewer().export(&dir) {
+            eprintln!("gb18: {}: {}", dir, err);
+            process::exit(1);
+        }
+    }
+    match result {
+        Ok(()) => println!("gb18: played {} frames", movie.inputs().len()),
+        Err(err) => {
+            eprintln!("gb18: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+struct Recording {
+    path: String,
+    input: Vec<(u64, u8)>,
+}
+
+/// Reads an input script: lines of a frame count and the buttons held for those frames, like
+/// `60 a,start`, or just the count for frames with nothing held.
+fn script(path: &str) -> io::Result<Vec<(u64, u8)>> {
+    let invalid = |number: usize| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("line {}: expected <frames> [buttons]", number))
+    };
+    let mut input = Vec::new();
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let frames = match words.next() {
+            Some(frames) => frames.parse().map_err(|_| invalid(number + 1))?,
+            None => continue,
+        };
+        let buttons = match words.next() {
+            Some(names) => joypad::parse(names).ok_or_else(|| invalid(number + 1))?,
+            None => 0x00,
+        };
+        if words.next().is_some() {
+            return Err(invalid(number + 1));
+        }
+        input.push((frames, buttons));
+    }
+    Ok(input)
+}
+
+/// Records a movie from power-on without cheats, holding the buttons the script says.
+fn record<M: Hardware>(mut cpu: Cpu, mut mmu: M, recording: Recording) {
+    let Recording { path, input } = recording;
+    let mut movie = Movie::power_on(&mut cpu, &mut mmu);
+    for (frames, buttons) in input {
+        for _ in 0..frames {
+            movie.record(&mut cpu, &mut mmu, buttons);
+        }
+    }
+    movie.finish(&mmu);
+    finish_trace(&mut cpu);
+    if let Err(err) = File::create(&path).and_then(|file| movie.write(BufWriter::new(file))) {
+        eprintln!("gb18: {}: {}", path, err);
+        process::exit(1);
+    }
+    println!("gb18: recorded {} frames", movie.inputs().len());
+}
+
+/// Music exports and runs that only trace last this long unless `--seconds` says otherwise.
+const DEFAULT_SECONDS: u64 = 180;
+
+/// Where a headless run saves what the APU plays.
+struct Export {
+    wav: Option<WavWriter<BufWriter<File>>>,
+    rate: u32,
+    vgm: Option<VgmWriter<BufWriter<File>>>,
+    seconds: u64,
+}
+
+impl Export {
+    /// Creates the files asked for.
+    fn create(wav: Option<String>, vgm: Option<String>, rate: u32, seconds: u64) -> Export {
+        let failed = |path: &str, err: io::Error| -> ! {
+            eprintln!("gb18: {}: {}", path, err);
+            process::exit(1);
+        };
+        let create = |path: &str| File::create(path).map(BufWriter::new).unwrap_or_else(|err| failed(path, err));
+        Export {
+            wav: wav.map(|path| WavWriter::new(create(&path), rate).unwrap_or_else(|err| failed(&path, err))),
+            rate,
+            vgm: vgm.map(|path| VgmWriter::new(create(&path)).unwrap_or_else(|err| failed(&path, err))),
+            seconds,
+        }
+    }
+
+    /// Has the APU sample and log what the files need.
+    fn start(&self, apu: &mut Apu) {
+        apu.set_sample_rate(if self.wav.is_some() { self.rate } else { 0 });
+        apu.set_logging(self.vgm.is_some());
+    }
+
+    /// Saves what the APU played since the last call.
+    fn save(&mut self, apu: &mut Apu) -> io::Result<()> {
+        if let Some(ref mut wav) = self.wav {
+            wav.write(&apu.take_samples())?;
+        }
+        if let Some(ref mut vgm) = self.vgm {
+            for write in apu.take_writes() {
+                vgm.write(write.cycle, write.address, write.value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self, apu: &Apu) -> io::Result<()> {
+        if let Some(wav) = self.wav {
+            wav.finish()?;
+        }
+        if let Some(vgm) = self.vgm {
+            vgm.finish(apu.cycles())?;
+        }
+        Ok(())
+    }
+
+    /// Runs from power-on without input for the chosen time, saving the mixed audio and the
+    /// sound register writes a frame at a time.
+    fn run<M: Hardware>(mut self, cpu: &mut Cpu, mmu: &mut M) -> io::Result<()> {
+        self.start(mmu.board_mut().apu_mut());
+        model::boot(cpu, mmu);
+        let mut clocks = 0;
+        while clocks < self.seconds * 4_194_304 {
+            let end = clocks + 70_224;
+            while clocks < end {
+                clocks += cpu.cycle(mmu) as u64;
+            }
+            self.save(mmu.board_mut().apu_mut())?;
+        }
+        self.finish(mmu.board().apu())
+    }
+}
+
+/// Plays a track of a GBS file, from 1, or the file's first: to the files asked for, or
+/// aloud for `seconds` or until interrupted.
+fn play_gbs(path: &str, track: Option<u8>, mut export: Export, seconds: Option<u64>) {
+    let mut player = fs::read(path).and_then(|file| Player::new(&file)).unwrap_or_else(|err| {
+        eprintln!("gb18: {}: {}", path, err);
+        process::exit(1);
+    });
+    let track = track.unwrap_or(player.header().first_song.max(1));
+    if track > player.header().songs {
+        eprintln!("gb18: {}: no track {}, only {}", path, track, player.header().songs);
+        process::exit(1);
+    }
+    {
+        let header = player.header();
+        println!("gb18: \"{}\" by {}, track {} of {}", header.title, header.author, track, header.songs);
+    }
+    let exporting = export.wav.is_some() || export.vgm.is_some();
+    let result = if exporting {
+        export.start(player.apu_mut());
+        player.start(track - 1).and_then(|_| {
+            let mut clocks = 0;
+            while clocks < export.seconds * 4_194_304 {
+                clocks += player.run(70_224) as u64;
+                export.save(player.apu_mut())?;
+            }
+            export.finish(player.apu())
+        })
+    } else {
+        play_aloud(&mut player, track - 1, export.rate, seconds).map_err(io::Error::other)
+    };
+    if let Err(err) = result {
+        eprintln!("gb18: {}: {}", path, err);
+        process::exit(1);
+    }
+}
+
+/// Queues a frame of samples at a time, staying about a tenth of a second ahead of the device.
+fn play_aloud(player: &mut Player, song: u8, rate: u32, seconds: Option<u64>) -> Result<(), String> {
+    let sdl = sdl2::init()?;
+    let audio = sdl.audio()?;
+    let desired = AudioSpecDesired { freq: Some(rate as i32), channels: Some(2), samples: None };
+    let queue = audio.open_queue::<i16, _>(None, &desired)?;
+    let rate = queue.spec().freq as u32;
+    player.apu_mut().set_sample_rate(rate);
+    player.start(song).map_err(|err| err.to_string())?;
+    // Two channels of two bytes
+    let queued = |queue: &AudioQueue<i16>| queue.size() as usize / 4;
+    let playing = |clocks| seconds.is_none_or(|seconds| clocks < seconds * 4_194_304);
+    let target = rate as usize / 10;
+    let mut clocks = 0;
+    // Fill the queue to the target before the device starts
+    while playing(clocks) && queued(&queue) < target {
+        clocks += player.run(70_224) as u64;
+        queue.queue(&player.apu_mut().take_samples());
+    }
+    queue.resume();
+    // From then on frames are paced by the clock, as they would be by the display, and the
+    // sample rate follows the queue so it neither runs dry nor grows
+    let control = RateControl::new(rate, target);
+    let frame = Duration::from_nanos(70_224 * 1_000_000_000 / 4_194_304);
+    let mut deadline = Instant::now();
+    while playing(clocks) {
+        clocks += player.run(70_224) as u64;
+        queue.queue(&player.apu_mut().take_samples());
+        player.apu_mut().adjust_sample_rate(control.update(queued(&queue)));
+        deadline += frame;
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        } else {
+            deadline = now;
+        }
+    }
+    // Let the queue drain
+    while queue.size() > 0 {
+        thread::sleep(Duration::from_millis(5));
+    }
+    Ok(())
+}
+
+fn run<M: Hardware>(mut cpu: Cpu, mut mmu: Cheats<M>, debug: bool, playback: Option<Playback>, recording: Option<Recording>,
+                    export: Option<Export>, seconds: u64) {
+    if let Some(playback) = playback {
+        return play(cpu, mmu.into_inner(), playback);
+    }
+    if let Some(recording) = recording {
+        return record(cpu, mmu.into_inner(), recording);
+    }
+    if let Some(export) = export {
+        if let Err(err) = export.run(&mut cpu, &mut mmu) {
+            eprintln!("gb18: export: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    model::boot(&mut cpu, &mut mmu);
+    if debug {
+        let stdin = io::stdin();
+        let mut debugger = Debugger::new(cpu, mmu);
+        if let Err(err) = debugger.repl(stdin.lock(), io::stdout()) {
+            eprintln!("gb18: {}", err);
+            process::exit(1);
+        }
+    } else {
+        let mut clocks = 0;
+        while clocks < seconds * 4_194_304 {
+            clocks += cpu.cycle(&mut mmu) as u64;
+            if let Some(line) = mmu.board_mut().take_unsafe_lcd_off() {
+                warn_unsafe_lcd_off(line);
+            }
+        }
+        finish_trace(&mut cpu);
+    }
+}
+
+fn warn_unsafe_lcd_off(line: u8) {
+    eprintln!("gb18: warning: LCD turned off outside VBlank, on line {}", line);
+}
 
-}
\ No newline at end of file
+/// Flushes the trace, if there is one, and reports anything that went wrong writing it.
+fn finish_trace(cpu: &mut Cpu) {
+    if let Some(tracer) = cpu.tracer_mut() {
+        if let Some(err) = tracer.take_error().or_else(|| tracer.flush().err()) {
+            eprintln!("gb18: trace: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Bytes in one uncompressed 24-bit frame.
+const FRAME_BYTES: u32 = (SCREEN_WIDTH * SCREEN_HEIGHT * 3) as u32;
+
+/// Where the header fields `finish` fills in live.
+const RIFF_SIZE: u64 = 4;
+const TOTAL_FRAMES: u64 = 48;
+const VIDEO_LENGTH: u64 = 140;
+const AUDIO_LENGTH: u64 = 264;
+const MOVI_SIZE: u64 = 316;
+
+/// Writes an AVI with an uncompressed RGB video stream at the LCD's frame rate and a 16-bit
+/// stereo PCM audio stream. Each frame is followed by the audio played during it.
+pub struct AviWriter<W: Write + Seek> {
+    out: W,
+    frames: u32,
+    samples: u32,
+    /// The chunk ID, offset from the `movi` list type and size of every chunk, for the index.
+    index: Vec<(&'static [u8; 4], u32, u32)>,
+    offset: u32,
+}
+
+fn put<W: Write>(out: &mut W, values: &[u32]) -> io::Result<()> {
+    for value in values {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn put16<W: Write>(out: &mut W, values: &[u16]) -> io::Result<()> {
+    for value in values {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<AviWriter<W>> {
+        let (width, height) = (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+        out.write_all(b"RIFF\0\0\0\0AVI LIST")?;
+        put(&mut out, &[4 + 64 + 124 + 100])?;
+        out.write_all(b"hdrlavih")?;
+        // Microseconds per frame, max bytes per second, padding, has index, frames, initial
+        // frames, streams, buffer size, size and reserved words
+        put(&mut out, &[56, 16_743, FRAME_BYTES * 60 + sample_rate * 4, 0, 0x10, 0, 0, 2, FRAME_BYTES,
+                        width, height, 0, 0, 0, 0])?;
+
+        out.write_all(b"LIST")?;
+        put(&mut out, &[4 + 64 + 48])?;
+        out.write_all(b"strlstrh")?;
+        put(&mut out, &[56])?;
+        out.write_all(b"vidsDIB ")?;
+        put(&mut out, &[0, 0, 0, 70_224, 4_194_304, 0, 0, FRAME_BYTES, !0, 0])?;
+        put16(&mut out, &[0, 0, width as u16, height as u16])?;
+        out.write_all(b"strf")?;
+        // BITMAPINFOHEADER: bottom-up 24-bit RGB
+        put(&mut out, &[40, 40, width, height])?;
+        put16(&mut out, &[1, 24])?;
+        put(&mut out, &[0, FRAME_BYTES, 0, 0, 0, 0])?;
+
+        out.write_all(b"LIST")?;
+        put(&mut out, &[4 + 64 + 24])?;
+        out.write_all(b"strlstrh")?;
+        put(&mut out, &[56])?;
+        out.write_all(b"auds\0\0\0\0")?;
+        put(&mut out, &[0, 0, 0, 1, sample_rate, 0, 0, sample_rate * 4, !0, 4])?;
+        put16(&mut out, &[0, 0, 0, 0])?;
+        out.write_all(b"strf")?;
+        // WAVEFORMATEX: PCM, 2 channels, 16 bits
+        put(&mut out, &[16])?;
+        put16(&mut out, &[1, 2])?;
+        put(&mut out, &[sample_rate, sample_rate * 4])?;
+        put16(&mut out, &[4, 16])?;
+
+        out.write_all(b"LIST\0\0\0\0movi")?;
+        Ok(AviWriter {
+            out,
+            frames: 0,
+            samples: 0,
+            index: Vec::new(),
+            offset: 4,
+        })
+    }
+
+    fn chunk(&mut self, id: &'static [u8; 4], data: &[u8]) -> io::Result<()> {
+        self.out.write_all(id)?;
+        put(&mut self.out, &[data.len() as u32])?;
+        self.out.write_all(data)?;
+        if !data.len().is_multiple_of(2) {
+            self.out.write_all(&[0])?;
+        }
+        self.index.push((id, self.offset, data.len() as u32));
+        self.offset += 8 + ((data.len() as u32 + 1) & !1);
+        Ok(())
+    }
+
+    /// Appends a frame from `Board::frame` and the interleaved stereo samples played with it.
+    pub fn write(&mut self, frame: &[u32], samples: &[i16]) -> io::Result<()> {
+        let mut data = Vec::with_capacity(FRAME_BYTES as usize);
+        for row in frame.chunks(SCREEN_WIDTH).rev() {
+            for &pixel in row {
+                data.extend_from_slice(&[pixel as u8, (pixel >> 8) as u8, (pixel >> 16) as u8]);
+            }
+        }
+        self.chunk(b"00db", &data)?;
+        self.frames += 1;
+        if !samples.is_empty() {
+            let mut data = Vec::with_capacity(samples.len() * 2);
+            for sample in samples {
+                data.extend_from_slice(&sample.to_le_bytes());
+            }
+            self.chunk(b"01wb", &data)?;
+            self.samples += samples.len() as u32 / 2;
+        }
+        Ok(())
+    }
+
+    /// Writes the index and fills in the header, returning the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        let movi = self.offset;
+        self.out.write_all(b"idx1")?;
+        put(&mut self.out, &[self.index.len() as u32 * 16])?;
+        for &(id, offset, size) in &self.index {
+            self.out.write_all(id)?;
+            put(&mut self.out, &[0x10, offset, size])?;
+        }
+        let end = self.out.stream_position()?;
+        for &(position, value) in &[
+            (RIFF_SIZE, end as u32 - 8),
+            (TOTAL_FRAMES, self.frames),
+            (VIDEO_LENGTH, self.frames),
+            (AUDIO_LENGTH, self.samples),
+            (MOVI_SIZE, movi),
+        ] {
+            self.out.seek(SeekFrom::Start(position))?;
+            put(&mut self.out, &[value])?;
+        }
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
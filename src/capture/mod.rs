@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod tests;
+mod avi;
+mod png;
+mod vgm;
+mod wav;
+mod y4m;
+
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub use self::avi::AviWriter;
+pub use self::png::write_png;
+pub use self::vgm::VgmWriter;
+pub use self::wav::WavWriter;
+pub use self::y4m::Y4mWriter;
+
+/// Saves a frame from `Board::frame` as a PNG file.
+pub fn screenshot(path: impl AsRef<Path>, frame: &[u32]) -> io::Result<()> {
+    let file = io::BufWriter::new(fs::File::create(path)?);
+    write_png(file, frame, SCREEN_WIDTH, SCREEN_HEIGHT)
+}
+
+/// Saves every frame given to it as a numbered PNG file in a directory.
+pub struct FrameSequence {
+    dir: PathBuf,
+    next: usize,
+}
+
+impl FrameSequence {
+    /// Creates the directory if it doesn't exist.
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<FrameSequence> {
+        fs::create_dir_all(&dir)?;
+        Ok(FrameSequence { dir: dir.as_ref().to_path_buf(), next: 0 })
+    }
+
+    /// Saves the next frame, returning where it went.
+    pub fn write(&mut self, frame: &[u32]) -> io::Result<PathBuf> {
+        let path = self.dir.join(format!("{:06}.png", self.next));
+        screenshot(&path, frame)?;
+        self.next += 1;
+        Ok(path)
+    }
+}
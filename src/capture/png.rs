@@ -0,0 +1,65 @@
+use std::io::{self, Write};
+
+/// CRC-32 as used by PNG chunks.
+pub(super) fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| if (crc & 1) != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 })
+    })
+}
+
+pub(super) fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+/// Wraps `data` in a zlib stream of stored deflate blocks. Frames are small enough that
+/// compressing them isn't worth the code.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(if blocks.peek().is_none() { 0x01 } else { 0x00 });
+        let len = block.len() as u16;
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut checked = kind.to_vec();
+    checked.extend_from_slice(data);
+    out.write_all(&checked)?;
+    out.write_all(&crc32(&checked).to_be_bytes())
+}
+
+/// Writes 0xAARRGGBB pixels, row by row, as an RGBA PNG.
+pub fn write_png<W: Write>(mut out: W, pixels: &[u32], width: usize, height: usize) -> io::Result<()> {
+    assert_eq!(width * height, pixels.len());
+    out.write_all(b"\x89PNG\r\n\x1A\n")?;
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header)?;
+    let mut data = Vec::with_capacity(height * (width * 4 + 1));
+    for row in pixels.chunks(width.max(1)) {
+        data.push(0x00);
+        for &pixel in row {
+            data.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, (pixel >> 24) as u8]);
+        }
+    }
+    chunk(&mut out, b"IDAT", &zlib(&data))?;
+    chunk(&mut out, b"IEND", &[])
+}
+
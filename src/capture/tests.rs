@@ -0,0 +1,132 @@
+use std::io::Cursor;
+use super::*;
+use super::png::{adler32, crc32};
+
+fn frame() -> Vec<u32> {
+    (0..SCREEN_WIDTH * SCREEN_HEIGHT).map(|i| 0xFF000000 | (i as u32 * 0x010203)).collect()
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+#[test]
+fn checksums() {
+    assert_eq!(0xCBF43926, crc32(b"123456789"));
+    assert_eq!(0x091E01DE, adler32(b"123456789"));
+}
+
+#[test]
+fn png() {
+    let mut out = Vec::new();
+    write_png(&mut out, &[0xFF102030, 0x80405060], 2, 1).unwrap();
+    assert_eq!(b"\x89PNG\r\n\x1A\n", &out[..8]);
+    assert_eq!(b"IHDR", &out[12..16]);
+    // One filter byte then the pixels, in a single final stored block after the zlib header
+    let idat = 8 + 25;
+    assert_eq!(b"IDAT", &out[idat + 4..idat + 8]);
+    assert_eq!(&[0x78, 0x01, 0x01, 9, 0, !9, 0xFF], &out[idat + 8..idat + 15]);
+    assert_eq!(&[0x00, 0x10, 0x20, 0x30, 0xFF, 0x40, 0x50, 0x60, 0x80], &out[idat + 15..idat + 24]);
+    assert_eq!(b"IEND", &out[out.len() - 8..out.len() - 4]);
+
+    let mut out = Vec::new();
+    write_png(&mut out, &frame(), SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
+    // 144 rows of 641 bytes need two stored blocks
+    assert_eq!(8 + 25 + 12 + 2 + 2 * 5 + 144 * 641 + 4 + 12, out.len());
+}
+
+#[test]
+fn wav() {
+    let mut wav = WavWriter::new(Cursor::new(Vec::new()), 48000).unwrap();
+    wav.write(&[1, -1, 2, -2]).unwrap();
+    wav.write(&[3, -3]).unwrap();
+    let out = wav.finish().unwrap().into_inner();
+    assert_eq!(44 + 12, out.len());
+    assert_eq!(36 + 12, u32_at(&out, 4));
+    assert_eq!(48000 * 4, u32_at(&out, 28));
+    assert_eq!(12, u32_at(&out, 40));
+    assert_eq!(&[0xFD, 0xFF], &out[54..]);
+}
+
+#[test]
+fn vgm() {
+    let mut vgm = VgmWriter::new(Cursor::new(Vec::new())).unwrap();
+    vgm.write(0, 0xFF26, 0x80).unwrap();
+    // 3 samples, then a frame at 60 Hz, then 1000 samples
+    vgm.write(72, 0xFF30, 0x12).unwrap();
+    vgm.write(17_548, 0xFF12, 0xF0).unwrap();
+    let out = vgm.finish(41_326).unwrap().into_inner();
+    assert_eq!(b"Vgm ", &out[..4]);
+    assert_eq!(out.len() as u32 - 4, u32_at(&out, 0x04));
+    assert_eq!(0x161, u32_at(&out, 0x08));
+    assert_eq!(1738, u32_at(&out, 0x18));
+    assert_eq!(0x100 - 0x34, u32_at(&out, 0x34));
+    assert_eq!(4_194_304, u32_at(&out, 0x80));
+    assert_eq!(&[0xB3, 0x16, 0x80, 0x72, 0xB3, 0x20, 0x12, 0x62, 0xB3, 0x02, 0xF0, 0x61, 0xE8, 0x03, 0x66],
+               &out[0x100..]);
+}
+
+#[test]
+fn y4m() {
+    let mut y4m = Y4mWriter::new(Vec::new()).unwrap();
+    y4m.write(&vec![0xFFFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT]).unwrap();
+    y4m.write(&vec![0xFF000000; SCREEN_WIDTH * SCREEN_HEIGHT]).unwrap();
+    let out = y4m.finish().unwrap();
+    let header = b"YUV4MPEG2 W160 H144 F4194304:70224 Ip A1:1 C444\n";
+    assert_eq!(&header[..], &out[..header.len()]);
+    let planes = SCREEN_WIDTH * SCREEN_HEIGHT;
+    assert_eq!(header.len() + 2 * (6 + planes * 3), out.len());
+    let white = header.len() + 6;
+    assert_eq!(&[235, 128, 128], &[out[white], out[white + planes], out[white + planes * 2]]);
+    let black = white + planes * 3 + 6;
+    assert_eq!(&[16, 128, 128], &[out[black], out[black + planes], out[black + planes * 2]]);
+}
+
+#[test]
+fn avi() {
+    let mut avi = AviWriter::new(Cursor::new(Vec::new()), 48000).unwrap();
+    avi.write(&frame(), &[0x0102, 0x0304]).unwrap();
+    avi.write(&frame(), &[]).unwrap();
+    let out = avi.finish().unwrap().into_inner();
+    assert_eq!(b"RIFF", &out[0..4]);
+    assert_eq!(out.len() as u32 - 8, u32_at(&out, 4));
+    assert_eq!(b"hdrlavih", &out[20..28]);
+    assert_eq!(292, u32_at(&out, 16));
+    assert_eq!(2, u32_at(&out, 48));
+    assert_eq!(b"vids", &out[108..112]);
+    assert_eq!(2, u32_at(&out, 140));
+    assert_eq!(b"auds", &out[232..236]);
+    assert_eq!(1, u32_at(&out, 264));
+    assert_eq!(b"LIST", &out[312..316]);
+    assert_eq!(b"movi", &out[320..324]);
+
+    let frame_bytes = SCREEN_WIDTH * SCREEN_HEIGHT * 3;
+    let movi = u32_at(&out, 316) as usize;
+    assert_eq!(4 + 2 * (8 + frame_bytes) + 8 + 4, movi);
+    // The bottom row comes first, as BGR
+    let last = frame()[(SCREEN_HEIGHT - 1) * SCREEN_WIDTH];
+    assert_eq!(b"00db", &out[324..328]);
+    assert_eq!(&[last as u8, (last >> 8) as u8, (last >> 16) as u8], &out[332..335]);
+    let audio = 324 + 8 + frame_bytes;
+    assert_eq!(b"01wb", &out[audio..audio + 4]);
+    assert_eq!(&[0x02, 0x01, 0x04, 0x03], &out[audio + 8..audio + 12]);
+
+    let index = 320 + movi;
+    assert_eq!(b"idx1", &out[index..index + 4]);
+    assert_eq!(3 * 16, u32_at(&out, index + 4));
+    assert_eq!(b"01wb", &out[index + 24..index + 28]);
+    assert_eq!((audio - 320) as u32, u32_at(&out, index + 32));
+}
+
+#[test]
+fn sequence() {
+    let dir = ::std::env::temp_dir().join(format!("gb18-capture-{}", ::std::process::id()));
+    let mut sequence = FrameSequence::new(&dir).unwrap();
+    assert_eq!(dir.join("000000.png"), sequence.write(&frame()).unwrap());
+    assert_eq!(dir.join("000001.png"), sequence.write(&frame()).unwrap());
+    let png = fs::read(dir.join("000001.png")).unwrap();
+    assert_eq!(b"\x89PNG\r\n\x1A\n", &png[..8]);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,76 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// VGM times everything in samples at this rate.
+const VGM_RATE: u64 = 44_100;
+
+/// M-cycles per second.
+const CYCLES_PER_SECOND: u64 = 1_048_576;
+
+/// The header of version 1.61, the first with the Game Boy, is 0x100 bytes.
+const HEADER_SIZE: u32 = 0x100;
+
+/// Writes sound register writes to a VGM file, to play in VGM players. The sizes and length in
+/// the header are filled in by `finish`.
+pub struct VgmWriter<W: Write + Seek> {
+    out: W,
+    bytes: u32,
+    samples: u64,
+}
+
+impl<W: Write + Seek> VgmWriter<W> {
+    pub fn new(mut out: W) -> io::Result<VgmWriter<W>> {
+        let mut header = vec![0x00; HEADER_SIZE as usize];
+        header[0x00..0x04].copy_from_slice(b"Vgm ");
+        header[0x08..0x0C].copy_from_slice(&0x0000_0161u32.to_le_bytes());
+        // The data starts right after the header, relative to this field
+        header[0x34..0x38].copy_from_slice(&(HEADER_SIZE - 0x34).to_le_bytes());
+        // The Game Boy DMG clock
+        header[0x80..0x84].copy_from_slice(&4_194_304u32.to_le_bytes());
+        out.write_all(&header)?;
+        Ok(VgmWriter { out, bytes: 0, samples: 0 })
+    }
+
+    #[inline]
+    fn command(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.bytes += bytes.len() as u32;
+        Ok(())
+    }
+
+    /// Waits until `cycle` M-cycles from the start.
+    fn wait(&mut self, cycle: u64) -> io::Result<()> {
+        let target = cycle * VGM_RATE / CYCLES_PER_SECOND;
+        while self.samples < target {
+            let samples = (target - self.samples).min(0xFFFF);
+            match samples {
+                1 ..= 16 => self.command(&[0x70 | (samples - 1) as u8])?,
+                735 => self.command(&[0x62])?,
+                882 => self.command(&[0x63])?,
+                _ => self.command(&[0x61, samples as u8, (samples >> 8) as u8])?,
+            }
+            self.samples += samples;
+        }
+        Ok(())
+    }
+
+    /// Appends a write to 0xFF10-0xFF3F at `cycle` M-cycles from the start. Writes must come in
+    /// order.
+    pub fn write(&mut self, cycle: u64, address: u16, value: u8) -> io::Result<()> {
+        self.wait(cycle)?;
+        self.command(&[0xB3, (address - 0xFF10) as u8, value])
+    }
+
+    /// Ends the music at `cycle` M-cycles from the start and fills in the header, returning the
+    /// output.
+    pub fn finish(mut self, cycle: u64) -> io::Result<W> {
+        self.wait(cycle)?;
+        self.command(&[0x66])?;
+        self.out.seek(SeekFrom::Start(0x04))?;
+        self.out.write_all(&(HEADER_SIZE + self.bytes - 4).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(0x18))?;
+        self.out.write_all(&(self.samples as u32).to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Writes 16-bit stereo PCM to a WAV file. The sizes in the header are filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    bytes: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        out.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM, 2 channels
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * 4).to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data\0\0\0\0")?;
+        Ok(WavWriter { out, bytes: 0 })
+    }
+
+    /// Appends interleaved left and right samples.
+    pub fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.out.write_all(&data)?;
+        self.bytes += data.len() as u32;
+        Ok(())
+    }
+
+    /// Fills in the header, returning the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + self.bytes).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.bytes.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,33 @@
+use std::io::{self, Write};
+use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Writes frames as a YUV4MPEG2 stream at the LCD's exact frame rate (4194304/70224 Hz),
+/// 4:4:4 so no color resolution is lost.
+pub struct Y4mWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Y4mWriter<W>> {
+        writeln!(out, "YUV4MPEG2 W{} H{} F4194304:70224 Ip A1:1 C444", SCREEN_WIDTH, SCREEN_HEIGHT)?;
+        Ok(Y4mWriter { out })
+    }
+
+    /// Appends a frame from `Board::frame`, converted to BT.601 YCbCr.
+    pub fn write(&mut self, frame: &[u32]) -> io::Result<()> {
+        let mut planes = vec![0; frame.len() * 3];
+        for (i, &pixel) in frame.iter().enumerate() {
+            let (r, g, b) = (((pixel >> 16) & 0xFF) as i32, ((pixel >> 8) & 0xFF) as i32, (pixel & 0xFF) as i32);
+            planes[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            planes[frame.len() + i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            planes[frame.len() * 2 + i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,299 @@
+#[cfg(test)]
+mod tests;
+
+use std::{fmt, fs, io};
+use std::path::Path;
+use mmu::{Board, Corruption, Hardware, Mmu, Port};
+use state::{Reader, State, Writer};
+
+/// A decoded cheat code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Code {
+    /// Replaces the ROM byte at `address` with `value`, but only while the byte there matches
+    /// `compare` (so the patch follows bank switches).
+    GameGenie { address: u16, value: u8, compare: Option<u8> },
+    /// Writes `value` to `address` every VBlank. Types 0x90-0x97 select the WRAM bank for
+    /// 0xD000-0xDFFF first.
+    GameShark { kind: u8, address: u16, value: u8 },
+}
+
+fn hex(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text, 16).map_err(|_| format!("invalid hex digits: {}", text))
+}
+
+impl Code {
+    /// Decodes `ABC-DEF` or `ABC-DEF-GHI` Game Genie codes and 8-digit `TTVVLLHH` GameShark codes.
+    pub fn parse(text: &str) -> Result<Code, String> {
+        let text = text.trim();
+        if !text.is_ascii() {
+            return Err(format!("not a Game Genie or GameShark code: {}", text));
+        }
+        if text.len() == 8 && !text.contains('-') {
+            let kind = hex(&text[0..2])? as u8;
+            let value = hex(&text[2..4])? as u8;
+            let address = hex(&text[4..6])? | (hex(&text[6..8])? << 8);
+            return Ok(Code::GameShark { kind, address, value });
+        }
+        let bytes = text.as_bytes();
+        let dashed = match text.len() {
+            7 => bytes[3] == b'-',
+            11 => bytes[3] == b'-' && bytes[7] == b'-',
+            _ => false,
+        };
+        let digits: String = text.split('-').collect();
+        if !dashed || (digits.len() != 6 && digits.len() != 9) {
+            return Err(format!("not a Game Genie or GameShark code: {}", text));
+        }
+        let value = hex(&digits[0..2])? as u8;
+        let address = (hex(&digits[2..5])? | (hex(&digits[5..6])? << 12)) ^ 0xF000;
+        let compare = if digits.len() == 9 {
+            let encoded = (hex(&digits[6..7])? << 4 | hex(&digits[8..9])?) as u8;
+            Some(encoded.rotate_right(2) ^ 0xBA)
+        } else {
+            None
+        };
+        if address >= 0x8000 {
+            return Err(format!("Game Genie code outside ROM: {}", text));
+        }
+        Ok(Code::GameGenie { address, value, compare })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: Code,
+    /// The code as written, kept for display and saving.
+    pub text: String,
+    pub name: String,
+    pub enabled: bool,
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.enabled { "" } else { "!" }, self.text)?;
+        if !self.name.is_empty() {
+            write!(f, " {}", self.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps an `Mmu` to apply cheats: Game Genie patches in the ROM read path and GameShark writes
+/// at the start of every VBlank. Works with any memory controller.
+pub struct Cheats<M: Mmu> {
+    mmu: M,
+    cheats: Vec<Cheat>,
+    ly: u8,
+}
+
+impl<M: Mmu> Cheats<M> {
+    pub fn new(mmu: M) -> Cheats<M> {
+        Cheats {
+            mmu,
+            cheats: Vec::new(),
+            ly: 0,
+        }
+    }
+
+    #[inline]
+    pub fn mmu(&self) -> &M {
+        &self.mmu
+    }
+
+    #[inline]
+    pub fn mmu_mut(&mut self) -> &mut M {
+        &mut self.mmu
+    }
+
+    pub fn into_inner(self) -> M {
+        self.mmu
+    }
+
+    #[inline]
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// Adds an enabled cheat, returning its index.
+    pub fn add(&mut self, text: &str, name: &str) -> Result<usize, String> {
+        let code = Code::parse(text)?;
+        self.cheats.push(Cheat {
+            code,
+            text: text.trim().to_uppercase(),
+            name: name.trim().to_string(),
+            enabled: true,
+        });
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.cheats.len() {
+            Some(self.cheats.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Parses a cheat file: one code per line followed by an optional name. A leading `!` adds
+    /// the cheat disabled, and lines starting with `#` are comments.
+    pub fn parse(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (enabled, line) = match line.strip_prefix('!') {
+                Some(line) => (false, line),
+                None => (true, line),
+            };
+            let (code, name) = match line.find(char::is_whitespace) {
+                Some(index) => (&line[..index], &line[index..]),
+                None => (line, ""),
+            };
+            let index = self.add(code, name).map_err(|err| format!("line {}: {}", number + 1, err))?;
+            self.cheats[index].enabled = enabled;
+        }
+        Ok(())
+    }
+
+    /// Loads the cheat file at `path`, the `.cht` file next to the ROM by convention.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        self.parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// The byte a read of `address` sees once Game Genie codes have patched `value` from ROM.
+    fn patch(&self, address: u16, value: u8) -> u8 {
+        if address >= 0x8000 {
+            return value;
+        }
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            if let Code::GameGenie { address: patched, value: replacement, compare } = cheat.code {
+                if patched == address && compare.is_none_or(|compare| compare == value) {
+                    return replacement;
+                }
+            }
+        }
+        value
+    }
+
+    fn apply_gameshark(&mut self) {
+        for i in 0..self.cheats.len() {
+            if !self.cheats[i].enabled {
+                continue;
+            }
+            if let Code::GameShark { kind, address, value } = self.cheats[i].code {
+                if (kind & 0xF8) == 0x90 && (0xD000..=0xDFFF).contains(&address) {
+                    let svbk = self.mmu.io_read(Port::SVBK);
+                    self.mmu.io_write(Port::SVBK, kind & 0x07);
+                    self.mmu.write(address, value);
+                    self.mmu.io_write(Port::SVBK, svbk);
+                } else {
+                    self.mmu.write(address, value);
+                }
+            }
+        }
+    }
+}
+
+impl<M: Mmu> Mmu for Cheats<M> {
+    #[inline]
+    fn read(&self, address: u16) -> u8 {
+        self.patch(address, self.mmu.read(address))
+    }
+
+    #[inline]
+    fn peek(&self, address: u16) -> u8 {
+        self.patch(address, self.mmu.peek(address))
+    }
+
+    #[inline]
+    fn write(&mut self, address: u16, value: u8) {
+        self.mmu.write(address, value)
+    }
+
+    fn tick(&mut self) {
+        self.mmu.tick();
+        let ly = self.mmu.io_read(Port::LY);
+        if ly >= 144 && self.ly < 144 {
+            self.apply_gameshark();
+        }
+        self.ly = ly;
+    }
+
+    #[inline]
+    fn take_stalled(&mut self) -> usize {
+        self.mmu.take_stalled()
+    }
+
+    #[inline]
+    fn take_unsafe_lcd_off(&mut self) -> Option<u8> {
+        self.mmu.take_unsafe_lcd_off()
+    }
+
+    #[inline]
+    fn corrupt_oam(&mut self, address: u16, corruption: Corruption) {
+        self.mmu.corrupt_oam(address, corruption)
+    }
+
+    #[inline]
+    fn io_read(&self, port: Port) -> u8 {
+        self.mmu.io_read(port)
+    }
+
+    #[inline]
+    fn io_write(&mut self, port: Port, value: u8) {
+        self.mmu.io_write(port, value)
+    }
+
+    #[inline]
+    fn bank(&self, address: u16) -> usize {
+        self.mmu.bank(address)
+    }
+}
+
+/// Cheats aren't part of the saved state.
+impl<M: Hardware> State for Cheats<M> {
+    #[inline]
+    fn save(&self, writer: &mut Writer) {
+        self.mmu.save(writer)
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.mmu.load(reader)?;
+        self.ly = self.mmu.io_read(Port::LY);
+        Ok(())
+    }
+}
+
+impl<M: Hardware> Hardware for Cheats<M> {
+    #[inline]
+    fn board(&self) -> &Board {
+        self.mmu.board()
+    }
+
+    #[inline]
+    fn board_mut(&mut self) -> &mut Board {
+        self.mmu.board_mut()
+    }
+
+    #[inline]
+    fn rom(&self) -> &[u8] {
+        self.mmu.rom()
+    }
+
+    fn power_on(&mut self) {
+        self.mmu.power_on();
+        self.ly = 0;
+    }
+}
@@ -0,0 +1,64 @@
+use super::*;
+
+#[test]
+fn game_genie() {
+    assert_eq!(Ok(Code::GameGenie { address: 0x4A17, value: 0x00, compare: Some(0xC8) }),
+               Code::parse("00A-17B-C49"));
+    assert_eq!(Ok(Code::GameGenie { address: 0x1234, value: 0x3E, compare: None }),
+               Code::parse("3E2-34E"));
+    assert!(Code::parse("3E2-34E-").is_err());
+    assert!(Code::parse("3E2-347").is_err());
+    assert!(Code::parse("XYZ-123").is_err());
+}
+
+#[test]
+fn gameshark() {
+    assert_eq!(Ok(Code::GameShark { kind: 0x01, address: 0xC0A5, value: 0x63 }),
+               Code::parse("0163A5C0"));
+    assert!(Code::parse("0163A5C").is_err());
+}
+
+#[test]
+fn patch() {
+    let mut mmu = vec![0x00; 0x10000];
+    mmu[0x4A17] = 0xC8;
+    mmu[0x1234] = 0x42;
+    let mut cheats = Cheats::new(mmu);
+    let index = cheats.add("00A-17B-C49", "").unwrap();
+    cheats.add("3E2-34E", "").unwrap();
+    assert_eq!(0x00, cheats.read(0x4A17));
+    assert_eq!(0x3E, cheats.read(0x1234));
+
+    cheats.mmu_mut()[0x4A17] = 0xC9;
+    assert_eq!(0xC9, cheats.read(0x4A17));
+    cheats.mmu_mut()[0x4A17] = 0xC8;
+    cheats.set_enabled(index, false);
+    assert_eq!(0xC8, cheats.read(0x4A17));
+}
+
+#[test]
+fn vblank() {
+    let mut cheats = Cheats::new(vec![0x00; 0x10000]);
+    cheats.add("0163A5C0", "").unwrap();
+    cheats.mmu_mut()[Port::LY as usize] = 143;
+    cheats.tick();
+    assert_eq!(0x00, cheats.read(0xC0A5));
+    cheats.mmu_mut()[Port::LY as usize] = 144;
+    cheats.tick();
+    assert_eq!(0x63, cheats.read(0xC0A5));
+    cheats.write(0xC0A5, 0x01);
+    cheats.tick();
+    assert_eq!(0x01, cheats.read(0xC0A5));
+}
+
+#[test]
+fn file() {
+    let mut cheats = Cheats::new(vec![0x00; 0x10000]);
+    cheats.parse("# Some game\n00A-17B-C49  Infinite lives\n!0163A5C0 Max money\n\n").unwrap();
+    assert_eq!(2, cheats.cheats().len());
+    assert_eq!("Infinite lives", cheats.cheats()[0].name);
+    assert_eq!(false, cheats.cheats()[1].enabled);
+    assert_eq!("!0163A5C0 Max money", cheats.cheats()[1].to_string());
+    assert_eq!(Err("line 2: not a Game Genie or GameShark code: bogus".to_string()),
+               Cheats::new(vec![0x00]).parse("# header\nbogus"));
+}
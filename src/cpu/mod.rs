@@ -1,8 +1,16 @@
 #[cfg(test)]
 mod tests;
+#[cfg(test)]
+mod single_step;
+mod registers;
+mod trace;
+
+use std::{io, mem};
+use mmu::{Corruption, Hardware, Mmu, Port};
+use state::{Reader, State, Writer};
 
-use std::{mem};
-use mmu::{Mmu, Port};
+pub use self::registers::{CpuBuilder, Registers};
+pub use self::trace::Tracer;
 
 #[derive(Default)]
 pub struct Cpu {
@@ -13,15 +21,21 @@ pub struct Cpu {
     de: u16,
     hl: u16,
 
-    interrupts_enabled: bool,
+    pub(crate) interrupts_enabled: bool,
+
+    pub(crate) stopped: bool,
+
+    pub(crate) halted: bool,
 
-    stopped: bool,
+    enable_interrupts: bool,
 
-    halted: bool,
+    halt_bug: bool,
+
+    tracer: Option<Tracer>,
 }
 
 #[derive(Copy, Clone)]
-enum WideRegister {
+pub(crate) enum WideRegister {
     PC,
     SP,
     AF,
@@ -31,7 +45,7 @@ enum WideRegister {
 }
 
 #[derive(Copy, Clone)]
-enum Register {
+pub(crate) enum Register {
     A,
     B,
     C,
@@ -42,7 +56,7 @@ enum Register {
 }
 
 #[derive(Copy, Clone)]
-enum Flag {
+pub(crate) enum Flag {
     Zero      = 0x80,
     Negative  = 0x40,
     HalfCarry = 0x20,
@@ -71,12 +85,12 @@ impl Cpu {
     }
 
     #[inline]
-    fn flag(&self, flag: Flag) -> bool {
+    pub(crate) fn flag(&self, flag: Flag) -> bool {
         (self.af & (flag as u16)) != 0
     }
 
     #[inline]
-    fn set_flag(&mut self, flag: Flag, value: bool) {
+    pub(crate) fn set_flag(&mut self, flag: Flag, value: bool) {
         if value {
             self.af |= flag as u16;
         } else {
@@ -84,20 +98,50 @@ impl Cpu {
         }
     }
 
+    /// Spends one M-cycle without touching the bus, letting the other components advance.
+    #[inline]
+    fn idle(&self, mmu: &mut impl Mmu) {
+        mmu.tick();
+    }
+
+    /// Reads `address` on its own M-cycle. Every bus access made by an instruction goes through
+    /// here or `write_address` so that timers, DMA and video see accesses when hardware would.
+    #[inline]
+    fn read_address(&self, address: u16, mmu: &mut impl Mmu) -> u8 {
+        mmu.tick();
+        mmu.corrupt_oam(address, Corruption::Read);
+        mmu.read(address)
+    }
+
+    /// Reads `address` while incrementing or decrementing it, which the OAM bug treats apart.
+    #[inline]
+    fn read_address_increment(&self, address: u16, mmu: &mut impl Mmu) -> u8 {
+        mmu.tick();
+        mmu.corrupt_oam(address, Corruption::ReadIncrement);
+        mmu.read(address)
+    }
+
+    #[inline]
+    fn write_address(&self, address: u16, value: u8, mmu: &mut impl Mmu) {
+        mmu.tick();
+        mmu.corrupt_oam(address, Corruption::Write);
+        mmu.write(address, value);
+    }
+
     #[inline]
-    fn read(&mut self, mmu: &impl Mmu) -> u8 {
-        let value = mmu.read(self.pc);
+    fn read(&mut self, mmu: &mut impl Mmu) -> u8 {
+        let value = self.read_address(self.pc, mmu);
         self.pc = self.pc.wrapping_add(1);
         value
     }
 
     #[inline]
-    fn read_wide(&mut self, mmu: &impl Mmu) -> u16 {
+    fn read_wide(&mut self, mmu: &mut impl Mmu) -> u16 {
         (self.read(mmu) as u16) | ((self.read(mmu) as u16) << 8)
     }
 
     #[inline]
-    fn register(&self, reg: Register) -> u8 {
+    pub(crate) fn register(&self, reg: Register) -> u8 {
         match reg {
             Register::A => Self::parts(self.af)[1],
             Register::B => Self::parts(self.bc)[1],
@@ -110,7 +154,7 @@ impl Cpu {
     }
 
     #[inline]
-    fn set_register(&mut self, reg: Register, value: u8) {
+    pub(crate) fn set_register(&mut self, reg: Register, value: u8) {
         match reg {
             Register::A => Self::parts_mut(&mut self.af)[1] = value,
             Register::B => Self::parts_mut(&mut self.bc)[1] = value,
@@ -130,7 +174,7 @@ impl Cpu {
     }
 
     #[inline]
-    fn wide_register(&self, reg: WideRegister) -> u16 {
+    pub(crate) fn wide_register(&self, reg: WideRegister) -> u16 {
         match reg {
             WideRegister::PC => self.pc,
             WideRegister::SP => self.sp,
@@ -142,7 +186,7 @@ impl Cpu {
     }
 
     #[inline]
-    fn set_wide_register(&mut self, reg: WideRegister, value: u16) {
+    pub(crate) fn set_wide_register(&mut self, reg: WideRegister, value: u16) {
         match reg {
             WideRegister::PC => self.pc = value,
             WideRegister::SP => self.sp = value,
@@ -159,14 +203,14 @@ impl Cpu {
     }
 
     #[inline]
-    fn read_wide_immediate(&mut self, reg: WideRegister, mmu: &impl Mmu) -> usize {
+    fn read_wide_immediate(&mut self, reg: WideRegister, mmu: &mut impl Mmu) -> usize {
         let value = self.read_wide(mmu);
         self.set_wide_register(reg, value);
         12
     }
 
     #[inline]
-    fn read_immediate(&mut self, reg: Register, mmu: &impl Mmu) -> usize {
+    fn read_immediate(&mut self, reg: Register, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         self.set_register(reg, value);
         8
@@ -174,27 +218,30 @@ impl Cpu {
 
     #[inline]
     fn write_register(&self, address: WideRegister, reg: Register, mmu: &mut impl Mmu) -> usize {
-        mmu.write(self.wide_register(address), self.register(reg));
+        self.write_address(self.wide_register(address), self.register(reg), mmu);
         8
     }
 
     #[inline]
-    fn inc_wide(&mut self, reg: WideRegister) -> usize {
-        let value = self.wide_register(reg).wrapping_add(1);
-        self.set_wide_register(reg, value);
+    fn inc_wide(&mut self, reg: WideRegister, mmu: &mut impl Mmu) -> usize {
+        let address = self.wide_register(reg);
+        self.set_wide_register(reg, address.wrapping_add(1));
+        self.idle(mmu);
+        mmu.corrupt_oam(address, Corruption::Write);
         8
     }
 
     #[inline]
-    fn dec_wide(&mut self, reg: WideRegister) -> usize {
-        let value = self.wide_register(reg).wrapping_sub(1);
-        self.set_wide_register(reg, value);
+    fn dec_wide(&mut self, reg: WideRegister, mmu: &mut impl Mmu) -> usize {
+        let address = self.wide_register(reg);
+        self.set_wide_register(reg, address.wrapping_sub(1));
+        self.idle(mmu);
+        mmu.corrupt_oam(address, Corruption::Write);
         8
     }
 
     #[inline]
     fn inc(&mut self, reg: Register) -> usize {
-        // TODO: inc/dec half-carry might be wrong
         let mut value = self.register(reg);
         self.set_flag(Flag::HalfCarry, (value & 0x0F) == 0x0F);
         value = value.wrapping_add(1);
@@ -236,9 +283,9 @@ impl Cpu {
     #[inline]
     fn rlc_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.rlc_value(value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
@@ -264,9 +311,9 @@ impl Cpu {
     #[inline]
     fn rl_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.rl_value(value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
@@ -292,9 +339,9 @@ impl Cpu {
     #[inline]
     fn rrc_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.rrc_value(value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
@@ -320,9 +367,9 @@ impl Cpu {
     #[inline]
     fn rr_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.rr_value(value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
@@ -384,9 +431,9 @@ impl Cpu {
     #[inline]
     fn sla_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.sla_value(value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
@@ -394,8 +441,8 @@ impl Cpu {
     fn sra_value(&mut self, value: u8) -> u8 {
         self.set_flag(Flag::Negative, false);
         self.set_flag(Flag::HalfCarry, false);
+        self.set_flag(Flag::Carry, (value & 0x01) != 0);
         let value = (value as i8) >> 1;
-        self.set_flag(Flag::Carry, false);
         self.set_flag(Flag::Zero, value == 0x00);
         value as u8
     }
@@ -411,9 +458,9 @@ impl Cpu {
     #[inline]
     fn sra_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.sra_value(value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
@@ -421,8 +468,8 @@ impl Cpu {
     fn srl_value(&mut self, value: u8) -> u8 {
         self.set_flag(Flag::Negative, false);
         self.set_flag(Flag::HalfCarry, false);
+        self.set_flag(Flag::Carry, (value & 0x01) != 0);
         let value = value >> 1;
-        self.set_flag(Flag::Carry, false);
         self.set_flag(Flag::Zero, value == 0x00);
         value
     }
@@ -438,63 +485,66 @@ impl Cpu {
     #[inline]
     fn srl_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.srl_value(value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
     #[inline]
     fn write_wide(&self, address: u16, value: u16, mmu: &mut impl Mmu,) {
-        mmu.write(address, (value & 0x00FF) as u8);
-        mmu.write(address.wrapping_add(1), ((value >> 8) & 0x00FF) as u8);
+        self.write_address(address, (value & 0x00FF) as u8, mmu);
+        self.write_address(address.wrapping_add(1), ((value >> 8) & 0x00FF) as u8, mmu);
     }
 
     #[inline]
     fn write_stack_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.read_wide(mmu);
         self.write_wide(address, self.sp, mmu);
-        12 // TODO: GB manual says this is 20
+        20
     }
 
     #[inline]
-    fn add_wide(&mut self, reg: WideRegister) -> usize {
-        // TODO: half carry??
+    fn add_wide(&mut self, reg: WideRegister, mmu: &mut impl Mmu) -> usize {
         let hl = self.hl as u32;
         let value = hl.wrapping_add(self.wide_register(reg) as u32);
         self.set_flag(Flag::Negative, false);
         self.set_flag(Flag::Carry, value > 0xFFFF);
         self.set_flag(Flag::HalfCarry, (value & 0x0FFF) < (hl & 0x0FFF));
         self.hl = (value & 0xFFFF) as u16;
+        self.idle(mmu);
         8
     }
 
     #[inline]
-    fn read_register(&mut self, address: WideRegister, reg: Register, mmu: &impl Mmu) -> usize {
-        let value = mmu.read(self.wide_register(address));
+    fn read_register(&mut self, address: WideRegister, reg: Register, mmu: &mut impl Mmu) -> usize {
+        let value = self.read_address(self.wide_register(address), mmu);
         self.set_register(reg, value);
         8
     }
 
     #[inline]
-    fn stop(&mut self, mmu: &impl Mmu) -> usize {
+    fn stop(&mut self, mmu: &mut impl Mmu) -> usize {
         self.stopped = true;
-        self.read(mmu);
+        // The byte after STOP is skipped without a bus cycle of its own
+        mmu.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
         4
     }
 
     #[inline]
-    fn jr(&mut self, mmu: &impl Mmu) -> usize {
-        // TODO: How does wrapping work here?
-        let pc = (self.pc as i16).wrapping_add((self.read(mmu) as i8) as i16);
-        self.pc = pc as u16;
+    fn jr(&mut self, mmu: &mut impl Mmu) -> usize {
+        let offset = self.read(mmu) as i8;
+        let pc = self.pc.wrapping_add(offset as u16);
+        self.pc = pc;
+        self.idle(mmu);
         12
     }
 
     #[inline]
-    fn jr_condition(&mut self, condition: Condition, mmu: &impl Mmu) -> usize {
-        // TODO: How does wrapping work here?
-        let pc = (self.pc as i16).wrapping_add((self.read(mmu) as i8) as i16);
+    fn jr_condition(&mut self, condition: Condition, mmu: &mut impl Mmu) -> usize {
+        let offset = self.read(mmu) as i8;
+        let pc = self.pc.wrapping_add(offset as u16);
         let met = match condition {
             Condition::Zero => self.flag(Flag::Zero),
             Condition::NotZero => !self.flag(Flag::Zero),
@@ -502,7 +552,8 @@ impl Cpu {
             Condition::NotCarry => !self.flag(Flag::Carry),
         };
         if met {
-            self.pc = pc as u16;
+            self.pc = pc;
+            self.idle(mmu);
             12
         } else {
             8
@@ -510,45 +561,50 @@ impl Cpu {
     }
 
     #[inline]
-    fn pop_value(&mut self, mmu: &impl Mmu) -> u8 {
-        let value = mmu.read(self.sp);
+    fn pop_value(&mut self, mmu: &mut impl Mmu) -> u8 {
+        let value = self.read_address_increment(self.sp, mmu);
         self.sp = self.sp.wrapping_add(1);
         value
     }
 
     #[inline]
-    fn pop_wide_value(&mut self, mmu: &impl Mmu) -> u16 {
+    fn pop_wide_value(&mut self, mmu: &mut impl Mmu) -> u16 {
         (self.pop_value(mmu) as u16) | ((self.pop_value(mmu) as u16) << 8)
     }
 
     #[inline]
     fn push_value(&mut self, value: u8, mmu: &mut impl Mmu) {
-        mmu.write(self.sp, value);
         self.sp = self.sp.wrapping_sub(1);
+        self.write_address(self.sp, value, mmu);
     }
 
+    /// Pushes the high byte first, as the hardware does. Callers spend the internal M-cycle that
+    /// precedes every push themselves.
     #[inline]
     fn push_wide_value(&mut self, value: u16, mmu: &mut impl Mmu) {
-        self.sp = self.sp.wrapping_sub(2);
-        self.write_wide(self.sp, value, mmu);
+        self.push_value((value >> 8) as u8, mmu);
+        self.push_value(value as u8, mmu);
     }
 
     #[inline]
-    fn ret(&mut self, mmu: &impl Mmu) -> usize {
+    fn ret(&mut self, mmu: &mut impl Mmu) -> usize {
         self.pc = self.pop_wide_value(mmu);
+        self.idle(mmu);
         16
     }
 
     #[inline]
-    fn ret_condition(&mut self, condition: Condition, mmu: &impl Mmu) -> usize {
+    fn ret_condition(&mut self, condition: Condition, mmu: &mut impl Mmu) -> usize {
         let met = match condition {
             Condition::Zero => self.flag(Flag::Zero),
             Condition::NotZero => !self.flag(Flag::Zero),
             Condition::Carry => self.flag(Flag::Carry),
             Condition::NotCarry => !self.flag(Flag::Carry),
         };
+        self.idle(mmu);
         if met {
             self.pc = self.pop_wide_value(mmu);
+            self.idle(mmu);
             20
         } else {
             8
@@ -557,7 +613,25 @@ impl Cpu {
 
     #[inline]
     fn daa(&mut self) -> usize {
-        unimplemented!();
+        let mut a = self.register(Register::A);
+        let mut adjust = 0x00;
+        let mut carry = self.flag(Flag::Carry);
+        if self.flag(Flag::HalfCarry) || (!self.flag(Flag::Negative) && (a & 0x0F) > 0x09) {
+            adjust |= 0x06;
+        }
+        if carry || (!self.flag(Flag::Negative) && a > 0x99) {
+            adjust |= 0x60;
+            carry = true;
+        }
+        if self.flag(Flag::Negative) {
+            a = a.wrapping_sub(adjust);
+        } else {
+            a = a.wrapping_add(adjust);
+        }
+        self.set_register(Register::A, a);
+        self.set_flag(Flag::Zero, a == 0x00);
+        self.set_flag(Flag::HalfCarry, false);
+        self.set_flag(Flag::Carry, carry);
         4
     }
 
@@ -581,7 +655,7 @@ impl Cpu {
     #[inline]
     fn write_a_hli(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        mmu.write(address, self.register(Register::A));
+        self.write_address(address, self.register(Register::A), mmu);
         self.hl = address.wrapping_add(1);
         8
     }
@@ -589,24 +663,24 @@ impl Cpu {
     #[inline]
     fn write_a_hld(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        mmu.write(address, self.register(Register::A));
+        self.write_address(address, self.register(Register::A), mmu);
         self.hl = address.wrapping_sub(1);
         8
     }
 
     #[inline]
-    fn read_a_hli(&mut self, mmu: &impl Mmu) -> usize {
+    fn read_a_hli(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address_increment(address, mmu);
         self.set_register(Register::A, value);
         self.hl = address.wrapping_add(1);
         8
     }
 
     #[inline]
-    fn read_a_hld(&mut self, mmu: &impl Mmu) -> usize {
+    fn read_a_hld(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address_increment(address, mmu);
         self.set_register(Register::A, value);
         self.hl = address.wrapping_sub(1);
         8
@@ -616,16 +690,18 @@ impl Cpu {
     fn cpl(&mut self) -> usize {
         let a = self.register(Register::A);
         self.set_register(Register::A, !a);
+        self.set_flag(Flag::Negative, true);
+        self.set_flag(Flag::HalfCarry, true);
         4
     }
 
     #[inline]
     fn inc_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         self.set_flag(Flag::HalfCarry, (value & 0x0F) == 0x0F);
         value = value.wrapping_add(1);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         self.set_flag(Flag::Zero, value == 0x00);
         self.set_flag(Flag::Negative, false);
         12
@@ -634,10 +710,10 @@ impl Cpu {
     #[inline]
     fn dec_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         self.set_flag(Flag::HalfCarry, (value & 0x0F) == 0x00);
         value = value.wrapping_sub(1);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         self.set_flag(Flag::Zero, value == 0x00);
         self.set_flag(Flag::Negative, true);
         12
@@ -646,7 +722,7 @@ impl Cpu {
     #[inline]
     fn write_mem_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
-        mmu.write(self.wide_register(WideRegister::HL), value);
+        self.write_address(self.wide_register(WideRegister::HL), value, mmu);
         12
     }
 
@@ -654,21 +730,26 @@ impl Cpu {
     fn write_register_immediate(&mut self, reg: Register, mmu: &mut impl Mmu) -> usize {
         let address = self.read_wide(mmu);
         let value = self.register(reg);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
     #[inline]
     fn read_register_immediate(&mut self, reg: Register, mmu: &mut impl Mmu) -> usize {
         let address = self.read_wide(mmu);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         self.set_register(reg, value);
         16
     }
 
     #[inline]
-    fn halt(&mut self) -> usize {
-        self.halted = true;
+    fn halt(&mut self, mmu: &mut impl Mmu) -> usize {
+        if !self.interrupts_enabled && self.interrupt_pending(mmu) {
+            // HALT bug: the CPU doesn't halt, and fails to increment PC after the next fetch
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
         4
     }
 
@@ -694,9 +775,9 @@ impl Cpu {
     }
 
     #[inline]
-    fn add_mem(&mut self, mmu: &impl Mmu) -> usize {
+    fn add_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         self.add_value(value, false);
         8
     }
@@ -710,9 +791,9 @@ impl Cpu {
     }
 
     #[inline]
-    fn add_carry_mem(&mut self, mmu: &impl Mmu) -> usize {
+    fn add_carry_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         let carry = self.flag(Flag::Carry);
         self.add_value(value, carry);
         8
@@ -740,9 +821,9 @@ impl Cpu {
     }
 
     #[inline]
-    fn sub_mem(&mut self, mmu: &impl Mmu) -> usize {
+    fn sub_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         self.sub_value(value, false);
         8
     }
@@ -756,9 +837,9 @@ impl Cpu {
     }
 
     #[inline]
-    fn sub_carry_mem(&mut self, mmu: &impl Mmu) -> usize {
+    fn sub_carry_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         let carry = self.flag(Flag::Carry);
         self.sub_value(value, carry);
         8
@@ -770,7 +851,7 @@ impl Cpu {
         a &= value;
         self.set_register(Register::A, a);
         self.set_flag(Flag::Zero, a == 0x00);
-        self.set_flag(Flag::Carry, false);
+        self.set_flag(Flag::Negative, false);
         self.set_flag(Flag::HalfCarry, true);
         self.set_flag(Flag::Carry, false);
     }
@@ -783,15 +864,15 @@ impl Cpu {
     }
 
     #[inline]
-    fn and_mem(&mut self, mmu: &impl Mmu) -> usize {
+    fn and_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         self.and_value(value);
         8
     }
 
     #[inline]
-    fn and_immediate(&mut self, mmu: &impl Mmu) -> usize {
+    fn and_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         self.and_value(value);
         8
@@ -803,7 +884,7 @@ impl Cpu {
         a ^= value;
         self.set_register(Register::A, a);
         self.set_flag(Flag::Zero, a == 0x00);
-        self.set_flag(Flag::Carry, false);
+        self.set_flag(Flag::Negative, false);
         self.set_flag(Flag::HalfCarry, false);
         self.set_flag(Flag::Carry, false);
     }
@@ -816,15 +897,15 @@ impl Cpu {
     }
 
     #[inline]
-    fn xor_mem(&mut self, mmu: &impl Mmu) -> usize {
+    fn xor_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         self.xor_value(value);
         8
     }
 
     #[inline]
-    fn xor_immediate(&mut self, mmu: &impl Mmu) -> usize {
+    fn xor_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         self.xor_value(value);
         8
@@ -836,7 +917,7 @@ impl Cpu {
         a |= value;
         self.set_register(Register::A, a);
         self.set_flag(Flag::Zero, a == 0x00);
-        self.set_flag(Flag::Carry, false);
+        self.set_flag(Flag::Negative, false);
         self.set_flag(Flag::HalfCarry, false);
         self.set_flag(Flag::Carry, false);
     }
@@ -849,15 +930,15 @@ impl Cpu {
     }
 
     #[inline]
-    fn or_mem(&mut self, mmu: &impl Mmu) -> usize {
+    fn or_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         self.or_value(value);
         8
     }
 
     #[inline]
-    fn or_immediate(&mut self, mmu: &impl Mmu) -> usize {
+    fn or_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         self.or_value(value);
         8
@@ -884,23 +965,27 @@ impl Cpu {
     }
 
     #[inline]
-    fn cp_mem(&mut self, mmu: &impl Mmu) -> usize {
+    fn cp_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         self.cp_value(value, false);
         8
     }
 
     #[inline]
-    fn cp_immediate(&mut self, mmu: &impl Mmu) -> usize {
+    fn cp_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         self.cp_value(value, false);
         8
     }
 
     #[inline]
-    fn pop_wide(&mut self, reg: WideRegister, mmu: &impl Mmu) -> usize {
-        let value = self.pop_wide_value(mmu);
+    fn pop_wide(&mut self, reg: WideRegister, mmu: &mut impl Mmu) -> usize {
+        let mut value = self.pop_wide_value(mmu);
+        if let WideRegister::AF = reg {
+            // The low nibble of F doesn't exist
+            value &= 0xFFF0;
+        }
         self.set_wide_register(reg, value);
         12
     }
@@ -908,18 +993,21 @@ impl Cpu {
     #[inline]
     fn push_wide(&mut self, reg: WideRegister, mmu: &mut impl Mmu) -> usize {
         let value = self.wide_register(reg);
+        self.idle(mmu);
+        mmu.corrupt_oam(self.sp, Corruption::Write);
         self.push_wide_value(value, mmu);
         16
     }
 
     #[inline]
-    fn jmp(&mut self, mmu: &impl Mmu) -> usize {
+    fn jmp(&mut self, mmu: &mut impl Mmu) -> usize {
         self.pc = self.read_wide(mmu);
+        self.idle(mmu);
         16
     }
 
     #[inline]
-    fn jmp_condition(&mut self, condition: Condition, mmu: &impl Mmu) -> usize {
+    fn jmp_condition(&mut self, condition: Condition, mmu: &mut impl Mmu) -> usize {
         let address = self.read_wide(mmu);
         let met = match condition {
             Condition::Zero => self.flag(Flag::Zero),
@@ -929,6 +1017,7 @@ impl Cpu {
         };
         if met {
             self.pc = address;
+            self.idle(mmu);
             16
         } else {
             12
@@ -962,14 +1051,14 @@ impl Cpu {
     }
 
     #[inline]
-    fn add_immediate(&mut self, mmu: &impl Mmu) -> usize {
+    fn add_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         self.add_value(value, false);
         8
     }
 
     #[inline]
-    fn add_carry_immediate(&mut self, mmu: &impl Mmu) -> usize {
+    fn add_carry_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         let carry = self.flag(Flag::Carry);
         self.add_value(value, carry);
@@ -977,14 +1066,14 @@ impl Cpu {
     }
 
     #[inline]
-    fn sub_immediate(&mut self, mmu: &impl Mmu) -> usize {
+    fn sub_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         self.sub_value(value, false);
         8
     }
 
     #[inline]
-    fn sub_carry_immediate(&mut self, mmu: &impl Mmu) -> usize {
+    fn sub_carry_immediate(&mut self, mmu: &mut impl Mmu) -> usize {
         let value = self.read(mmu);
         let carry = self.flag(Flag::Carry);
         self.sub_value(value, carry);
@@ -999,7 +1088,7 @@ impl Cpu {
     }
 
     #[inline]
-    fn reti(&mut self, mmu: &impl Mmu) -> usize {
+    fn reti(&mut self, mmu: &mut impl Mmu) -> usize {
         self.interrupts_enabled = true;
         self.ret(mmu)
     }
@@ -1007,7 +1096,7 @@ impl Cpu {
     #[inline]
     fn write_high_offset(&mut self, offset: u8, value: u8, mmu: &mut impl Mmu) {
         let address = 0xFF00 + (offset as u16);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
     }
 
     #[inline]
@@ -1029,7 +1118,7 @@ impl Cpu {
     #[inline]
     fn read_high_offset(&mut self, offset: u8, mmu: &mut impl Mmu) -> u8 {
         let address = 0xFF00 + (offset as u16);
-        mmu.read(address)
+        self.read_address(address, mmu)
     }
 
     #[inline]
@@ -1048,41 +1137,58 @@ impl Cpu {
         8
     }
 
+    /// SP plus a signed immediate. The flags come from the unsigned addition of the low bytes.
     #[inline]
-    fn add_sp(&mut self, mmu: &impl Mmu) -> usize {
-        let sp = self.sp as i32;
-        let value = (self.read(mmu) as i8) as i32;
-        let overflow = sp + value;
+    fn sp_offset_value(&mut self, mmu: &mut impl Mmu) -> u16 {
+        let sp = self.sp;
+        let value = self.read(mmu) as i8 as u16;
         self.set_flag(Flag::Negative, false);
         self.set_flag(Flag::Zero, false);
-        self.set_flag(Flag::HalfCarry, (overflow & 0x0FFF) < (sp & 0x0FFFF));
-        self.sp = (overflow & 0xFFFF) as u16;
-        self.set_flag(Flag::Carry, overflow > 0xFFFF);
+        self.set_flag(Flag::HalfCarry, ((sp & 0x000F) + (value & 0x000F)) > 0x000F);
+        self.set_flag(Flag::Carry, ((sp & 0x00FF) + (value & 0x00FF)) > 0x00FF);
+        sp.wrapping_add(value)
+    }
+
+    #[inline]
+    fn add_sp(&mut self, mmu: &mut impl Mmu) -> usize {
+        self.sp = self.sp_offset_value(mmu);
+        self.idle(mmu);
+        self.idle(mmu);
         16
     }
 
     #[inline]
-    fn jmp_hl(&mut self, mmu: &impl Mmu) -> usize {
-        unimplemented!();
+    fn copy_sp_offset(&mut self, mmu: &mut impl Mmu) -> usize {
+        self.hl = self.sp_offset_value(mmu);
+        self.idle(mmu);
+        12
+    }
+
+    #[inline]
+    fn jmp_hl(&mut self) -> usize {
+        self.pc = self.hl;
         4
     }
 
     #[inline]
     fn di(&mut self) -> usize {
         self.interrupts_enabled = false;
+        self.enable_interrupts = false;
         4
     }
 
+    /// Interrupts are only enabled once the instruction after EI has executed.
     #[inline]
     fn ei(&mut self) -> usize {
-        self.interrupts_enabled = true;
+        self.enable_interrupts = true;
         4
     }
 
     #[inline]
-    fn copy_wide_register(&mut self, dest: WideRegister, src: WideRegister) -> usize {
+    fn copy_wide_register(&mut self, dest: WideRegister, src: WideRegister, mmu: &mut impl Mmu) -> usize {
         let value = self.wide_register(src);
         self.set_wide_register(dest, value);
+        self.idle(mmu);
         8
     }
 
@@ -1107,9 +1213,9 @@ impl Cpu {
     #[inline]
     fn swap_mem(&mut self, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.swap_value(value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
@@ -1128,11 +1234,11 @@ impl Cpu {
     }
 
     #[inline]
-    fn bit_mem(&mut self, bit: u8, mmu: &impl Mmu) -> usize {
+    fn bit_mem(&mut self, bit: u8, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let value = mmu.read(address);
+        let value = self.read_address(address, mmu);
         self.bit_value(bit, value);
-        16
+        12
     }
 
     #[inline]
@@ -1151,9 +1257,9 @@ impl Cpu {
     #[inline]
     fn reset_bit_mem(&mut self, bit: u8, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.reset_bit_value(bit, value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
@@ -1173,64 +1279,124 @@ impl Cpu {
     #[inline]
     fn set_bit_mem(&mut self, bit: u8, mmu: &mut impl Mmu) -> usize {
         let address = self.wide_register(WideRegister::HL);
-        let mut value = mmu.read(address);
+        let mut value = self.read_address(address, mmu);
         value = self.set_bit_value(bit, value);
-        mmu.write(address, value);
+        self.write_address(address, value, mmu);
         16
     }
 
     #[inline]
-    fn service_interrupts(&mut self, mmu: &mut impl Mmu) {
-        if !self.interrupts_enabled {
-            return;
-        }
+    fn interrupt_pending(&self, mmu: &impl Mmu) -> bool {
+        (mmu.io_read(Port::IE) & mmu.io_read(Port::IF) & 0x1F) != 0x00
+    }
+
+    /// Dispatches the highest priority pending interrupt: two wait states, PC pushed high byte
+    /// first, then the jump. IE is sampled again after the high byte is pushed, so a push that
+    /// overwrites IE can cancel the dispatch, which then jumps to 0x0000.
+    fn service_interrupts(&mut self, mmu: &mut impl Mmu) -> usize {
+        self.interrupts_enabled = false;
+        self.idle(mmu);
+        self.idle(mmu);
+        let pc = self.pc;
+        self.push_value((pc >> 8) as u8, mmu);
         let if_value = mmu.io_read(Port::IF);
-        let flags = mmu.io_read(Port::IE) & if_value;
+        let flags = mmu.io_read(Port::IE) & if_value & 0x1F;
+        self.push_value(pc as u8, mmu);
         if flags == 0x00 {
-            return;
-        } else if (flags & 0x01) != 0x00 {
-            self.rst(0x0040, mmu);
-            mmu.io_write(Port::IF, if_value ^ 0x01);
-            self.interrupts_enabled = false;
-        } else if (flags & 0x02) != 0x00 {
-            self.rst(0x0048, mmu);
-            mmu.io_write(Port::IF, if_value ^ 0x02);
-            self.interrupts_enabled = false;
-        } else if (flags & 0x04) != 0x00 {
-            self.rst(0x0050, mmu);
-            mmu.io_write(Port::IF, if_value ^ 0x04);
-            self.interrupts_enabled = false;
-        } else if (flags & 0x08) != 0x00 {
-            self.rst(0x0058, mmu);
-            mmu.io_write(Port::IF, if_value ^ 0x08);
-            self.interrupts_enabled = false;
-        } else if (flags & 0x10) != 0x00 {
-            self.rst(0x0060, mmu);
-            mmu.io_write(Port::IF, if_value ^ 0x10);
-            self.interrupts_enabled = false;
+            self.pc = 0x0000;
+        } else {
+            let bit = flags.trailing_zeros() as u16;
+            mmu.io_write(Port::IF, if_value & !(1 << bit));
+            self.pc = 0x0040 + bit * 0x0008;
+        }
+        self.idle(mmu);
+        20
+    }
+
+    /// Installs a tracer that logs every executed instruction, returning the previous one.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        mem::replace(&mut self.tracer, tracer)
+    }
+
+    #[inline]
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    #[cold]
+    fn trace(&mut self, mmu: &impl Mmu) {
+        let [f, a] = Self::parts(self.af);
+        let [c, b] = Self::parts(self.bc);
+        let [e, d] = Self::parts(self.de);
+        let [l, h] = Self::parts(self.hl);
+        let pc = self.pc;
+        let pcmem = [
+            mmu.peek(pc),
+            mmu.peek(pc.wrapping_add(1)),
+            mmu.peek(pc.wrapping_add(2)),
+            mmu.peek(pc.wrapping_add(3)),
+        ];
+        let sp = self.sp;
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.log([a, f, b, c, d, e, h, l], sp, pc, pcmem);
         }
     }
 
+    /// Runs until the LCD completes a frame, returning the clock cycles it took.
+    pub fn frame(&mut self, mmu: &mut impl Hardware) -> usize {
+        let frames = mmu.board().frames();
+        let mut cycles = 0;
+        while mmu.board().frames() == frames {
+            cycles += self.cycle(mmu);
+        }
+        cycles
+    }
+
+    /// Executes one instruction, or dispatches an interrupt, returning the clock cycles it took.
+    /// The `Mmu` is ticked once per M-cycle as the instruction runs, and any time a DMA halted
+    /// the CPU for is counted too.
     pub fn cycle(&mut self, mmu: &mut impl Mmu) -> usize {
+        let cycles = self.execute(mmu);
+        cycles + mmu.take_stalled()
+    }
+
+    fn execute(&mut self, mmu: &mut impl Mmu) -> usize {
         if self.halted {
-            // TODO: exit halt state
-            return 4;
+            if !self.interrupt_pending(mmu) {
+                self.idle(mmu);
+                return 4;
+            }
+            self.halted = false;
         }
-        self.service_interrupts(mmu);
-        let opcode = self.read(mmu);
+        if self.interrupts_enabled && self.interrupt_pending(mmu) {
+            return self.service_interrupts(mmu);
+        }
+        if self.enable_interrupts {
+            self.enable_interrupts = false;
+            self.interrupts_enabled = true;
+        }
+        if self.tracer.as_ref().is_some_and(|tracer| tracer.enabled()) {
+            self.trace(mmu);
+        }
+        let opcode = if self.halt_bug {
+            self.halt_bug = false;
+            self.read_address(self.pc, mmu)
+        } else {
+            self.read(mmu)
+        };
         match opcode {
             0x00 => { self.nop() },
             0x01 => { self.read_wide_immediate(WideRegister::BC, mmu) }
             0x02 => { self.write_register(WideRegister::BC, Register::A, mmu) }
-            0x03 => { self.inc_wide(WideRegister::BC) }
+            0x03 => { self.inc_wide(WideRegister::BC, mmu) }
             0x04 => { self.inc(Register::B) }
             0x05 => { self.dec(Register::B) }
             0x06 => { self.read_immediate(Register::B, mmu) }
             0x07 => { self.rlca() }
             0x08 => { self.write_stack_immediate(mmu) }
-            0x09 => { self.add_wide(WideRegister::BC) }
+            0x09 => { self.add_wide(WideRegister::BC, mmu) }
             0x0A => { self.read_register(WideRegister::BC, Register::A, mmu) }
-            0x0B => { self.dec_wide(WideRegister::BC) }
+            0x0B => { self.dec_wide(WideRegister::BC, mmu) }
             0x0C => { self.inc(Register::C) }
             0x0D => { self.dec(Register::C) }
             0x0E => { self.read_immediate(Register::C, mmu) }
@@ -1239,15 +1405,15 @@ impl Cpu {
             0x10 => { self.stop(mmu) }
             0x11 => { self.read_wide_immediate(WideRegister::DE, mmu) }
             0x12 => { self.write_register(WideRegister::DE, Register::A, mmu) }
-            0x13 => { self.inc_wide(WideRegister::DE) }
+            0x13 => { self.inc_wide(WideRegister::DE, mmu) }
             0x14 => { self.inc(Register::D) }
             0x15 => { self.dec(Register::D) }
             0x16 => { self.read_immediate(Register::D, mmu) }
             0x17 => { self.rla() }
             0x18 => { self.jr(mmu) }
-            0x19 => { self.add_wide(WideRegister::DE) }
+            0x19 => { self.add_wide(WideRegister::DE, mmu) }
             0x1A => { self.read_register(WideRegister::DE, Register::A, mmu) }
-            0x1B => { self.dec_wide(WideRegister::DE) }
+            0x1B => { self.dec_wide(WideRegister::DE, mmu) }
             0x1C => { self.inc(Register::E) }
             0x1D => { self.dec(Register::E) }
             0x1E => { self.read_immediate(Register::E, mmu) }
@@ -1256,15 +1422,15 @@ impl Cpu {
             0x20 => { self.jr_condition(Condition::NotZero, mmu) }
             0x21 => { self.read_wide_immediate(WideRegister::HL, mmu) }
             0x22 => { self.write_a_hli(mmu) }
-            0x23 => { self.inc_wide(WideRegister::HL) }
+            0x23 => { self.inc_wide(WideRegister::HL, mmu) }
             0x24 => { self.inc(Register::H) }
             0x25 => { self.dec(Register::H) }
             0x26 => { self.read_immediate(Register::H, mmu) }
             0x27 => { self.daa() }
             0x28 => { self.jr_condition(Condition::Zero, mmu) }
-            0x29 => { self.add_wide(WideRegister::HL) }
+            0x29 => { self.add_wide(WideRegister::HL, mmu) }
             0x2A => { self.read_a_hli(mmu) }
-            0x2B => { self.dec_wide(WideRegister::HL) }
+            0x2B => { self.dec_wide(WideRegister::HL, mmu) }
             0x2C => { self.inc(Register::L) }
             0x2D => { self.dec(Register::L) }
             0x2E => { self.read_immediate(Register::L, mmu) }
@@ -1273,15 +1439,15 @@ impl Cpu {
             0x30 => { self.jr_condition(Condition::NotCarry, mmu) }
             0x31 => { self.read_wide_immediate(WideRegister::SP, mmu) }
             0x32 => { self.write_a_hld(mmu) }
-            0x33 => { self.inc_wide(WideRegister::SP) }
+            0x33 => { self.inc_wide(WideRegister::SP, mmu) }
             0x34 => { self.inc_mem(mmu) }
             0x35 => { self.dec_mem(mmu) }
             0x36 => { self.write_mem_immediate(mmu) }
             0x37 => { self.scf() }
             0x38 => { self.jr_condition(Condition::Carry, mmu) }
-            0x39 => { self.add_wide(WideRegister::SP) }
+            0x39 => { self.add_wide(WideRegister::SP, mmu) }
             0x3A => { self.read_a_hld(mmu) }
-            0x3B => { self.dec_wide(WideRegister::SP) }
+            0x3B => { self.dec_wide(WideRegister::SP, mmu) }
             0x3C => { self.inc(Register::A) }
             0x3D => { self.dec(Register::A) }
             0x3E => { self.read_immediate(Register::A, mmu) }
@@ -1344,7 +1510,7 @@ impl Cpu {
             0x73 => { self.write_register(WideRegister::HL, Register::E, mmu) }
             0x74 => { self.write_register(WideRegister::HL, Register::H, mmu) }
             0x75 => { self.write_register(WideRegister::HL, Register::L, mmu) }
-            0x76 => { self.halt() }
+            0x76 => { self.halt(mmu) }
             0x77 => { self.write_register(WideRegister::HL, Register::A, mmu) }
             0x78 => { self.copy_register(Register::A, Register::B) }
             0x79 => { self.copy_register(Register::A, Register::C) }
@@ -1466,7 +1632,7 @@ impl Cpu {
             0xE6 => { self.and_immediate(mmu) }
             0xE7 => { self.rst(0x0020, mmu) }
             0xE8 => { self.add_sp(mmu) }
-            0xE9 => { self.jmp_hl(mmu) }
+            0xE9 => { self.jmp_hl() }
             0xEA => { self.write_register_immediate(Register::A, mmu) }
             0xEB => { unimplemented!() }
             0xEC => { unimplemented!() }
@@ -1482,8 +1648,8 @@ impl Cpu {
             0xF5 => { self.push_wide(WideRegister::AF, mmu) }
             0xF6 => { self.or_immediate(mmu) }
             0xF7 => { self.rst(0x0030, mmu)}
-            0xF8 => { unimplemented!() }
-            0xF9 => { self.copy_wide_register(WideRegister::SP, WideRegister::HL) }
+            0xF8 => { self.copy_sp_offset(mmu) }
+            0xF9 => { self.copy_wide_register(WideRegister::SP, WideRegister::HL, mmu) }
             0xFA => { self.read_register_immediate(Register::A, mmu) }
             0xFB => { self.ei() }
             0xFC => { unimplemented!() }
@@ -1774,4 +1940,31 @@ impl Cpu {
             _ => { unreachable!() }
         }
     }
-}
\ No newline at end of file
+}
+
+/// The tracer isn't part of the state.
+impl State for Cpu {
+    fn save(&self, writer: &mut Writer) {
+        for &value in &[self.pc, self.sp, self.af, self.bc, self.de, self.hl] {
+            writer.u16(value);
+        }
+        for &value in &[self.interrupts_enabled, self.stopped, self.halted, self.enable_interrupts, self.halt_bug] {
+            writer.bool(value);
+        }
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.pc = reader.u16()?;
+        self.sp = reader.u16()?;
+        self.af = reader.u16()? & 0xFFF0;
+        self.bc = reader.u16()?;
+        self.de = reader.u16()?;
+        self.hl = reader.u16()?;
+        self.interrupts_enabled = reader.bool()?;
+        self.stopped = reader.bool()?;
+        self.halted = reader.bool()?;
+        self.enable_interrupts = reader.bool()?;
+        self.halt_bug = reader.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+use super::{Cpu, Flag, Tracer};
+
+/// A snapshot of the CPU's architectural state: the register file, IME and the HALT and STOP
+/// states. The low nibble of F doesn't exist on hardware and always reads as zero.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    af: u16,
+    bc: u16,
+    de: u16,
+    hl: u16,
+    sp: u16,
+    pc: u16,
+    ime: bool,
+    halted: bool,
+    stopped: bool,
+}
+
+#[inline]
+fn high(value: u16) -> u8 {
+    (value >> 8) as u8
+}
+
+#[inline]
+fn low(value: u16) -> u8 {
+    value as u8
+}
+
+#[inline]
+fn with_high(value: u16, high: u8) -> u16 {
+    (value & 0x00FF) | ((high as u16) << 8)
+}
+
+#[inline]
+fn with_low(value: u16, low: u8) -> u16 {
+    (value & 0xFF00) | (low as u16)
+}
+
+impl Registers {
+    #[inline]
+    pub fn a(&self) -> u8 {
+        high(self.af)
+    }
+
+    #[inline]
+    pub fn f(&self) -> u8 {
+        low(self.af)
+    }
+
+    #[inline]
+    pub fn b(&self) -> u8 {
+        high(self.bc)
+    }
+
+    #[inline]
+    pub fn c(&self) -> u8 {
+        low(self.bc)
+    }
+
+    #[inline]
+    pub fn d(&self) -> u8 {
+        high(self.de)
+    }
+
+    #[inline]
+    pub fn e(&self) -> u8 {
+        low(self.de)
+    }
+
+    #[inline]
+    pub fn h(&self) -> u8 {
+        high(self.hl)
+    }
+
+    #[inline]
+    pub fn l(&self) -> u8 {
+        low(self.hl)
+    }
+
+    #[inline]
+    pub fn af(&self) -> u16 {
+        self.af
+    }
+
+    #[inline]
+    pub fn bc(&self) -> u16 {
+        self.bc
+    }
+
+    #[inline]
+    pub fn de(&self) -> u16 {
+        self.de
+    }
+
+    #[inline]
+    pub fn hl(&self) -> u16 {
+        self.hl
+    }
+
+    #[inline]
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    #[inline]
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    #[inline]
+    pub fn set_a(&mut self, value: u8) {
+        self.af = with_high(self.af, value)
+    }
+
+    #[inline]
+    pub fn set_f(&mut self, value: u8) {
+        self.af = with_low(self.af, value & 0xF0)
+    }
+
+    #[inline]
+    pub fn set_b(&mut self, value: u8) {
+        self.bc = with_high(self.bc, value)
+    }
+
+    #[inline]
+    pub fn set_c(&mut self, value: u8) {
+        self.bc = with_low(self.bc, value)
+    }
+
+    #[inline]
+    pub fn set_d(&mut self, value: u8) {
+        self.de = with_high(self.de, value)
+    }
+
+    #[inline]
+    pub fn set_e(&mut self, value: u8) {
+        self.de = with_low(self.de, value)
+    }
+
+    #[inline]
+    pub fn set_h(&mut self, value: u8) {
+        self.hl = with_high(self.hl, value)
+    }
+
+    #[inline]
+    pub fn set_l(&mut self, value: u8) {
+        self.hl = with_low(self.hl, value)
+    }
+
+    #[inline]
+    pub fn set_af(&mut self, value: u16) {
+        self.af = value & 0xFFF0
+    }
+
+    #[inline]
+    pub fn set_bc(&mut self, value: u16) {
+        self.bc = value
+    }
+
+    #[inline]
+    pub fn set_de(&mut self, value: u16) {
+        self.de = value
+    }
+
+    #[inline]
+    pub fn set_hl(&mut self, value: u16) {
+        self.hl = value
+    }
+
+    #[inline]
+    pub fn set_sp(&mut self, value: u16) {
+        self.sp = value
+    }
+
+    #[inline]
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value
+    }
+
+    #[inline]
+    fn flag(&self, flag: Flag) -> bool {
+        (self.af & (flag as u16)) != 0
+    }
+
+    #[inline]
+    fn set_flag(&mut self, flag: Flag, value: bool) {
+        if value {
+            self.af |= flag as u16;
+        } else {
+            self.af &= !(flag as u16);
+        }
+    }
+
+    /// The zero flag.
+    #[inline]
+    pub fn zf(&self) -> bool {
+        self.flag(Flag::Zero)
+    }
+
+    /// The subtract flag.
+    #[inline]
+    pub fn nf(&self) -> bool {
+        self.flag(Flag::Negative)
+    }
+
+    /// The half-carry flag.
+    #[inline]
+    pub fn hf(&self) -> bool {
+        self.flag(Flag::HalfCarry)
+    }
+
+    /// The carry flag.
+    #[inline]
+    pub fn cf(&self) -> bool {
+        self.flag(Flag::Carry)
+    }
+
+    #[inline]
+    pub fn set_zf(&mut self, value: bool) {
+        self.set_flag(Flag::Zero, value)
+    }
+
+    #[inline]
+    pub fn set_nf(&mut self, value: bool) {
+        self.set_flag(Flag::Negative, value)
+    }
+
+    #[inline]
+    pub fn set_hf(&mut self, value: bool) {
+        self.set_flag(Flag::HalfCarry, value)
+    }
+
+    #[inline]
+    pub fn set_cf(&mut self, value: bool) {
+        self.set_flag(Flag::Carry, value)
+    }
+
+    /// The interrupt master enable. An `ei` still waiting out its one-instruction delay counts
+    /// as enabled.
+    #[inline]
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    #[inline]
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    #[inline]
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    #[inline]
+    pub fn set_ime(&mut self, value: bool) {
+        self.ime = value
+    }
+
+    #[inline]
+    pub fn set_halted(&mut self, value: bool) {
+        self.halted = value
+    }
+
+    #[inline]
+    pub fn set_stopped(&mut self, value: bool) {
+        self.stopped = value
+    }
+}
+
+impl Cpu {
+    pub fn builder() -> CpuBuilder {
+        CpuBuilder::default()
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            af: self.af,
+            bc: self.bc,
+            de: self.de,
+            hl: self.hl,
+            sp: self.sp,
+            pc: self.pc,
+            ime: self.interrupts_enabled || self.enable_interrupts,
+            halted: self.halted,
+            stopped: self.stopped,
+        }
+    }
+
+    /// Replaces the CPU state. Any pending `ei` delay or HALT bug is dropped.
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.af = registers.af;
+        self.bc = registers.bc;
+        self.de = registers.de;
+        self.hl = registers.hl;
+        self.sp = registers.sp;
+        self.pc = registers.pc;
+        self.interrupts_enabled = registers.ime;
+        self.enable_interrupts = false;
+        self.halted = registers.halted;
+        self.stopped = registers.stopped;
+        self.halt_bug = false;
+    }
+}
+
+/// Constructs a `Cpu` in an arbitrary state. Anything not given starts zeroed, as in
+/// `Cpu::default()`.
+#[derive(Default)]
+pub struct CpuBuilder {
+    registers: Registers,
+    tracer: Option<Tracer>,
+}
+
+impl CpuBuilder {
+    /// Starts from a whole snapshot; later calls override single registers.
+    pub fn registers(mut self, registers: Registers) -> Self {
+        self.registers = registers;
+        self
+    }
+
+    pub fn af(mut self, value: u16) -> Self {
+        self.registers.set_af(value);
+        self
+    }
+
+    pub fn bc(mut self, value: u16) -> Self {
+        self.registers.set_bc(value);
+        self
+    }
+
+    pub fn de(mut self, value: u16) -> Self {
+        self.registers.set_de(value);
+        self
+    }
+
+    pub fn hl(mut self, value: u16) -> Self {
+        self.registers.set_hl(value);
+        self
+    }
+
+    pub fn sp(mut self, value: u16) -> Self {
+        self.registers.set_sp(value);
+        self
+    }
+
+    pub fn pc(mut self, value: u16) -> Self {
+        self.registers.set_pc(value);
+        self
+    }
+
+    pub fn ime(mut self, value: bool) -> Self {
+        self.registers.set_ime(value);
+        self
+    }
+
+    pub fn halted(mut self, value: bool) -> Self {
+        self.registers.set_halted(value);
+        self
+    }
+
+    pub fn stopped(mut self, value: bool) -> Self {
+        self.registers.set_stopped(value);
+        self
+    }
+
+    pub fn tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    pub fn build(self) -> Cpu {
+        let mut cpu = Cpu::default();
+        cpu.set_registers(self.registers);
+        cpu.tracer = self.tracer;
+        cpu
+    }
+}
@@ -0,0 +1,283 @@
+//! Runs the SM83 single-step JSON test vectors found under `tests/sm83`, or the directory named
+//! by `GB18_SM83_TESTS`. Each file holds an array of vectors giving an initial CPU and RAM state,
+//! the expected final state and the bus activity of every M-cycle. Every vector executes one
+//! instruction against a flat 64KB RAM, and each divergence is reported with the opcode and the
+//! first field that differs. When no vectors are present the suite is skipped.
+
+use std::{env, fmt, fs};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use serde_json::{self, Value};
+use disasm;
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cycle {
+    Internal,
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Cycle::Internal => write!(f, "internal"),
+            Cycle::Read(address, value) => write!(f, "read ${:02X} from ${:04X}", value, address),
+            Cycle::Write(address, value) => write!(f, "write ${:02X} to ${:04X}", value, address),
+        }
+    }
+}
+
+/// Flat RAM that records what happens on the bus in each M-cycle.
+struct Bus {
+    memory: Vec<u8>,
+    cycles: RefCell<Vec<Cycle>>,
+}
+
+impl Bus {
+    /// Fills in the M-cycle the CPU just ticked into.
+    fn record(&self, cycle: Cycle) {
+        let mut cycles = self.cycles.borrow_mut();
+        match cycles.last_mut() {
+            Some(last) if *last == Cycle::Internal => *last = cycle,
+            _ => cycles.push(cycle),
+        }
+    }
+}
+
+impl Mmu for Bus {
+    fn read(&self, address: u16) -> u8 {
+        let value = self.memory[address as usize];
+        self.record(Cycle::Read(address, value));
+        value
+    }
+
+    #[inline]
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+        self.record(Cycle::Write(address, value));
+    }
+
+    fn tick(&mut self) {
+        self.cycles.borrow_mut().push(Cycle::Internal);
+    }
+
+    /// Interrupt checks between instructions aren't bus cycles.
+    fn io_read(&self, port: Port) -> u8 {
+        self.memory[port as usize]
+    }
+}
+
+/// Where a vector's outcome first departed from the expected state.
+struct Divergence {
+    name: String,
+    instruction: String,
+    field: String,
+    expected: String,
+    actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {} expected {}, got {}",
+               self.name, self.instruction, self.field, self.expected, self.actual)
+    }
+}
+
+fn number(state: &Value, key: &str) -> Option<u16> {
+    state.get(key).and_then(Value::as_u64).map(|value| value as u16)
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state.get("ram").and_then(Value::as_array).map_or(Vec::new(), |entries| {
+        entries.iter()
+            .filter_map(|entry| Some((entry.get(0)?.as_u64()? as u16, entry.get(1)?.as_u64()? as u8)))
+            .collect()
+    })
+}
+
+fn cycles(vector: &Value) -> Vec<Cycle> {
+    vector.get("cycles").and_then(Value::as_array).map_or(Vec::new(), |entries| {
+        entries.iter().map(|entry| {
+            let address = entry.get(0).and_then(Value::as_u64).map(|value| value as u16);
+            let value = entry.get(1).and_then(Value::as_u64).map(|value| value as u8);
+            let kind = entry.get(2).and_then(Value::as_str).unwrap_or("");
+            match (address, value) {
+                (Some(address), Some(value)) if kind.contains('r') => Cycle::Read(address, value),
+                (Some(address), Some(value)) if kind.contains('w') => Cycle::Write(address, value),
+                _ => Cycle::Internal,
+            }
+        }).collect()
+    })
+}
+
+const REGISTERS: [(&str, Register); 7] = [
+    ("a", Register::A), ("b", Register::B), ("c", Register::C), ("d", Register::D),
+    ("e", Register::E), ("h", Register::H), ("l", Register::L),
+];
+
+/// Runs one vector, returning the first field that diverged.
+fn run(vector: &Value) -> Result<(), Divergence> {
+    let name = vector.get("name").and_then(Value::as_str).unwrap_or("?").to_string();
+    let initial = &vector["initial"];
+    let expected = &vector["final"];
+
+    let mut bus = Bus { memory: vec![0x00; 0x10000], cycles: RefCell::new(Vec::new()) };
+    let mut cpu = Cpu::default();
+    for &(key, reg) in &REGISTERS {
+        cpu.set_register(reg, number(initial, key).unwrap_or(0) as u8);
+    }
+    cpu.af = (cpu.af & 0xFF00) | (number(initial, "f").unwrap_or(0) & 0x00FF);
+    cpu.pc = number(initial, "pc").unwrap_or(0);
+    cpu.sp = number(initial, "sp").unwrap_or(0);
+    cpu.interrupts_enabled = number(initial, "ime").unwrap_or(0) != 0;
+    if let Some(ie) = number(initial, "ie") {
+        bus.memory[Port::IE as usize] = ie as u8;
+    }
+    for (address, value) in ram(initial) {
+        bus.memory[address as usize] = value;
+    }
+    let instruction = disasm::decode(&bus, cpu.pc);
+    bus.cycles.borrow_mut().clear();
+    let instruction = format!("{} `{}`", instruction.bytes.iter()
+        .map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" "), instruction);
+
+    cpu.cycle(&mut bus);
+
+    let diverged = |field: String, expected: String, actual: String| Err(Divergence {
+        name: name.clone(),
+        instruction: instruction.clone(),
+        field,
+        expected,
+        actual,
+    });
+    let mut fields = Vec::new();
+    for &(key, reg) in &REGISTERS {
+        fields.push((key.to_string(), cpu.register(reg) as u16));
+    }
+    fields.push(("f".to_string(), cpu.af & 0x00FF));
+    fields.push(("pc".to_string(), cpu.pc));
+    fields.push(("sp".to_string(), cpu.sp));
+    // An EI still waiting out its delay counts as enabled; it takes effect before the next fetch
+    fields.push(("ime".to_string(), (cpu.interrupts_enabled || cpu.enable_interrupts) as u16));
+    for (key, actual) in fields {
+        if let Some(value) = number(expected, &key) {
+            if value != actual {
+                return diverged(key, format!("${:02X}", value), format!("${:02X}", actual));
+            }
+        }
+    }
+    if let Some(ie) = number(expected, "ie") {
+        let actual = bus.memory[Port::IE as usize];
+        if ie as u8 != actual {
+            return diverged("ie".to_string(), format!("${:02X}", ie), format!("${:02X}", actual));
+        }
+    }
+    for (address, value) in ram(expected) {
+        let actual = bus.memory[address as usize];
+        if value != actual {
+            return diverged(format!("ram[${:04X}]", address), format!("${:02X}", value), format!("${:02X}", actual));
+        }
+    }
+    let expected = cycles(vector);
+    if !expected.is_empty() {
+        let actual = bus.cycles.borrow();
+        for index in 0..expected.len().max(actual.len()) {
+            let show = |cycle: Option<&Cycle>| cycle.map_or("nothing".to_string(), Cycle::to_string);
+            if expected.get(index) != actual.get(index) {
+                return diverged(format!("cycle {}", index), show(expected.get(index)), show(actual.get(index)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs every vector in a file, returning how many ran and every divergence.
+fn run_file(path: &Path) -> (usize, Vec<Divergence>) {
+    let text = fs::read_to_string(path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    let vectors: Value = serde_json::from_str(&text).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    let vectors = vectors.as_array().map_or(&[][..], |vectors| &vectors[..]);
+    (vectors.len(), vectors.iter().filter_map(|vector| run(vector).err()).collect())
+}
+
+#[test]
+fn single_step() {
+    let dir = env::var_os("GB18_SM83_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("sm83"));
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir).map(|entries| {
+        entries.filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .collect()
+    }).unwrap_or_default();
+    if files.is_empty() {
+        eprintln!("single_step: no test vectors under {}, skipping", dir.display());
+        return;
+    }
+    files.sort();
+
+    let mut total = 0;
+    let mut failed = Vec::new();
+    for path in &files {
+        let (count, divergences) = run_file(path);
+        total += count;
+        if divergences.is_empty() {
+            continue;
+        }
+        let file = path.file_name().unwrap().to_string_lossy().into_owned();
+        eprintln!("{}: {}/{} vectors diverged, first: {}", file, divergences.len(), count, divergences[0]);
+        failed.push(file);
+    }
+    eprintln!("single_step: {} vectors in {} files", total, files.len());
+    assert!(failed.is_empty(), "diverging opcodes: {:?}", failed);
+}
+
+fn parse(json: &str) -> Value {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn passing_vector() {
+    let vector = parse(r#"{
+        "name": "cb 11 0000",
+        "initial": {"pc": 49152, "sp": 65534, "a": 0, "b": 0, "c": 128, "d": 0, "e": 0, "f": 16,
+                    "h": 0, "l": 0, "ime": 0, "ie": 0, "ram": [[49152, 203], [49153, 17]]},
+        "final": {"pc": 49154, "sp": 65534, "a": 0, "b": 0, "c": 1, "d": 0, "e": 0, "f": 16,
+                  "h": 0, "l": 0, "ime": 0, "ie": 0, "ram": [[49152, 203], [49153, 17]]},
+        "cycles": [[49152, 203, "r-m"], [49153, 17, "r-m"]]
+    }"#);
+    assert!(run(&vector).is_ok());
+}
+
+#[test]
+fn diverging_vector() {
+    let vector = parse(r#"{
+        "name": "34 0000",
+        "initial": {"pc": 256, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0,
+                    "h": 192, "l": 0, "ime": 0, "ram": [[256, 52], [49152, 15]]},
+        "final": {"pc": 257, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0,
+                  "h": 192, "l": 0, "ime": 0, "ram": [[256, 52], [49152, 16]]},
+        "cycles": [[256, 52, "r-m"], [49152, 15, "r-m"], [49152, 16, "-wm"]]
+    }"#);
+    let divergence = run(&vector).err().unwrap();
+    assert_eq!("f", divergence.field);
+    assert_eq!("$00", divergence.expected);
+    assert_eq!("$20", divergence.actual);
+    assert_eq!("34 0000 (34 `inc [hl]`): f expected $00, got $20", divergence.to_string());
+
+    let vector = parse(r#"{
+        "name": "36 0000",
+        "initial": {"pc": 256, "h": 192, "l": 0, "ram": [[256, 54], [257, 66]]},
+        "final": {"pc": 258, "ram": [[49152, 66]]},
+        "cycles": [[256, 54, "r-m"], [257, 66, "r-m"], [49152, 66, "-wm"], [258, null, "---"]]
+    }"#);
+    let divergence = run(&vector).err().unwrap();
+    assert_eq!("cycle 3", divergence.field);
+    assert_eq!("internal", divergence.expected);
+    assert_eq!("nothing", divergence.actual);
+}
@@ -49,10 +49,11 @@ fn write_register() {
 #[test]
 fn inc_wide() {
     let mut cpu = Cpu::default();
+    let mut mmu: Vec<u8> = vec!();
     cpu.set_wide_register(WideRegister::BC, 0xFFFE);
-    assert_eq!(8, cpu.inc_wide(WideRegister::BC));
+    assert_eq!(8, cpu.inc_wide(WideRegister::BC, &mut mmu));
     assert_eq!(0xFFFF, cpu.wide_register(WideRegister::BC));
-    assert_eq!(8, cpu.inc_wide(WideRegister::BC));
+    assert_eq!(8, cpu.inc_wide(WideRegister::BC, &mut mmu));
     assert_eq!(0x0000, cpu.wide_register(WideRegister::BC));
 }
 
@@ -189,7 +190,7 @@ fn write_stack_immediate() {
     let mut cpu = Cpu::default();
     let mut mmu = vec!(0x03, 0x00, 0x00, 0x00, 0x00);
     cpu.set_wide_register(WideRegister::SP, 0xDEAD);
-    assert_eq!(12, cpu.write_stack_immediate(&mut mmu));
+    assert_eq!(20, cpu.write_stack_immediate(&mut mmu));
     assert_eq!(0xAD, mmu.read(0x03));
     assert_eq!(0xDE, mmu.read(0x04));
 }
@@ -197,9 +198,10 @@ fn write_stack_immediate() {
 #[test]
 fn add_wide() {
     let mut cpu = Cpu::default();
+    let mut mmu: Vec<u8> = vec!();
     cpu.set_wide_register(WideRegister::HL, 0x0040);
     cpu.set_wide_register(WideRegister::BC, 0x0002);
-    assert_eq!(8, cpu.add_wide(WideRegister::BC));
+    assert_eq!(8, cpu.add_wide(WideRegister::BC, &mut mmu));
     assert_eq!(0x0042, cpu.wide_register(WideRegister::HL));
     assert_eq!(0x0002, cpu.wide_register(WideRegister::BC));
     assert_eq!(false, cpu.flag(Flag::Negative));
@@ -207,9 +209,10 @@ fn add_wide() {
     assert_eq!(false, cpu.flag(Flag::HalfCarry));
 
     let mut cpu = Cpu::default();
+    let mut mmu: Vec<u8> = vec!();
     cpu.set_wide_register(WideRegister::HL, 0xFFFF);
     cpu.set_wide_register(WideRegister::BC, 0x0001);
-    assert_eq!(8, cpu.add_wide(WideRegister::BC));
+    assert_eq!(8, cpu.add_wide(WideRegister::BC, &mut mmu));
     assert_eq!(0x0000, cpu.wide_register(WideRegister::HL));
     assert_eq!(0x0001, cpu.wide_register(WideRegister::BC));
     assert_eq!(false, cpu.flag(Flag::Negative));
@@ -217,9 +220,10 @@ fn add_wide() {
     assert_eq!(true, cpu.flag(Flag::HalfCarry));
 
     let mut cpu = Cpu::default();
+    let mut mmu: Vec<u8> = vec!();
     cpu.set_wide_register(WideRegister::HL, 0x0FFE);
     cpu.set_wide_register(WideRegister::BC, 0x0002);
-    assert_eq!(8, cpu.add_wide(WideRegister::BC));
+    assert_eq!(8, cpu.add_wide(WideRegister::BC, &mut mmu));
     assert_eq!(0x1000, cpu.wide_register(WideRegister::HL));
     assert_eq!(0x0002, cpu.wide_register(WideRegister::BC));
     assert_eq!(false, cpu.flag(Flag::Negative));
@@ -239,10 +243,11 @@ fn read_register() {
 #[test]
 fn dec_wide() {
     let mut cpu = Cpu::default();
+    let mut mmu: Vec<u8> = vec!();
     cpu.set_wide_register(WideRegister::BC, 0x0001);
-    assert_eq!(8, cpu.dec_wide(WideRegister::BC));
+    assert_eq!(8, cpu.dec_wide(WideRegister::BC, &mut mmu));
     assert_eq!(0x0000, cpu.wide_register(WideRegister::BC));
-    assert_eq!(8, cpu.dec_wide(WideRegister::BC));
+    assert_eq!(8, cpu.dec_wide(WideRegister::BC, &mut mmu));
     assert_eq!(0xFFFF, cpu.wide_register(WideRegister::BC));
 }
 
@@ -459,18 +464,18 @@ fn jr() {
     let mut cpu = Cpu::default();
     let mut mmu = vec!(0x42);
     assert_eq!(12, cpu.jr(&mut mmu));
-    assert_eq!(0x0042, cpu.wide_register(WideRegister::PC));
+    assert_eq!(0x0043, cpu.wide_register(WideRegister::PC));
 
     let mut cpu = Cpu::default();
     let mut mmu = vec!(0x00, -0x01i8 as u8);
     cpu.set_wide_register(WideRegister::PC, 0x0001);
     assert_eq!(12, cpu.jr(&mut mmu));
-    assert_eq!(0x0000, cpu.wide_register(WideRegister::PC));
+    assert_eq!(0x0001, cpu.wide_register(WideRegister::PC));
 
     let mut cpu = Cpu::default();
     let mut mmu = vec!(-0x01i8 as u8);
     assert_eq!(12, cpu.jr(&mut mmu));
-    assert_eq!(0xFFFF, cpu.wide_register(WideRegister::PC));
+    assert_eq!(0x0000, cpu.wide_register(WideRegister::PC));
 }
 
 #[test]
@@ -484,12 +489,12 @@ fn jr_condition() {
     let mut mmu = vec!(0x42);
     cpu.set_flag(Flag::Zero, true);
     assert_eq!(12, cpu.jr_condition(Condition::Zero, &mut mmu));
-    assert_eq!(0x0042, cpu.wide_register(WideRegister::PC));
+    assert_eq!(0x0043, cpu.wide_register(WideRegister::PC));
 
     let mut cpu = Cpu::default();
     let mut mmu = vec!(0x42);
     assert_eq!(12, cpu.jr_condition(Condition::NotZero, &mut mmu));
-    assert_eq!(0x0042, cpu.wide_register(WideRegister::PC));
+    assert_eq!(0x0043, cpu.wide_register(WideRegister::PC));
 
     let mut cpu = Cpu::default();
     let mut mmu = vec!(0x42);
@@ -506,12 +511,12 @@ fn jr_condition() {
     let mut mmu = vec!(0x42);
     cpu.set_flag(Flag::Carry, true);
     assert_eq!(12, cpu.jr_condition(Condition::Carry, &mut mmu));
-    assert_eq!(0x0042, cpu.wide_register(WideRegister::PC));
+    assert_eq!(0x0043, cpu.wide_register(WideRegister::PC));
 
     let mut cpu = Cpu::default();
     let mut mmu = vec!(0x42);
     assert_eq!(12, cpu.jr_condition(Condition::NotCarry, &mut mmu));
-    assert_eq!(0x0042, cpu.wide_register(WideRegister::PC));
+    assert_eq!(0x0043, cpu.wide_register(WideRegister::PC));
 
     let mut cpu = Cpu::default();
     let mut mmu = vec!(0x42);
@@ -545,6 +550,8 @@ fn cpl() {
     cpu.set_register(Register::A, 0xAA);
     assert_eq!(4, cpu.cpl());
     assert_eq!(0x55, cpu.register(Register::A));
+    assert_eq!(true, cpu.flag(Flag::Negative));
+    assert_eq!(true, cpu.flag(Flag::HalfCarry));
 }
 
 #[test]
@@ -1555,7 +1562,19 @@ fn sla_mem() {
 
 #[test]
 fn sra() {
+    let mut cpu = Cpu::default();
+    cpu.set_register(Register::B, 0x81);
+    assert_eq!(8, cpu.sra(Register::B));
+    assert_eq!(0xC0, cpu.register(Register::B));
+    assert_eq!(true, cpu.flag(Flag::Carry));
+    assert_eq!(false, cpu.flag(Flag::Zero));
 
+    let mut cpu = Cpu::default();
+    cpu.set_register(Register::B, 0x01);
+    assert_eq!(8, cpu.sra(Register::B));
+    assert_eq!(0x00, cpu.register(Register::B));
+    assert_eq!(true, cpu.flag(Flag::Carry));
+    assert_eq!(true, cpu.flag(Flag::Zero));
 }
 
 #[test]
@@ -1575,7 +1594,17 @@ fn swap_mem() {
 
 #[test]
 fn srl() {
+    let mut cpu = Cpu::default();
+    cpu.set_register(Register::B, 0x81);
+    assert_eq!(8, cpu.srl(Register::B));
+    assert_eq!(0x40, cpu.register(Register::B));
+    assert_eq!(true, cpu.flag(Flag::Carry));
 
+    let mut cpu = Cpu::default();
+    cpu.set_register(Register::B, 0x02);
+    assert_eq!(8, cpu.srl(Register::B));
+    assert_eq!(0x01, cpu.register(Register::B));
+    assert_eq!(false, cpu.flag(Flag::Carry));
 }
 
 #[test]
@@ -1611,4 +1640,255 @@ fn set_bit() {
 #[test]
 fn set_bit_mem() {
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn trace() {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Write::write(&mut *self.0.borrow_mut(), buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut cpu = Cpu::default();
+    let mut mmu = vec!(0x00, 0x3E, 0x42, 0x00, 0x00);
+    cpu.set_wide_register(WideRegister::AF, 0x01B0);
+    cpu.set_wide_register(WideRegister::SP, 0xFFFE);
+    cpu.set_tracer(Some(Tracer::new(Shared(log.clone()))));
+    cpu.cycle(&mut mmu);
+    cpu.cycle(&mut mmu);
+    cpu.tracer_mut().unwrap().set_enabled(false);
+    cpu.cycle(&mut mmu);
+    assert_eq!("A:01 F:B0 B:00 C:00 D:00 E:00 H:00 L:00 SP:FFFE PC:0000 PCMEM:00,3E,42,00\n\
+                A:01 F:B0 B:00 C:00 D:00 E:00 H:00 L:00 SP:FFFE PC:0001 PCMEM:3E,42,00,00\n",
+               String::from_utf8(log.borrow().clone()).unwrap());
+}
+
+/// Counts the M-cycles the CPU spends on the bus.
+struct Ticks {
+    memory: Vec<u8>,
+    ticks: usize,
+}
+
+impl Mmu for Ticks {
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+}
+
+#[test]
+fn cycles() {
+    use disasm::{self, Mnemonic};
+
+    for prefixed in &[false, true] {
+        for opcode in 0x00..0x100 {
+            let opcode = opcode as u8;
+            let bytes = if *prefixed { [0xCB, opcode, 0x00] } else { [opcode, 0x00, 0xC0] };
+            let mut mmu = Ticks { memory: vec![0x00; 0x10000], ticks: 0 };
+            mmu.memory[0xC000..0xC003].copy_from_slice(&bytes);
+            let instruction = disasm::decode(&mmu, 0xC000);
+            if instruction.mnemonic == Mnemonic::Db || (!prefixed && opcode == 0xCB) {
+                continue;
+            }
+            for flags in &[0x00, 0xF0] {
+                let mut cpu = Cpu { pc: 0xC000, ..Cpu::default() };
+                cpu.set_wide_register(WideRegister::SP, 0xD000);
+                cpu.set_wide_register(WideRegister::AF, *flags);
+                mmu.memory[0xC000..0xC003].copy_from_slice(&bytes);
+                mmu.ticks = 0;
+                let cycles = cpu.cycle(&mut mmu);
+                assert_eq!(cycles, mmu.ticks * 4, "{} ticked the bus {} times", instruction, mmu.ticks);
+                assert!(cycles == instruction.cycles || Some(cycles) == instruction.taken_cycles,
+                        "{} took {} cycles", instruction, cycles);
+            }
+        }
+    }
+}
+
+#[test]
+fn push_order() {
+    struct Log(Vec<u8>, Vec<u16>);
+
+    impl Mmu for Log {
+        fn read(&self, address: u16) -> u8 {
+            self.0[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.1.push(address);
+            self.0[address as usize] = value;
+        }
+    }
+
+    let mut cpu = Cpu::default();
+    let mut mmu = Log(vec![0x00; 0x10000], Vec::new());
+    mmu.0[0x0000] = 0xC5;
+    cpu.set_wide_register(WideRegister::SP, 0xD000);
+    cpu.set_wide_register(WideRegister::BC, 0x1234);
+    assert_eq!(16, cpu.cycle(&mut mmu));
+    assert_eq!(vec![0xCFFF, 0xCFFE], mmu.1);
+    assert_eq!(0x12, mmu.0[0xCFFF]);
+    assert_eq!(0x34, mmu.0[0xCFFE]);
+}
+
+#[test]
+fn oam_bug_accesses() {
+    struct Log(Vec<u8>, Vec<(u16, Corruption)>);
+
+    impl Mmu for Log {
+        fn read(&self, address: u16) -> u8 {
+            self.0[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.0[address as usize] = value;
+        }
+
+        fn corrupt_oam(&mut self, address: u16, corruption: Corruption) {
+            if address >= 0xFE00 {
+                self.1.push((address, corruption));
+            }
+        }
+    }
+
+    let mut cpu = Cpu::default();
+    let mut mmu = Log(vec![0x00; 0x10000], Vec::new());
+    // inc hl, ld a,[hld], ld [hli],a, push bc, pop bc
+    mmu.0[0x0000..0x0005].copy_from_slice(&[0x23, 0x3A, 0x22, 0xC5, 0xC1]);
+    cpu.set_wide_register(WideRegister::HL, 0xFE10);
+    cpu.set_wide_register(WideRegister::SP, 0xFE40);
+    for _ in 0..5 {
+        cpu.cycle(&mut mmu);
+    }
+    assert_eq!(vec![
+        (0xFE10, Corruption::Write),
+        (0xFE11, Corruption::ReadIncrement),
+        (0xFE10, Corruption::Write),
+        (0xFE40, Corruption::Write),
+        (0xFE3F, Corruption::Write),
+        (0xFE3E, Corruption::Write),
+        (0xFE3E, Corruption::ReadIncrement),
+        (0xFE3F, Corruption::ReadIncrement),
+    ], mmu.1);
+}
+
+#[test]
+fn interrupts() {
+    let mut cpu = Cpu::default();
+    let mut mmu = vec![0x00; 0x10000];
+    mmu[0x0000] = 0xFB;
+    mmu[0xFFFF] = 0x04;
+    mmu[0xFF0F] = 0x04;
+    cpu.set_wide_register(WideRegister::SP, 0xD000);
+    assert_eq!(4, cpu.cycle(&mut mmu));
+    assert_eq!(4, cpu.cycle(&mut mmu));
+    assert_eq!(0x0002, cpu.pc);
+    assert_eq!(20, cpu.cycle(&mut mmu));
+    assert_eq!(0x0050, cpu.pc);
+    assert_eq!(0x00, mmu[0xFF0F]);
+    assert_eq!(0xCFFE, cpu.wide_register(WideRegister::SP));
+    assert_eq!(0x02, mmu[0xCFFE]);
+    assert_eq!(false, cpu.interrupts_enabled);
+}
+
+#[test]
+fn halt_bug() {
+    let mut cpu = Cpu::default();
+    let mut mmu = vec![0x00; 0x10000];
+    mmu[0x0000] = 0x76;
+    mmu[0x0001] = 0x3C;
+    mmu[0xFFFF] = 0x01;
+    mmu[0xFF0F] = 0x01;
+    cpu.cycle(&mut mmu);
+    assert_eq!(false, cpu.halted);
+    cpu.cycle(&mut mmu);
+    cpu.cycle(&mut mmu);
+    assert_eq!(0x02, cpu.register(Register::A));
+    assert_eq!(0x0002, cpu.pc);
+}
+
+#[test]
+fn pop_af() {
+    let mut cpu = Cpu::default();
+    let mut mmu = vec!(0xFF, 0x12);
+    assert_eq!(12, cpu.pop_wide(WideRegister::AF, &mut mmu));
+    assert_eq!(0x12F0, cpu.wide_register(WideRegister::AF));
+}
+
+#[test]
+fn logic_clears_negative() {
+    let mut cpu = Cpu::default();
+    cpu.set_flag(Flag::Negative, true);
+    cpu.and_value(0xFF);
+    assert_eq!(false, cpu.flag(Flag::Negative));
+    cpu.set_flag(Flag::Negative, true);
+    cpu.xor_value(0xFF);
+    assert_eq!(false, cpu.flag(Flag::Negative));
+    cpu.set_flag(Flag::Negative, true);
+    cpu.or_value(0xFF);
+    assert_eq!(false, cpu.flag(Flag::Negative));
+}
+
+#[test]
+fn registers() {
+    let mut registers = Registers::default();
+    registers.set_af(0x01B7);
+    registers.set_c(0x13);
+    registers.set_hl(0x014D);
+    registers.set_pc(0x0100);
+    registers.set_ime(true);
+    assert_eq!(0x01B0, registers.af());
+    assert_eq!(true, registers.zf());
+    assert_eq!(false, registers.nf());
+    assert_eq!(true, registers.hf());
+    assert_eq!(true, registers.cf());
+    registers.set_nf(true);
+    assert_eq!(0xF0, registers.f());
+    assert_eq!(0x01, registers.h());
+
+    let mut cpu = Cpu::default();
+    cpu.set_registers(registers);
+    assert_eq!(registers, cpu.registers());
+    assert_eq!(0x13, cpu.register(Register::C));
+    assert_eq!(true, cpu.interrupts_enabled);
+}
+
+#[test]
+fn builder() {
+    let mut cpu = Cpu::builder()
+        .af(0x01B0)
+        .sp(0xFFFE)
+        .pc(0x0100)
+        .halted(true)
+        .build();
+    let registers = cpu.registers();
+    assert_eq!(0x01, registers.a());
+    assert_eq!(0xFFFE, registers.sp());
+    assert_eq!(true, registers.halted());
+    assert_eq!(false, registers.ime());
+
+    let mut mmu = vec![0x00; 0x10000];
+    mmu[0x0100] = 0xFB;
+    cpu.set_registers(Cpu::builder().pc(0x0100).build().registers());
+    cpu.cycle(&mut mmu);
+    assert_eq!(true, cpu.registers().ime());
+}
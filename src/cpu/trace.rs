@@ -0,0 +1,50 @@
+use std::io::{self, Write};
+
+/// Logs the CPU state before every instruction in the line format used by gameboy-doctor:
+///
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+pub struct Tracer {
+    out: Box<dyn Write>,
+    enabled: bool,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + 'static) -> Tracer {
+        Tracer {
+            out: Box::new(out),
+            enabled: true,
+            error: None,
+        }
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[inline]
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// The write error that stopped tracing, if any. Tracing is disabled after an error.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub(crate) fn log(&mut self, registers: [u8; 8], sp: u16, pc: u16, pcmem: [u8; 4]) {
+        let [a, f, b, c, d, e, h, l] = registers;
+        let result = writeln!(self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            a, f, b, c, d, e, h, l, sp, pc, pcmem[0], pcmem[1], pcmem[2], pcmem[3]);
+        if let Err(err) = result {
+            self.enabled = false;
+            self.error = Some(err);
+        }
+    }
+}
@@ -0,0 +1,568 @@
+#[cfg(test)]
+mod tests;
+
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use cpu::{Cpu, Flag, Register, WideRegister};
+use disasm;
+use mmu::{Corruption, Mmu};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    #[inline]
+    fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+/// A watched bus access that stopped execution.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Hit {
+    pub access: Access,
+    pub address: u16,
+    pub value: u8,
+}
+
+/// Wraps an `Mmu` so every access made through `Mmu::read` and `Mmu::write` can be watched.
+struct Watched<M: Mmu> {
+    mmu: M,
+    watchpoints: Vec<(u16, Access)>,
+    hit: Cell<Option<Hit>>,
+}
+
+impl<M: Mmu> Watched<M> {
+    #[inline]
+    fn check(&self, access: Access, address: u16, value: u8) {
+        if self.hit.get().is_some() {
+            return;
+        }
+        let watched = self.watchpoints.iter()
+            .any(|&(watch, kind)| watch == address && kind.covers(access));
+        if watched {
+            self.hit.set(Some(Hit { access, address, value }));
+        }
+    }
+}
+
+impl<M: Mmu> Mmu for Watched<M> {
+    fn read(&self, address: u16) -> u8 {
+        let value = self.mmu.read(address);
+        self.check(Access::Read, address, value);
+        value
+    }
+
+    #[inline]
+    fn peek(&self, address: u16) -> u8 {
+        self.mmu.peek(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.mmu.write(address, value);
+        self.check(Access::Write, address, value);
+    }
+
+    #[inline]
+    fn tick(&mut self) {
+        self.mmu.tick()
+    }
+
+    #[inline]
+    fn take_stalled(&mut self) -> usize {
+        self.mmu.take_stalled()
+    }
+
+    #[inline]
+    fn take_unsafe_lcd_off(&mut self) -> Option<u8> {
+        self.mmu.take_unsafe_lcd_off()
+    }
+
+    #[inline]
+    fn corrupt_oam(&mut self, address: u16, corruption: Corruption) {
+        self.mmu.corrupt_oam(address, corruption)
+    }
+
+    #[inline]
+    fn bank(&self, address: u16) -> usize {
+        self.mmu.bank(address)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    A, F, B, C, D, E, H, L,
+    AF, BC, DE, HL, SP, PC,
+    Zero, Negative, HalfCarry, Carry,
+    Ime,
+}
+
+impl Target {
+    fn parse(name: &str) -> Option<Target> {
+        match name.to_lowercase().as_str() {
+            "a" => Some(Target::A),
+            "f" => Some(Target::F),
+            "b" => Some(Target::B),
+            "c" => Some(Target::C),
+            "d" => Some(Target::D),
+            "e" => Some(Target::E),
+            "h" => Some(Target::H),
+            "l" => Some(Target::L),
+            "af" => Some(Target::AF),
+            "bc" => Some(Target::BC),
+            "de" => Some(Target::DE),
+            "hl" => Some(Target::HL),
+            "sp" => Some(Target::SP),
+            "pc" => Some(Target::PC),
+            "zf" => Some(Target::Zero),
+            "nf" => Some(Target::Negative),
+            "hf" => Some(Target::HalfCarry),
+            "cf" => Some(Target::Carry),
+            "ime" => Some(Target::Ime),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Next,
+    Continue,
+    Break(u16),
+    Delete(u16),
+    Watch(u16, Access),
+    Unwatch(u16),
+    List,
+    Registers,
+    Set(Target, u16),
+    Dump(u16, usize),
+    Disassemble(Option<u16>, usize),
+    Help,
+    Quit,
+}
+
+/// The digits of a number written in hexadecimal with a `$` or `0x` prefix.
+fn hex_digits(text: &str) -> Option<&str> {
+    text.strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"))
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = hex_digits(text).unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number: {}", text))
+}
+
+fn parse_count(text: &str) -> Result<usize, String> {
+    match hex_digits(text) {
+        Some(digits) => usize::from_str_radix(digits, 16),
+        None => text.parse(),
+    }.map_err(|_| format!("invalid count: {}", text))
+}
+
+impl Command {
+    /// Parses a single REPL line. Addresses and values are hexadecimal, optionally prefixed with
+    /// `$` or `0x`, while counts are decimal unless prefixed.
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let word = |index: usize| -> Result<&str, String> {
+            words.get(index).cloned().ok_or_else(|| format!("{}: missing argument", words[0]))
+        };
+        let arg = |index: usize| word(index).and_then(parse_number);
+        let count = |index: usize| word(index).and_then(parse_count);
+        if words.is_empty() {
+            return Err("empty command".to_string());
+        }
+        match words[0] {
+            "s" | "step" => {
+                let count = if words.len() > 1 { count(1)? } else { 1 };
+                Ok(Command::Step(count))
+            }
+            "n" | "next" => Ok(Command::Next),
+            "c" | "continue" => Ok(Command::Continue),
+            "b" | "break" => Ok(Command::Break(arg(1)?)),
+            "d" | "delete" => Ok(Command::Delete(arg(1)?)),
+            "w" | "watch" => {
+                let access = match words.get(2).copied() {
+                    None | Some("rw") => Access::ReadWrite,
+                    Some("r") => Access::Read,
+                    Some("w") => Access::Write,
+                    Some(other) => return Err(format!("watch: invalid access: {}", other)),
+                };
+                Ok(Command::Watch(arg(1)?, access))
+            }
+            "u" | "unwatch" => Ok(Command::Unwatch(arg(1)?)),
+            "l" | "list" => Ok(Command::List),
+            "r" | "regs" => Ok(Command::Registers),
+            "set" => {
+                let name = words.get(1).ok_or("set: missing register")?;
+                let target = Target::parse(name)
+                    .ok_or_else(|| format!("set: unknown register: {}", name))?;
+                Ok(Command::Set(target, arg(2)?))
+            }
+            "x" | "dump" => {
+                let length = if words.len() > 2 { count(2)? } else { 0x40 };
+                Ok(Command::Dump(arg(1)?, length))
+            }
+            "i" | "dis" => {
+                let address = if words.len() > 1 { Some(arg(1)?) } else { None };
+                let count = if words.len() > 2 { count(2)? } else { 8 };
+                Ok(Command::Disassemble(address, count))
+            }
+            "h" | "help" => Ok(Command::Help),
+            "q" | "quit" => Ok(Command::Quit),
+            other => Err(format!("unknown command: {}", other)),
+        }
+    }
+}
+
+/// Why execution stopped after a step or continue.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Step,
+    Breakpoint(u16),
+    Watchpoint(Hit),
+    /// The game turned the LCD off outside VBlank, on this line.
+    UnsafeLcdOff(u8),
+    /// Running went on for the whole budget without anything else stopping it.
+    Budget,
+}
+
+/// Instructions `continue` and `next` run before giving up, about ten seconds of emulated time.
+const BUDGET: usize = 10_000_000;
+
+static HELP: &str = "\
+s, step [n]            execute n instructions (default 1)
+n, next                step over calls and restarts
+c, continue            run until a breakpoint or watchpoint, for at most 10M instructions
+b, break <addr>        set an execution breakpoint
+d, delete <addr>       remove an execution breakpoint
+w, watch <addr> [r|w]  watch reads, writes or both (default)
+u, unwatch <addr>      remove a watchpoint
+l, list                list breakpoints and watchpoints
+r, regs                show registers
+set <reg> <value>      set a register (a..l, af..pc, zf nf hf cf, ime)
+x, dump <addr> [len]   dump memory with bank annotation
+i, dis [addr] [n]      disassemble n instructions (default at PC)
+q, quit                exit the debugger
+";
+
+pub struct Debugger<M: Mmu> {
+    cpu: Cpu,
+    bus: Watched<M>,
+    breakpoints: BTreeSet<u16>,
+    budget: usize,
+}
+
+impl<M: Mmu> Debugger<M> {
+    pub fn new(cpu: Cpu, mmu: M) -> Debugger<M> {
+        Debugger {
+            cpu,
+            bus: Watched {
+                mmu,
+                watchpoints: Vec::new(),
+                hit: Cell::new(None),
+            },
+            breakpoints: BTreeSet::new(),
+            budget: BUDGET,
+        }
+    }
+
+    #[inline]
+    pub fn pc(&self) -> u16 {
+        self.cpu.wide_register(WideRegister::PC)
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, address: u16, access: Access) {
+        self.bus.watchpoints.retain(|&(watch, _)| watch != address);
+        self.bus.watchpoints.push((address, access));
+    }
+
+    /// Sets how many instructions `continue` and `next` run before stopping with `Stop::Budget`.
+    pub fn set_budget(&mut self, instructions: usize) {
+        self.budget = instructions;
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        let count = self.bus.watchpoints.len();
+        self.bus.watchpoints.retain(|&(watch, _)| watch != address);
+        count != self.bus.watchpoints.len()
+    }
+
+    /// Executes a single instruction, reporting a watchpoint if one was hit, or the LCD being
+    /// turned off outside VBlank.
+    pub fn step(&mut self) -> Stop {
+        self.bus.hit.set(None);
+        self.cpu.cycle(&mut self.bus);
+        let unsafe_lcd_off = self.bus.take_unsafe_lcd_off();
+        match (self.bus.hit.take(), unsafe_lcd_off) {
+            (Some(hit), _) => Stop::Watchpoint(hit),
+            (None, Some(line)) => Stop::UnsafeLcdOff(line),
+            (None, None) => Stop::Step,
+        }
+    }
+
+    /// Runs until a breakpoint, a watchpoint or an unsafe LCD off. The instruction at the current
+    /// PC always executes, so continuing from a breakpoint makes progress.
+    pub fn resume(&mut self) -> Stop {
+        self.run_until(None)
+    }
+
+    /// Steps over `call` and `rst`, stopping when the instruction after it is reached.
+    pub fn step_over(&mut self) -> Stop {
+        let instruction = disasm::decode(&self.bus.mmu, self.pc());
+        if !instruction.is_call() {
+            return self.step();
+        }
+        self.run_until(Some(instruction.address.wrapping_add(instruction.len() as u16)))
+    }
+
+    fn run_until(&mut self, target: Option<u16>) -> Stop {
+        for _ in 0..self.budget {
+            let stop = self.step();
+            if stop != Stop::Step {
+                return stop;
+            }
+            let pc = self.pc();
+            if Some(pc) == target {
+                return Stop::Step;
+            }
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+        Stop::Budget
+    }
+
+    pub fn set(&mut self, target: Target, value: u16) {
+        let byte = value as u8;
+        let cpu = &mut self.cpu;
+        match target {
+            Target::A => cpu.set_register(Register::A, byte),
+            Target::B => cpu.set_register(Register::B, byte),
+            Target::C => cpu.set_register(Register::C, byte),
+            Target::D => cpu.set_register(Register::D, byte),
+            Target::E => cpu.set_register(Register::E, byte),
+            Target::H => cpu.set_register(Register::H, byte),
+            Target::L => cpu.set_register(Register::L, byte),
+            Target::F => {
+                let af = cpu.wide_register(WideRegister::AF);
+                cpu.set_wide_register(WideRegister::AF, (af & 0xFF00) | (value & 0x00F0));
+            }
+            Target::AF => cpu.set_wide_register(WideRegister::AF, value & 0xFFF0),
+            Target::BC => cpu.set_wide_register(WideRegister::BC, value),
+            Target::DE => cpu.set_wide_register(WideRegister::DE, value),
+            Target::HL => cpu.set_wide_register(WideRegister::HL, value),
+            Target::SP => cpu.set_wide_register(WideRegister::SP, value),
+            Target::PC => cpu.set_wide_register(WideRegister::PC, value),
+            Target::Zero => cpu.set_flag(Flag::Zero, value != 0),
+            Target::Negative => cpu.set_flag(Flag::Negative, value != 0),
+            Target::HalfCarry => cpu.set_flag(Flag::HalfCarry, value != 0),
+            Target::Carry => cpu.set_flag(Flag::Carry, value != 0),
+            Target::Ime => cpu.interrupts_enabled = value != 0,
+        }
+    }
+
+    pub fn print_registers(&self, out: &mut impl Write) -> io::Result<()> {
+        let cpu = &self.cpu;
+        let flag = |flag: Flag, name: char| if cpu.flag(flag) { name } else { '-' };
+        writeln!(out, "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} {}{}{}{} IME={} HALT={}",
+                 cpu.wide_register(WideRegister::AF),
+                 cpu.wide_register(WideRegister::BC),
+                 cpu.wide_register(WideRegister::DE),
+                 cpu.wide_register(WideRegister::HL),
+                 cpu.wide_register(WideRegister::SP),
+                 cpu.wide_register(WideRegister::PC),
+                 flag(Flag::Zero, 'Z'),
+                 flag(Flag::Negative, 'N'),
+                 flag(Flag::HalfCarry, 'H'),
+                 flag(Flag::Carry, 'C'),
+                 cpu.interrupts_enabled as u8,
+                 cpu.halted as u8)
+    }
+
+    /// Names the memory region at `address` along with its currently mapped bank, e.g. `WRA1`.
+    pub fn region(&self, address: u16) -> String {
+        let name = match address {
+            0x0000 ..= 0x7FFF => "ROM",
+            0x8000 ..= 0x9FFF => "VRA",
+            0xA000 ..= 0xBFFF => "SRA",
+            0xC000 ..= 0xDFFF => "WRA",
+            0xE000 ..= 0xFDFF => "ECH",
+            0xFE00 ..= 0xFE9F => return "OAM".to_string(),
+            0xFEA0 ..= 0xFEFF => return "----".to_string(),
+            0xFF00 ..= 0xFF7F => return "I/O".to_string(),
+            0xFF80 ..= 0xFFFE => "HRA",
+            _ => return "IE".to_string(),
+        };
+        format!("{}{:X}", name, self.bus.mmu.bank(address))
+    }
+
+    pub fn dump(&self, address: u16, length: usize, out: &mut impl Write) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < length {
+            let start = address.wrapping_add(offset as u16);
+            let count = (length - offset).min(16);
+            write!(out, "{:>4}:{:04X} ", self.region(start), start)?;
+            let bytes: Vec<u8> = (0..count)
+                .map(|i| self.bus.mmu.read(start.wrapping_add(i as u16)))
+                .collect();
+            for byte in &bytes {
+                write!(out, " {:02X}", byte)?;
+            }
+            for _ in count..16 {
+                write!(out, "   ")?;
+            }
+            let text: String = bytes.iter()
+                .map(|&byte| if (0x20..0x7F).contains(&byte) { byte as char } else { '.' })
+                .collect();
+            writeln!(out, "  |{}|", text)?;
+            offset += count;
+        }
+        Ok(())
+    }
+
+    pub fn disassemble(&self, address: u16, count: usize, out: &mut impl Write) -> io::Result<()> {
+        let mut address = address;
+        for _ in 0..count {
+            let instruction = disasm::decode(&self.bus.mmu, address);
+            let bytes: Vec<String> = instruction.bytes.iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            writeln!(out, "{:>4}:{:04X}  {:<9} {}",
+                     self.region(address), address, bytes.join(" "), instruction)?;
+            address = address.wrapping_add(instruction.len() as u16);
+        }
+        Ok(())
+    }
+
+    fn report(&self, stop: Stop, out: &mut impl Write) -> io::Result<()> {
+        match stop {
+            Stop::Step => {}
+            Stop::Breakpoint(address) => writeln!(out, "breakpoint at ${:04X}", address)?,
+            Stop::Watchpoint(hit) => {
+                let access = if hit.access == Access::Write { "write" } else { "read" };
+                writeln!(out, "watchpoint: {} ${:04X} = ${:02X}", access, hit.address, hit.value)?
+            }
+            Stop::UnsafeLcdOff(line) => writeln!(out, "warning: LCD turned off outside VBlank, on line {}", line)?,
+            Stop::Budget => writeln!(out, "stopped after {} instructions", self.budget)?,
+        }
+        self.print_registers(out)?;
+        let pc = self.pc();
+        self.disassemble(pc, 1, out)
+    }
+
+    /// Executes a command, returning `false` once the debugger should exit.
+    pub fn execute(&mut self, command: &Command, out: &mut impl Write) -> io::Result<bool> {
+        match *command {
+            Command::Step(count) => {
+                let mut stop = Stop::Step;
+                for _ in 0..count {
+                    stop = self.step();
+                    if stop != Stop::Step {
+                        break;
+                    }
+                }
+                self.report(stop, out)?;
+            }
+            Command::Next => {
+                let stop = self.step_over();
+                self.report(stop, out)?;
+            }
+            Command::Continue => {
+                let stop = self.resume();
+                self.report(stop, out)?;
+            }
+            Command::Break(address) => {
+                self.add_breakpoint(address);
+                writeln!(out, "breakpoint at ${:04X}", address)?;
+            }
+            Command::Delete(address) => {
+                if !self.remove_breakpoint(address) {
+                    writeln!(out, "no breakpoint at ${:04X}", address)?;
+                }
+            }
+            Command::Watch(address, access) => {
+                self.add_watchpoint(address, access);
+                writeln!(out, "watchpoint at ${:04X}", address)?;
+            }
+            Command::Unwatch(address) => {
+                if !self.remove_watchpoint(address) {
+                    writeln!(out, "no watchpoint at ${:04X}", address)?;
+                }
+            }
+            Command::List => {
+                for address in &self.breakpoints {
+                    writeln!(out, "break ${:04X}", address)?;
+                }
+                for &(address, access) in &self.bus.watchpoints {
+                    let kind = match access {
+                        Access::Read => "r",
+                        Access::Write => "w",
+                        Access::ReadWrite => "rw",
+                    };
+                    writeln!(out, "watch ${:04X} {}", address, kind)?;
+                }
+            }
+            Command::Registers => self.print_registers(out)?,
+            Command::Set(target, value) => {
+                self.set(target, value);
+                self.print_registers(out)?;
+            }
+            Command::Dump(address, length) => self.dump(address, length, out)?,
+            Command::Disassemble(address, count) => {
+                let address = address.unwrap_or_else(|| self.pc());
+                self.disassemble(address, count, out)?;
+            }
+            Command::Help => write!(out, "{}", HELP)?,
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Runs the interactive prompt until `quit` or end of input. An empty line repeats the last
+    /// command.
+    pub fn repl(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        let mut last = None;
+        self.report(Stop::Step, &mut out)?;
+        write!(out, "(gb18) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let command = if line.trim().is_empty() {
+                last.clone()
+            } else {
+                match Command::parse(&line) {
+                    Ok(command) => Some(command),
+                    Err(message) => {
+                        writeln!(out, "{}", message)?;
+                        None
+                    }
+                }
+            };
+            if let Some(command) = command {
+                if !self.execute(&command, &mut out)? {
+                    return Ok(());
+                }
+                last = Some(command);
+            }
+            write!(out, "(gb18) ")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
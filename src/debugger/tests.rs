@@ -0,0 +1,145 @@
+use super::*;
+use cpu::Tracer;
+use mmu::Mbc0;
+use model::Model;
+
+fn load(program: &[u8]) -> Debugger<Vec<u8>> {
+    let mut mmu = vec![0x00; 0x10000];
+    mmu[..program.len()].copy_from_slice(program);
+    Debugger::new(Cpu::default(), mmu)
+}
+
+#[test]
+fn parse() {
+    assert_eq!(Ok(Command::Step(1)), Command::parse("s"));
+    assert_eq!(Ok(Command::Step(10)), Command::parse("step 10"));
+    assert_eq!(Ok(Command::Step(0x10)), Command::parse("step $10"));
+    assert_eq!(Ok(Command::Break(0x0150)), Command::parse("b $0150"));
+    assert_eq!(Ok(Command::Break(0xC000)), Command::parse("break 0xC000"));
+    assert_eq!(Ok(Command::Watch(0xFF44, Access::Read)), Command::parse("w FF44 r"));
+    assert_eq!(Ok(Command::Watch(0xC000, Access::ReadWrite)), Command::parse("watch C000"));
+    assert_eq!(Ok(Command::Set(Target::HL, 0xBEEF)), Command::parse("set hl beef"));
+    assert_eq!(Ok(Command::Dump(0x8000, 0x40)), Command::parse("x 8000"));
+    assert_eq!(Ok(Command::Dump(0x8000, 16)), Command::parse("x 8000 16"));
+    assert_eq!(Ok(Command::Disassemble(None, 8)), Command::parse("dis"));
+    assert_eq!(Ok(Command::Disassemble(Some(0x0150), 2)), Command::parse("i 150 2"));
+    assert!(Command::parse("b").is_err());
+    assert!(Command::parse("set q 1").is_err());
+    assert!(Command::parse("step 1f").is_err());
+    assert!(Command::parse("frobnicate").is_err());
+}
+
+#[test]
+fn breakpoint() {
+    // nop; nop; nop; jr -2
+    let mut debugger = load(&[0x00, 0x00, 0x00, 0x18, 0xFE]);
+    debugger.add_breakpoint(0x0002);
+    assert_eq!(Stop::Breakpoint(0x0002), debugger.resume());
+    assert_eq!(0x0002, debugger.pc());
+}
+
+#[test]
+fn budget() {
+    // jr -2
+    let mut debugger = load(&[0x18, 0xFE]);
+    debugger.set_budget(100);
+    assert_eq!(Stop::Budget, debugger.resume());
+    assert_eq!(0x0000, debugger.pc());
+}
+
+#[test]
+fn watchpoint() {
+    // ld a, $42; ld [$C000], a; jr -2
+    let mut debugger = load(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+    debugger.add_watchpoint(0xC000, Access::Write);
+    let hit = Hit { access: Access::Write, address: 0xC000, value: 0x42 };
+    assert_eq!(Stop::Watchpoint(hit), debugger.resume());
+    assert_eq!(0x0005, debugger.pc());
+
+    let mut debugger = load(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+    debugger.add_watchpoint(0xC000, Access::Read);
+    debugger.add_breakpoint(0x0005);
+    assert_eq!(Stop::Breakpoint(0x0005), debugger.resume());
+}
+
+#[test]
+fn trace() {
+    // Tracing reads the bytes at PC without tripping watchpoints
+    let mut cpu = Cpu::default();
+    cpu.set_tracer(Some(Tracer::new(io::sink())));
+    let mut mmu = vec![0x00; 0x10000];
+    mmu[..5].copy_from_slice(&[0x00, 0x00, 0x00, 0x18, 0xFE]);
+    let mut debugger = Debugger::new(cpu, mmu);
+    debugger.add_watchpoint(0x0003, Access::Read);
+    for _ in 0..3 {
+        assert_eq!(Stop::Step, debugger.step());
+    }
+    assert_eq!(Stop::Watchpoint(Hit { access: Access::Read, address: 0x0003, value: 0x18 }), debugger.step());
+}
+
+#[test]
+fn step_over() {
+    // call $0010; nop ... $0010: ret
+    let mut debugger = load(&[0xCD, 0x10, 0x00, 0x00]);
+    debugger.bus.mmu[0x0010] = 0xC9;
+    debugger.set(Target::SP, 0xFFFE);
+    assert_eq!(Stop::Step, debugger.step_over());
+    assert_eq!(0x0003, debugger.pc());
+    assert_eq!(Stop::Step, debugger.step_over());
+    assert_eq!(0x0004, debugger.pc());
+}
+
+#[test]
+fn set() {
+    let mut debugger = load(&[]);
+    debugger.set(Target::A, 0x42);
+    debugger.set(Target::F, 0xFF);
+    assert_eq!(0x42F0, debugger.cpu.wide_register(WideRegister::AF));
+    debugger.set(Target::Zero, 0);
+    assert_eq!(false, debugger.cpu.flag(Flag::Zero));
+    debugger.set(Target::Ime, 1);
+    assert_eq!(true, debugger.cpu.interrupts_enabled);
+}
+
+#[test]
+fn dump() {
+    let debugger = load(&[0x48, 0x49]);
+    let mut out = Vec::new();
+    debugger.dump(0x0000, 2, &mut out).unwrap();
+    assert_eq!("ROM0:0000  48 49                                            |HI|\n",
+               String::from_utf8(out).unwrap());
+    assert_eq!("WRA1", debugger.region(0xD000));
+    assert_eq!("OAM", debugger.region(0xFE00));
+}
+
+#[test]
+fn disassemble() {
+    let debugger = load(&[0x3E, 0x42, 0xCB, 0x7C]);
+    let mut out = Vec::new();
+    debugger.disassemble(0x0000, 2, &mut out).unwrap();
+    assert_eq!("ROM0:0000  3E 42     ld a, $42\nROM0:0002  CB 7C     bit 7, h\n",
+               String::from_utf8(out).unwrap());
+}
+
+#[test]
+fn unsafe_lcd_off() {
+    // ld a, $91; ldh [$40], a; xor a; ldh [$40], a; jr -2
+    let program = [0x3E, 0x91, 0xE0, 0x40, 0xAF, 0xE0, 0x40, 0x18, 0xFE];
+    let machine = |model| {
+        let mut mmu = Mbc0::with_model(vec![0x00; 0x8000], model);
+        for (i, &byte) in program.iter().enumerate() {
+            mmu.write(0xC000 + i as u16, byte);
+        }
+        let mut debugger = Debugger::new(Cpu::default(), mmu);
+        debugger.set(Target::PC, 0xC000);
+        debugger.add_breakpoint(0xC007);
+        debugger
+    };
+    let mut debugger = machine(Model::Dmg);
+    assert_eq!(Stop::UnsafeLcdOff(0), debugger.resume());
+    assert_eq!(0xC007, debugger.pc());
+
+    // A Game Boy Color's screen doesn't mind
+    let mut debugger = machine(Model::Cgb);
+    assert_eq!(Stop::Breakpoint(0xC007), debugger.resume());
+}
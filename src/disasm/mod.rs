@@ -0,0 +1,420 @@
+#[cfg(test)]
+mod tests;
+
+use std::fmt;
+use mmu::Mmu;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mnemonic {
+    Nop, Stop, Halt, Di, Ei,
+    Ld, Ldh, Push, Pop,
+    Inc, Dec, Add, Adc, Sub, Sbc, And, Xor, Or, Cp,
+    Daa, Cpl, Scf, Ccf,
+    Rlca, Rrca, Rla, Rra,
+    Rlc, Rrc, Rl, Rr, Sla, Sra, Swap, Srl, Bit, Res, Set,
+    Jr, Jp, Call, Ret, Reti, Rst,
+    /// An opcode the SM83 does not implement, shown as a raw `db`.
+    Db,
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Mnemonic::Nop => "nop",
+            Mnemonic::Stop => "stop",
+            Mnemonic::Halt => "halt",
+            Mnemonic::Di => "di",
+            Mnemonic::Ei => "ei",
+            Mnemonic::Ld => "ld",
+            Mnemonic::Ldh => "ldh",
+            Mnemonic::Push => "push",
+            Mnemonic::Pop => "pop",
+            Mnemonic::Inc => "inc",
+            Mnemonic::Dec => "dec",
+            Mnemonic::Add => "add",
+            Mnemonic::Adc => "adc",
+            Mnemonic::Sub => "sub",
+            Mnemonic::Sbc => "sbc",
+            Mnemonic::And => "and",
+            Mnemonic::Xor => "xor",
+            Mnemonic::Or => "or",
+            Mnemonic::Cp => "cp",
+            Mnemonic::Daa => "daa",
+            Mnemonic::Cpl => "cpl",
+            Mnemonic::Scf => "scf",
+            Mnemonic::Ccf => "ccf",
+            Mnemonic::Rlca => "rlca",
+            Mnemonic::Rrca => "rrca",
+            Mnemonic::Rla => "rla",
+            Mnemonic::Rra => "rra",
+            Mnemonic::Rlc => "rlc",
+            Mnemonic::Rrc => "rrc",
+            Mnemonic::Rl => "rl",
+            Mnemonic::Rr => "rr",
+            Mnemonic::Sla => "sla",
+            Mnemonic::Sra => "sra",
+            Mnemonic::Swap => "swap",
+            Mnemonic::Srl => "srl",
+            Mnemonic::Bit => "bit",
+            Mnemonic::Res => "res",
+            Mnemonic::Set => "set",
+            Mnemonic::Jr => "jr",
+            Mnemonic::Jp => "jp",
+            Mnemonic::Call => "call",
+            Mnemonic::Ret => "ret",
+            Mnemonic::Reti => "reti",
+            Mnemonic::Rst => "rst",
+            Mnemonic::Db => "db",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Register {
+    A, B, C, D, E, H, L,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WideRegister {
+    AF, BC, DE, HL, SP,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    NotZero, Zero, NotCarry, Carry,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    WideRegister(WideRegister),
+    /// `[bc]`, `[de]` or `[hl]`
+    Indirect(WideRegister),
+    /// `[hl+]`
+    IndirectIncrement,
+    /// `[hl-]`
+    IndirectDecrement,
+    /// `[c]`, i.e. `$FF00 + c`
+    HighC,
+    Condition(Condition),
+    Immediate(u8),
+    WideImmediate(u16),
+    /// `[n16]`
+    Address(u16),
+    /// `[$FF00 + n8]`, shown as the full address
+    HighAddress(u8),
+    /// The absolute target of a `jr`, resolved from the instruction address
+    Relative(u16),
+    /// The signed operand of `add sp, e8`
+    Signed(i8),
+    /// `sp + e8`
+    StackOffset(i8),
+    Bit(u8),
+    Vector(u8),
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Register::A => "a",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+            Register::E => "e",
+            Register::H => "h",
+            Register::L => "l",
+        })
+    }
+}
+
+impl fmt::Display for WideRegister {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            WideRegister::AF => "af",
+            WideRegister::BC => "bc",
+            WideRegister::DE => "de",
+            WideRegister::HL => "hl",
+            WideRegister::SP => "sp",
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Condition::NotZero => "nz",
+            Condition::Zero => "z",
+            Condition::NotCarry => "nc",
+            Condition::Carry => "c",
+        })
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Register(reg) => write!(f, "{}", reg),
+            Operand::WideRegister(reg) => write!(f, "{}", reg),
+            Operand::Indirect(reg) => write!(f, "[{}]", reg),
+            Operand::IndirectIncrement => f.write_str("[hl+]"),
+            Operand::IndirectDecrement => f.write_str("[hl-]"),
+            Operand::HighC => f.write_str("[c]"),
+            Operand::Condition(condition) => write!(f, "{}", condition),
+            Operand::Immediate(value) => write!(f, "${:02X}", value),
+            Operand::WideImmediate(value) => write!(f, "${:04X}", value),
+            Operand::Address(address) => write!(f, "[${:04X}]", address),
+            Operand::HighAddress(offset) => write!(f, "[${:04X}]", 0xFF00 | (offset as u16)),
+            Operand::Relative(target) => write!(f, "${:04X}", target),
+            Operand::Signed(value) => write!(f, "{}", value),
+            Operand::StackOffset(value) if value < 0 => write!(f, "sp - {}", -(value as i16)),
+            Operand::StackOffset(value) => write!(f, "sp + {}", value),
+            Operand::Bit(bit) => write!(f, "{}", bit),
+            Operand::Vector(vector) => write!(f, "${:02X}", vector),
+        }
+    }
+}
+
+/// A decoded SM83 instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: Mnemonic,
+    pub operands: Vec<Operand>,
+    /// Clock cycles taken, or for conditional branches, taken when the branch is not.
+    pub cycles: usize,
+    /// Clock cycles taken by a conditional branch when the condition is met.
+    pub taken_cycles: Option<usize>,
+}
+
+impl Instruction {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Never true, as every instruction has at least its opcode.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Whether this instruction pushes a return address, so a debugger can step over it.
+    #[inline]
+    pub fn is_call(&self) -> bool {
+        self.mnemonic == Mnemonic::Call || self.mnemonic == Mnemonic::Rst
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+        }
+        Ok(())
+    }
+}
+
+static REGISTERS: [Option<Register>; 8] = [
+    Some(Register::B), Some(Register::C), Some(Register::D), Some(Register::E),
+    Some(Register::H), Some(Register::L), None, Some(Register::A),
+];
+
+static WIDE_REGISTERS: [WideRegister; 4] = [
+    WideRegister::BC, WideRegister::DE, WideRegister::HL, WideRegister::SP,
+];
+
+static STACK_REGISTERS: [WideRegister; 4] = [
+    WideRegister::BC, WideRegister::DE, WideRegister::HL, WideRegister::AF,
+];
+
+static CONDITIONS: [Condition; 4] = [
+    Condition::NotZero, Condition::Zero, Condition::NotCarry, Condition::Carry,
+];
+
+static ALU: [Mnemonic; 8] = [
+    Mnemonic::Add, Mnemonic::Adc, Mnemonic::Sub, Mnemonic::Sbc,
+    Mnemonic::And, Mnemonic::Xor, Mnemonic::Or, Mnemonic::Cp,
+];
+
+static SHIFTS: [Mnemonic; 8] = [
+    Mnemonic::Rlc, Mnemonic::Rrc, Mnemonic::Rl, Mnemonic::Rr,
+    Mnemonic::Sla, Mnemonic::Sra, Mnemonic::Swap, Mnemonic::Srl,
+];
+
+/// The register operand encoded in the low three bits of many opcodes, where 6 is `[hl]`.
+#[inline]
+fn register_operand(index: u8) -> Operand {
+    match REGISTERS[(index & 0x07) as usize] {
+        Some(reg) => Operand::Register(reg),
+        None => Operand::Indirect(WideRegister::HL),
+    }
+}
+
+struct Decoder<'a, M: Mmu + 'a> {
+    mmu: &'a M,
+    address: u16,
+    bytes: Vec<u8>,
+}
+
+impl<'a, M: Mmu> Decoder<'a, M> {
+    #[inline]
+    fn read(&mut self) -> u8 {
+        let value = self.mmu.read(self.address.wrapping_add(self.bytes.len() as u16));
+        self.bytes.push(value);
+        value
+    }
+
+    #[inline]
+    fn read_wide(&mut self) -> u16 {
+        (self.read() as u16) | ((self.read() as u16) << 8)
+    }
+
+    #[inline]
+    fn relative(&mut self) -> Operand {
+        let offset = self.read() as i8;
+        let next = self.address.wrapping_add(2);
+        Operand::Relative(next.wrapping_add(offset as i16 as u16))
+    }
+
+    fn finish(self, mnemonic: Mnemonic, operands: Vec<Operand>, cycles: usize,
+              taken_cycles: Option<usize>) -> Instruction {
+        Instruction {
+            address: self.address,
+            bytes: self.bytes,
+            mnemonic,
+            operands,
+            cycles,
+            taken_cycles,
+        }
+    }
+}
+
+/// Decodes the instruction at `address`. Reads go through `Mmu::read` but have no other effect
+/// on the emulated machine.
+pub fn decode(mmu: &impl Mmu, address: u16) -> Instruction {
+    let mut decoder = Decoder { mmu, address, bytes: Vec::with_capacity(3) };
+    let opcode = decoder.read();
+    let y = (opcode >> 3) & 0x07;
+    let z = opcode & 0x07;
+    let p = ((opcode >> 4) & 0x03) as usize;
+    let mem = |index: u8| (index & 0x07) == 0x06;
+
+    use self::Mnemonic::*;
+    use self::Operand::*;
+    let (mnemonic, operands, cycles, taken) = match opcode {
+        0x00 => (Nop, vec![], 4, None),
+        0x10 => (Stop, vec![Immediate(decoder.read())], 4, None),
+        0x76 => (Halt, vec![], 4, None),
+        0xF3 => (Di, vec![], 4, None),
+        0xFB => (Ei, vec![], 4, None),
+        0xCB => return decode_prefixed(decoder),
+
+        0x01 | 0x11 | 0x21 | 0x31 => {
+            (Ld, vec![WideRegister(WIDE_REGISTERS[p]), WideImmediate(decoder.read_wide())], 12, None)
+        }
+        0x02 | 0x12 => (Ld, vec![Indirect(WIDE_REGISTERS[p]), Register(self::Register::A)], 8, None),
+        0x0A | 0x1A => (Ld, vec![Register(self::Register::A), Indirect(WIDE_REGISTERS[p])], 8, None),
+        0x22 => (Ld, vec![IndirectIncrement, Register(self::Register::A)], 8, None),
+        0x32 => (Ld, vec![IndirectDecrement, Register(self::Register::A)], 8, None),
+        0x2A => (Ld, vec![Register(self::Register::A), IndirectIncrement], 8, None),
+        0x3A => (Ld, vec![Register(self::Register::A), IndirectDecrement], 8, None),
+        0x03 | 0x13 | 0x23 | 0x33 => (Inc, vec![WideRegister(WIDE_REGISTERS[p])], 8, None),
+        0x0B | 0x1B | 0x2B | 0x3B => (Dec, vec![WideRegister(WIDE_REGISTERS[p])], 8, None),
+        0x09 | 0x19 | 0x29 | 0x39 => {
+            (Add, vec![WideRegister(self::WideRegister::HL), WideRegister(WIDE_REGISTERS[p])], 8, None)
+        }
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
+            (Inc, vec![register_operand(y)], if mem(y) { 12 } else { 4 }, None)
+        }
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => {
+            (Dec, vec![register_operand(y)], if mem(y) { 12 } else { 4 }, None)
+        }
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
+            (Ld, vec![register_operand(y), Immediate(decoder.read())], if mem(y) { 12 } else { 8 }, None)
+        }
+        0x07 => (Rlca, vec![], 4, None),
+        0x0F => (Rrca, vec![], 4, None),
+        0x17 => (Rla, vec![], 4, None),
+        0x1F => (Rra, vec![], 4, None),
+        0x27 => (Daa, vec![], 4, None),
+        0x2F => (Cpl, vec![], 4, None),
+        0x37 => (Scf, vec![], 4, None),
+        0x3F => (Ccf, vec![], 4, None),
+        0x08 => (Ld, vec![Address(decoder.read_wide()), WideRegister(self::WideRegister::SP)], 20, None),
+        0x18 => (Jr, vec![decoder.relative()], 12, None),
+        0x20 | 0x28 | 0x30 | 0x38 => {
+            (Jr, vec![Condition(CONDITIONS[(y - 4) as usize]), decoder.relative()], 8, Some(12))
+        }
+
+        0x40 ..= 0x7F => {
+            let cycles = if mem(y) || mem(z) { 8 } else { 4 };
+            (Ld, vec![register_operand(y), register_operand(z)], cycles, None)
+        }
+        0x80 ..= 0xBF => {
+            let cycles = if mem(z) { 8 } else { 4 };
+            (ALU[y as usize], alu_operands(ALU[y as usize], register_operand(z)), cycles, None)
+        }
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+            let operand = Immediate(decoder.read());
+            (ALU[y as usize], alu_operands(ALU[y as usize], operand), 8, None)
+        }
+
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => (Ret, vec![Condition(CONDITIONS[y as usize])], 8, Some(20)),
+        0xC9 => (Ret, vec![], 16, None),
+        0xD9 => (Reti, vec![], 16, None),
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => (Pop, vec![WideRegister(STACK_REGISTERS[p])], 12, None),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => (Push, vec![WideRegister(STACK_REGISTERS[p])], 16, None),
+        0xC2 | 0xCA | 0xD2 | 0xDA => {
+            (Jp, vec![Condition(CONDITIONS[y as usize]), WideImmediate(decoder.read_wide())], 12, Some(16))
+        }
+        0xC3 => (Jp, vec![WideImmediate(decoder.read_wide())], 16, None),
+        0xE9 => (Jp, vec![WideRegister(self::WideRegister::HL)], 4, None),
+        0xC4 | 0xCC | 0xD4 | 0xDC => {
+            (Call, vec![Condition(CONDITIONS[y as usize]), WideImmediate(decoder.read_wide())], 12, Some(24))
+        }
+        0xCD => (Call, vec![WideImmediate(decoder.read_wide())], 24, None),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => (Rst, vec![Vector(y * 8)], 16, None),
+
+        0xE0 => (Ldh, vec![HighAddress(decoder.read()), Register(self::Register::A)], 12, None),
+        0xF0 => (Ldh, vec![Register(self::Register::A), HighAddress(decoder.read())], 12, None),
+        0xE2 => (Ldh, vec![HighC, Register(self::Register::A)], 8, None),
+        0xF2 => (Ldh, vec![Register(self::Register::A), HighC], 8, None),
+        0xEA => (Ld, vec![Address(decoder.read_wide()), Register(self::Register::A)], 16, None),
+        0xFA => (Ld, vec![Register(self::Register::A), Address(decoder.read_wide())], 16, None),
+        0xE8 => (Add, vec![WideRegister(self::WideRegister::SP), Signed(decoder.read() as i8)], 16, None),
+        0xF8 => {
+            (Ld, vec![WideRegister(self::WideRegister::HL), StackOffset(decoder.read() as i8)], 12, None)
+        }
+        0xF9 => (Ld, vec![WideRegister(self::WideRegister::SP), WideRegister(self::WideRegister::HL)], 8, None),
+
+        _ => (Db, vec![Immediate(opcode)], 4, None),
+    };
+    decoder.finish(mnemonic, operands, cycles, taken)
+}
+
+/// RGBDS spells out the accumulator for `add`, `adc` and `sbc`, but not for the other ALU ops.
+#[inline]
+fn alu_operands(mnemonic: Mnemonic, operand: Operand) -> Vec<Operand> {
+    match mnemonic {
+        Mnemonic::Add | Mnemonic::Adc | Mnemonic::Sbc => vec![Operand::Register(Register::A), operand],
+        _ => vec![operand],
+    }
+}
+
+fn decode_prefixed<M: Mmu>(mut decoder: Decoder<M>) -> Instruction {
+    let opcode = decoder.read();
+    let y = (opcode >> 3) & 0x07;
+    let z = opcode & 0x07;
+    let operand = register_operand(z);
+    let mem = z == 0x06;
+    let (mnemonic, operands, cycles) = match opcode {
+        0x00 ..= 0x3F => (SHIFTS[y as usize], vec![operand], if mem { 16 } else { 8 }),
+        0x40 ..= 0x7F => (Mnemonic::Bit, vec![Operand::Bit(y), operand], if mem { 12 } else { 8 }),
+        0x80 ..= 0xBF => (Mnemonic::Res, vec![Operand::Bit(y), operand], if mem { 16 } else { 8 }),
+        _ => (Mnemonic::Set, vec![Operand::Bit(y), operand], if mem { 16 } else { 8 }),
+    };
+    decoder.finish(mnemonic, operands, cycles, None)
+}
@@ -0,0 +1,177 @@
+use super::*;
+
+fn decode_bytes(bytes: &[u8], address: u16) -> Instruction {
+    let mut mmu = vec![0x00; 0x10000];
+    for (i, byte) in bytes.iter().enumerate() {
+        mmu[address as usize + i] = *byte;
+    }
+    decode(&mmu, address)
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    match text.strip_prefix('$') {
+        Some(digits) => i32::from_str_radix(digits, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Matches `text` against the shape of `template`, appending any operand bytes it encodes.
+fn assemble_operand(template: &Operand, text: &str, address: u16, bytes: &mut Vec<u8>) -> bool {
+    let inner = if text.starts_with('[') && text.ends_with(']') {
+        Some(&text[1..text.len() - 1])
+    } else {
+        None
+    };
+    match *template {
+        Operand::Immediate(_) => match parse_number(text) {
+            Some(value) if (0..=0xFF).contains(&value) => bytes.push(value as u8),
+            _ => return false,
+        },
+        Operand::WideImmediate(_) => match parse_number(text) {
+            Some(value) if (0..=0xFFFF).contains(&value) => {
+                bytes.push(value as u8);
+                bytes.push((value >> 8) as u8);
+            }
+            _ => return false,
+        },
+        Operand::Address(_) => match inner.and_then(parse_number) {
+            Some(value) if (0..=0xFFFF).contains(&value) => {
+                bytes.push(value as u8);
+                bytes.push((value >> 8) as u8);
+            }
+            _ => return false,
+        },
+        Operand::HighAddress(_) => match inner.and_then(parse_number) {
+            Some(value) if (0xFF00..=0xFFFF).contains(&value) => bytes.push(value as u8),
+            _ => return false,
+        },
+        Operand::Relative(_) => match parse_number(text) {
+            Some(target) => {
+                let offset = target - (address as i32 + 2);
+                if !(-128..=127).contains(&offset) {
+                    return false;
+                }
+                bytes.push(offset as i8 as u8);
+            }
+            _ => return false,
+        },
+        Operand::Signed(_) => match parse_number(text) {
+            Some(value) if (-128..=127).contains(&value) => bytes.push(value as i8 as u8),
+            _ => return false,
+        },
+        Operand::StackOffset(_) => {
+            let value = if let Some(offset) = text.strip_prefix("sp + ") {
+                parse_number(offset)
+            } else if let Some(offset) = text.strip_prefix("sp - ") {
+                parse_number(offset).map(|value| -value)
+            } else {
+                None
+            };
+            match value {
+                Some(value) if (-128..=127).contains(&value) => bytes.push(value as i8 as u8),
+                _ => return false,
+            }
+        }
+        _ => return template.to_string() == text,
+    }
+    true
+}
+
+/// A minimal assembler for the RGBDS syntax `decode` emits, used to check the text round-trips.
+fn assemble(text: &str, address: u16) -> Option<Vec<u8>> {
+    let (mnemonic, operands) = match text.find(' ') {
+        Some(index) => (&text[..index], text[index + 1..].split(", ").collect()),
+        None => (text, Vec::new()),
+    };
+    for prefixed in &[false, true] {
+        for opcode in 0x00..0x100 {
+            let opcode = opcode as u8;
+            if !prefixed && opcode == 0xCB {
+                continue;
+            }
+            let mut bytes = if *prefixed { vec![0xCB, opcode] } else { vec![opcode] };
+            let template = decode_bytes(&bytes, address);
+            if template.mnemonic.to_string() != mnemonic || template.operands.len() != operands.len() {
+                continue;
+            }
+            if template.mnemonic == Mnemonic::Db {
+                if operands[0] == template.operands[0].to_string() {
+                    return Some(bytes);
+                }
+                continue;
+            }
+            let matched = template.operands.iter().zip(operands.iter())
+                .all(|(template, text)| assemble_operand(template, text, address, &mut bytes));
+            if matched {
+                return Some(bytes);
+            }
+        }
+    }
+    None
+}
+
+#[test]
+fn round_trip() {
+    let address = 0x4000;
+    for prefixed in &[false, true] {
+        for opcode in 0x00..0x100 {
+            let opcode = opcode as u8;
+            if !prefixed && opcode == 0xCB {
+                continue;
+            }
+            let bytes = if *prefixed { vec![0xCB, opcode] } else { vec![opcode, 0xF0, 0xFF] };
+            let instruction = decode_bytes(&bytes, address);
+            let text = instruction.to_string();
+            assert_eq!(Some(instruction.bytes.clone()), assemble(&text, address),
+                       "{:02X?} disassembled to {:?}", bytes, text);
+            assert_eq!(&bytes[..instruction.len()], &instruction.bytes[..]);
+        }
+    }
+}
+
+#[test]
+fn lengths() {
+    assert_eq!(1, decode_bytes(&[0x00], 0).len());
+    assert_eq!(2, decode_bytes(&[0x10, 0x00], 0).len());
+    assert_eq!(3, decode_bytes(&[0x01, 0x34, 0x12], 0).len());
+    assert_eq!(2, decode_bytes(&[0xE0, 0x40], 0).len());
+    assert_eq!(3, decode_bytes(&[0xCD, 0x00, 0x40], 0).len());
+    assert_eq!(2, decode_bytes(&[0xCB, 0x7C], 0).len());
+    assert_eq!(1, decode_bytes(&[0xD3], 0).len());
+}
+
+#[test]
+fn cycles() {
+    let instruction = decode_bytes(&[0x20, 0x00], 0);
+    assert_eq!((8, Some(12)), (instruction.cycles, instruction.taken_cycles));
+    let instruction = decode_bytes(&[0xC4, 0x00, 0x00], 0);
+    assert_eq!((12, Some(24)), (instruction.cycles, instruction.taken_cycles));
+    let instruction = decode_bytes(&[0xC0], 0);
+    assert_eq!((8, Some(20)), (instruction.cycles, instruction.taken_cycles));
+    assert_eq!(20, decode_bytes(&[0x08, 0x00, 0xC0], 0).cycles);
+    assert_eq!(12, decode_bytes(&[0xCB, 0x46], 0).cycles);
+    assert_eq!(16, decode_bytes(&[0xCB, 0x86], 0).cycles);
+    assert_eq!(8, decode_bytes(&[0x46], 0).cycles);
+    assert_eq!(4, decode_bytes(&[0x41], 0).cycles);
+}
+
+#[test]
+fn format() {
+    assert_eq!("ld bc, $1234", decode_bytes(&[0x01, 0x34, 0x12], 0).to_string());
+    assert_eq!("ld [hl+], a", decode_bytes(&[0x22], 0).to_string());
+    assert_eq!("ld [hl], $42", decode_bytes(&[0x36, 0x42], 0).to_string());
+    assert_eq!("ldh [$FF40], a", decode_bytes(&[0xE0, 0x40], 0).to_string());
+    assert_eq!("ldh a, [c]", decode_bytes(&[0xF2], 0).to_string());
+    assert_eq!("jr nz, $0150", decode_bytes(&[0x20, 0x0E], 0x0140).to_string());
+    assert_eq!("jr $0100", decode_bytes(&[0x18, 0xFE], 0x0100).to_string());
+    assert_eq!("add a, b", decode_bytes(&[0x80], 0).to_string());
+    assert_eq!("sub [hl]", decode_bytes(&[0x96], 0).to_string());
+    assert_eq!("add sp, -2", decode_bytes(&[0xE8, 0xFE], 0).to_string());
+    assert_eq!("ld hl, sp + 4", decode_bytes(&[0xF8, 0x04], 0).to_string());
+    assert_eq!("ld hl, sp - 128", decode_bytes(&[0xF8, 0x80], 0).to_string());
+    assert_eq!("rst $38", decode_bytes(&[0xFF], 0).to_string());
+    assert_eq!("bit 7, h", decode_bytes(&[0xCB, 0x7C], 0).to_string());
+    assert_eq!("swap [hl]", decode_bytes(&[0xCB, 0x36], 0).to_string());
+    assert_eq!("db $DD", decode_bytes(&[0xDD], 0).to_string());
+    assert_eq!("stop $00", decode_bytes(&[0x10, 0x00], 0).to_string());
+}
@@ -0,0 +1,323 @@
+#[cfg(test)]
+mod tests;
+
+use std::{io, mem};
+use apu::Apu;
+use cpu::Cpu;
+use mmu::{Mmu, Port};
+use model::Model;
+use timer::Timer;
+
+const MAGIC: &[u8; 3] = b"GBS";
+
+/// The header before the data in a GBS file.
+const HEADER_SIZE: usize = 0x70;
+
+/// Where the CPU waits between calls, in the space below the load address the file leaves to the
+/// player: a `jr` to itself. A `halt` would hit the HALT bug once init enables interrupts in IE.
+const IDLE: u16 = 0x00F0;
+
+/// M-cycles between VBlanks.
+const FRAME_CYCLES: u32 = 17_556;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A NUL-padded string of the header.
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&byte| byte == 0x00).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[inline]
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    bytes[offset] as u16 | ((bytes[offset + 1] as u16) << 8)
+}
+
+/// The header of a GBS file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub songs: u8,
+    /// The song to play first, from 1.
+    pub first_song: u8,
+    /// Where the data after the header goes.
+    pub load: u16,
+    /// Called with the song, from 0, in A.
+    pub init: u16,
+    /// Called at the rate the timer settings choose.
+    pub play: u16,
+    pub stack: u16,
+    pub tma: u8,
+    /// With bit 2 set, play is called on timer interrupts from TMA and TAC instead of at each
+    /// VBlank. Bit 7 asks for the Game Boy Color's double speed.
+    pub tac: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> io::Result<Header> {
+        if data.len() < HEADER_SIZE || &data[..3] != MAGIC {
+            return Err(invalid("not a GBS file"));
+        }
+        if data[0x03] != 1 {
+            return Err(invalid("unsupported GBS version"));
+        }
+        let header = Header {
+            songs: data[0x04],
+            first_song: data[0x05],
+            load: u16_at(data, 0x06),
+            init: u16_at(data, 0x08),
+            play: u16_at(data, 0x0A),
+            stack: u16_at(data, 0x0C),
+            tma: data[0x0E],
+            tac: data[0x0F],
+            title: text(&data[0x10..0x30]),
+            author: text(&data[0x30..0x50]),
+            copyright: text(&data[0x50..0x70]),
+        };
+        if header.load < 0x0400 || header.load >= 0x8000 {
+            return Err(invalid("GBS load address outside ROM"));
+        }
+        Ok(header)
+    }
+
+    /// Whether play is called on timer interrupts rather than at VBlank.
+    #[inline]
+    pub fn timer(&self) -> bool {
+        (self.tac & 0x04) != 0
+    }
+}
+
+/// Just what GBS music needs: banked ROM holding the data, cartridge and work RAM, HRAM, the
+/// timer and the APU. There's no LCD, but VBlank is still requested every frame to pace play.
+struct Gbs {
+    rom: Vec<u8>,
+    bank: usize,
+    ram: Vec<u8>,
+    wram: Vec<u8>,
+    hram: [u8; 0x7F],
+    timer: Timer,
+    apu: Apu,
+    interrupt_flag: u8,
+    interrupt_enable: u8,
+    frame_cycles: u32,
+    /// VBlanks and timer interrupts since the player last looked, as in IF. Kept apart from IF
+    /// so the music's own interrupt handling can't hide them.
+    requests: u8,
+    /// Without double speed in the CPU, the timer runs twice per M-cycle instead.
+    double_speed: bool,
+}
+
+impl Gbs {
+    /// Places the data at the load address and, below it, the player's code: RST vectors
+    /// jumping into the data, as the format moves them there, `reti` for interrupts, and the
+    /// idle loop.
+    fn new(header: &Header, data: &[u8]) -> Gbs {
+        let load = header.load as usize;
+        let size = (load + data.len()).max(0x8000);
+        let mut rom = vec![0xFF; (size + 0x3FFF) & !0x3FFF];
+        rom[load..load + data.len()].copy_from_slice(data);
+        for rst in (0x00..0x40).step_by(0x08) {
+            let target = header.load + rst as u16;
+            rom[rst..rst + 3].copy_from_slice(&[0xC3, target as u8, (target >> 8) as u8]);
+        }
+        for vector in (0x40..0x68).step_by(0x08) {
+            rom[vector] = 0xD9;
+        }
+        let idle = IDLE as usize;
+        rom[idle..idle + 2].copy_from_slice(&[0x18, 0xFE]);
+        Gbs {
+            rom,
+            bank: 1,
+            ram: vec![0x00; 0x2000],
+            wram: vec![0x00; 0x2000],
+            hram: [0x00; 0x7F],
+            timer: Timer::default(),
+            // Double speed is for the Game Boy Color, with its own output filter
+            apu: Apu::new(if (header.tac & 0x80) != 0 { Model::Cgb } else { Model::Dmg }),
+            interrupt_flag: 0x00,
+            interrupt_enable: 0x00,
+            frame_cycles: 0,
+            requests: 0x00,
+            double_speed: (header.tac & 0x80) != 0,
+        }
+    }
+
+    #[inline]
+    fn banks(&self) -> usize {
+        self.rom.len() / 0x4000
+    }
+}
+
+impl Mmu for Gbs {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000 ..= 0x3FFF => self.rom[address as usize],
+            0x4000 ..= 0x7FFF => self.rom[self.bank * 0x4000 + (address as usize - 0x4000)],
+            0xA000 ..= 0xBFFF => self.ram[address as usize - 0xA000],
+            0xC000 ..= 0xDFFF => self.wram[address as usize - 0xC000],
+            0xE000 ..= 0xFDFF => self.wram[address as usize - 0xE000],
+            0xFF04 => self.timer.div(),
+            0xFF05 => self.timer.tima(),
+            0xFF06 => self.timer.tma(),
+            0xFF07 => self.timer.tac(),
+            0xFF0F => self.interrupt_flag | 0xE0,
+            0xFF10 ..= 0xFF3F => self.apu.read(address),
+            0xFF80 ..= 0xFFFE => self.hram[address as usize - 0xFF80],
+            0xFFFF => self.interrupt_enable,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            // Any bank can be selected, except that 0 selects 1
+            0x2000 ..= 0x3FFF => self.bank = (value as usize % self.banks()).max(1),
+            0xA000 ..= 0xBFFF => self.ram[address as usize - 0xA000] = value,
+            0xC000 ..= 0xDFFF => self.wram[address as usize - 0xC000] = value,
+            0xE000 ..= 0xFDFF => self.wram[address as usize - 0xE000] = value,
+            0xFF04 => self.timer.write_div(),
+            0xFF05 => self.timer.write_tima(value),
+            0xFF06 => self.timer.write_tma(value),
+            0xFF07 => self.timer.write_tac(value),
+            0xFF0F => self.interrupt_flag = value & 0x1F,
+            0xFF10 ..= 0xFF3F => self.apu.write(address, value),
+            0xFF80 ..= 0xFFFE => self.hram[address as usize - 0xFF80] = value,
+            0xFFFF => self.interrupt_enable = value,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        let mut interrupts = 0x00;
+        self.frame_cycles += 1;
+        if self.frame_cycles == FRAME_CYCLES {
+            self.frame_cycles = 0;
+            interrupts |= 0x01;
+        }
+        let mut overflow = self.timer.tick();
+        if self.double_speed {
+            overflow |= self.timer.tick();
+        }
+        if overflow {
+            interrupts |= 0x04;
+        }
+        self.interrupt_flag |= interrupts;
+        self.requests |= interrupts;
+        // In double speed the frame sequencer follows the next bit of DIV up
+        let div = self.timer.div();
+        self.apu.tick(if self.double_speed { div >> 1 } else { div });
+    }
+
+    #[inline]
+    fn bank(&self, address: u16) -> usize {
+        match address {
+            0x4000 ..= 0x7FFF => self.bank,
+            _ => 0,
+        }
+    }
+}
+
+/// Plays the songs of a GBS file on the CPU core: init is called for the chosen song, then play
+/// on every VBlank or timer interrupt, whichever the header asks for. A call that comes while the
+/// last one is still running is skipped.
+pub struct Player {
+    header: Header,
+    data: Vec<u8>,
+    cpu: Cpu,
+    gbs: Gbs,
+}
+
+impl Player {
+    pub fn new(file: &[u8]) -> io::Result<Player> {
+        let header = Header::parse(file)?;
+        let data = file[HEADER_SIZE..].to_vec();
+        if header.load as usize + data.len() > 0x400000 {
+            return Err(invalid("GBS data too large"));
+        }
+        let gbs = Gbs::new(&header, &data);
+        Ok(Player { header, data, cpu: Cpu::default(), gbs })
+    }
+
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    #[inline]
+    pub fn apu(&self) -> &Apu {
+        &self.gbs.apu
+    }
+
+    /// The APU, to set the sample rate and take samples from.
+    #[inline]
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.gbs.apu
+    }
+
+    /// Resets everything but the APU's settings and calls init for a song, from 0. The sound
+    /// starts on, as the boot ROM leaves it.
+    pub fn start(&mut self, song: u8) -> io::Result<()> {
+        if song >= self.header.songs {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no such song in the GBS file"));
+        }
+        let apu = mem::take(&mut self.gbs.apu);
+        self.gbs = Gbs::new(&self.header, &self.data);
+        self.gbs.apu = apu;
+        self.gbs.apu.reset();
+        self.gbs.apu.write(0xFF26, 0x80);
+        self.gbs.apu.write(0xFF24, 0x77);
+        self.gbs.apu.write(0xFF25, 0xFF);
+        self.gbs.io_write(Port::TMA, self.header.tma);
+        self.gbs.io_write(Port::TIMA, self.header.tma);
+        self.gbs.io_write(Port::TAC, self.header.tac & 0x07);
+        self.cpu = Cpu::default();
+        let mut registers = self.cpu.registers();
+        registers.set_sp(self.header.stack);
+        registers.set_pc(IDLE);
+        self.cpu.set_registers(registers);
+        let init = self.header.init;
+        self.call(init, song);
+        Ok(())
+    }
+
+    #[inline]
+    fn idle(&self) -> bool {
+        let pc = self.cpu.registers().pc();
+        (IDLE..IDLE + 2).contains(&pc)
+    }
+
+    /// Calls a routine with `a` in A, returning to the idle loop.
+    fn call(&mut self, routine: u16, a: u8) {
+        let mut registers = self.cpu.registers();
+        let sp = registers.sp().wrapping_sub(2);
+        self.gbs.write(sp, IDLE as u8);
+        self.gbs.write(sp.wrapping_add(1), (IDLE >> 8) as u8);
+        registers.set_sp(sp);
+        registers.set_pc(routine);
+        registers.set_a(a);
+        registers.set_ime(false);
+        registers.set_halted(false);
+        self.cpu.set_registers(registers);
+    }
+
+    /// Runs for at least `clocks` clock cycles, returning how many it ran.
+    pub fn run(&mut self, clocks: usize) -> usize {
+        let source = if self.header.timer() { 0x04 } else { 0x01 };
+        let mut elapsed = 0;
+        while elapsed < clocks {
+            elapsed += self.cpu.cycle(&mut self.gbs);
+            if (self.gbs.requests & source) != 0 {
+                self.gbs.requests = 0x00;
+                if self.idle() {
+                    let play = self.header.play;
+                    self.call(play, 0);
+                }
+            }
+        }
+        elapsed
+    }
+}
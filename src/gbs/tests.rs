@@ -0,0 +1,136 @@
+use super::*;
+
+/// A GBS file with three songs. Init stores the song at 0xC000 and starts channel 1; play
+/// counts its calls at 0xC001. The data reaches into bank 2, which starts with 0x42.
+fn file(tma: u8, tac: u8) -> Vec<u8> {
+    let mut file = vec![0x00; HEADER_SIZE + 0x8000];
+    file[..4].copy_from_slice(b"GBS\x01");
+    file[0x04] = 3;
+    file[0x05] = 1;
+    file[0x06..0x10].copy_from_slice(&[0x00, 0x04, 0x00, 0x04, 0x10, 0x04, 0xFE, 0xFF, tma, tac]);
+    file[0x10..0x15].copy_from_slice(b"Title");
+    file[0x30..0x36].copy_from_slice(b"Author");
+    file[HEADER_SIZE..HEADER_SIZE + 12].copy_from_slice(&[
+        0xEA, 0x00, 0xC0,   // ld [$C000], a
+        0x3E, 0xF0,         // ld a, $F0
+        0xE0, 0x12,         // ldh [$FF12], a
+        0x3E, 0x87,         // ld a, $87
+        0xE0, 0x14,         // ldh [$FF14], a
+        0xC9,               // ret
+    ]);
+    file[HEADER_SIZE + 0x10..HEADER_SIZE + 0x15].copy_from_slice(&[
+        0x21, 0x01, 0xC0,   // ld hl, $C001
+        0x34,               // inc [hl]
+        0xC9,               // ret
+    ]);
+    file[HEADER_SIZE + 0x8000 - 0x0400] = 0x42;
+    file
+}
+
+#[test]
+fn header() {
+    let header = Header::parse(&file(0xF0, 0x04)).unwrap();
+    assert_eq!(Header {
+        songs: 3,
+        first_song: 1,
+        load: 0x0400,
+        init: 0x0400,
+        play: 0x0410,
+        stack: 0xFFFE,
+        tma: 0xF0,
+        tac: 0x04,
+        title: "Title".to_string(),
+        author: "Author".to_string(),
+        copyright: String::new(),
+    }, header);
+    assert!(header.timer());
+
+    let err = Header::parse(b"GBX\x01").unwrap_err();
+    assert_eq!("not a GBS file", err.to_string());
+    let mut data = file(0x00, 0x00);
+    data[0x03] = 2;
+    assert_eq!("unsupported GBS version", Header::parse(&data).unwrap_err().to_string());
+    data[0x03] = 1;
+    data[0x07] = 0x00;
+    assert_eq!("GBS load address outside ROM", Header::parse(&data).unwrap_err().to_string());
+}
+
+#[test]
+fn memory() {
+    let data = file(0x00, 0x00);
+    let header = Header::parse(&data).unwrap();
+    let mut gbs = Gbs::new(&header, &data[HEADER_SIZE..]);
+    assert_eq!(&[0xC3, 0x00, 0x04], &[gbs.read(0x0000), gbs.read(0x0001), gbs.read(0x0002)]);
+    assert_eq!(&[0xC3, 0x38, 0x04], &[gbs.read(0x0038), gbs.read(0x0039), gbs.read(0x003A)]);
+    assert_eq!(0xD9, gbs.read(0x0040));
+    assert_eq!(0xEA, gbs.read(0x0400));
+    assert_eq!(0x00, gbs.read(0x4000));
+    gbs.write(0x2000, 0x02);
+    assert_eq!(0x42, gbs.read(0x4000));
+    assert_eq!(2, gbs.bank(0x4000));
+    gbs.write(0x2000, 0x00);
+    assert_eq!(1, gbs.bank(0x4000));
+    gbs.write(0xE123, 0x99);
+    assert_eq!(0x99, gbs.read(0xC123));
+    assert_eq!(0xFF, gbs.read(0x8000));
+    assert_eq!(0xFF, gbs.read(0xFF40));
+}
+
+#[test]
+fn vblank() {
+    let mut player = Player::new(&file(0x00, 0x00)).unwrap();
+    player.start(2).unwrap();
+    player.run(70_224 * 10 + 1_000);
+    assert_eq!(2, player.gbs.read(0xC000));
+    assert_eq!(10, player.gbs.read(0xC001));
+    assert_eq!(0xF1, player.apu().read(0xFF26));
+
+    player.start(0).unwrap();
+    assert_eq!(0, player.gbs.read(0xC001));
+    player.run(1_000);
+    assert_eq!(0, player.gbs.read(0xC000));
+    assert_eq!(io::ErrorKind::InvalidInput, player.start(3).unwrap_err().kind());
+}
+
+#[test]
+fn interrupts_enabled() {
+    // Init enables VBlank in IE, leaving it pending while the player idles
+    let mut data = file(0x00, 0x00);
+    data[HEADER_SIZE + 11..HEADER_SIZE + 16].copy_from_slice(&[
+        0x3E, 0x01,         // ld a, $01
+        0xE0, 0xFF,         // ldh [$FFFF], a
+        0xC9,               // ret
+    ]);
+    let mut player = Player::new(&data).unwrap();
+    player.start(0).unwrap();
+    player.run(70_224 * 10 + 1_000);
+    assert_eq!(10, player.gbs.read(0xC001));
+}
+
+#[test]
+fn timer() {
+    // 16 increments of 1024 clocks between calls
+    let mut player = Player::new(&file(0xF0, 0x04)).unwrap();
+    player.start(0).unwrap();
+    player.run(16_384 * 8 + 1_000);
+    assert_eq!(8, player.gbs.read(0xC001));
+
+    // Twice as often in double speed
+    let mut player = Player::new(&file(0xF0, 0x84)).unwrap();
+    player.start(0).unwrap();
+    player.run(16_384 * 8 + 1_000);
+    assert_eq!(16, player.gbs.read(0xC001));
+}
+
+#[test]
+fn samples() {
+    let mut player = Player::new(&file(0x00, 0x00)).unwrap();
+    player.apu_mut().set_sample_rate(48_000);
+    player.start(0).unwrap();
+    player.run(70_224);
+    let samples = player.apu_mut().take_samples();
+    assert!(samples.len() >= 1600 && samples.len() <= 1610);
+    assert!(samples.iter().any(|&sample| sample != 0));
+    player.start(1).unwrap();
+    assert_eq!(48_000, player.apu().sample_rate());
+}
@@ -0,0 +1,135 @@
+#[cfg(test)]
+mod tests;
+
+use std::io;
+use state::{Reader, State, Writer};
+
+pub const RIGHT: u8 = 0x01;
+pub const LEFT: u8 = 0x02;
+pub const UP: u8 = 0x04;
+pub const DOWN: u8 = 0x08;
+pub const A: u8 = 0x10;
+pub const B: u8 = 0x20;
+pub const SELECT: u8 = 0x40;
+pub const START: u8 = 0x80;
+
+/// Parses a comma-separated list of button names, like `a,start`, into a mask.
+pub fn parse(names: &str) -> Option<u8> {
+    let mut buttons = 0x00;
+    for name in names.split(',') {
+        buttons |= match name.to_lowercase().as_str() {
+            "right" => RIGHT,
+            "left" => LEFT,
+            "up" => UP,
+            "down" => DOWN,
+            "a" => A,
+            "b" => B,
+            "select" => SELECT,
+            "start" => START,
+            _ => return None,
+        };
+    }
+    Some(buttons)
+}
+
+/// The JOYP button matrix. Bits 4 and 5 of JOYP select the direction keys and the action
+/// buttons, and the low nibble reads the selected lines, pulled low while a button is held.
+/// A selected line going low requests the joypad interrupt.
+///
+/// A Super Game Boy can multiplex up to four joypads. With more than one player, deselecting
+/// both lines reads the current joypad's ID (0xF for the first, counting down) and each rising
+/// edge of bit 5 moves on to the next joypad.
+pub struct Joypad {
+    select: u8,
+    buttons: [u8; 4],
+    players: usize,
+    player: usize,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad {
+            select: 0,
+            buttons: [0; 4],
+            players: 1,
+            player: 0,
+        }
+    }
+}
+
+impl Joypad {
+    /// The selected lines that are low, active high.
+    #[inline]
+    fn lines(&self) -> u8 {
+        let buttons = self.buttons[self.player];
+        let mut lines = 0x00;
+        if (self.select & 0x10) == 0 {
+            lines |= buttons & 0x0F;
+        }
+        if (self.select & 0x20) == 0 {
+            lines |= buttons >> 4;
+        }
+        lines
+    }
+
+    #[inline]
+    pub fn joyp(&self) -> u8 {
+        if self.select == 0x30 && self.players > 1 {
+            return 0xF0 | (0x0F - self.player as u8);
+        }
+        0xC0 | self.select | (!self.lines() & 0x0F)
+    }
+
+    pub fn write_joyp(&mut self, value: u8) {
+        if (value & !self.select & 0x20) != 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+        self.select = value & 0x30;
+    }
+
+    /// The first player's buttons held, as a mask of `RIGHT`, `A` and the rest.
+    #[inline]
+    pub fn buttons(&self) -> u8 {
+        self.buttons[0]
+    }
+
+    /// Changes the first player's buttons, returning whether that requests the joypad interrupt.
+    pub fn set_buttons(&mut self, buttons: u8) -> bool {
+        self.set_player_buttons(0, buttons)
+    }
+
+    /// Changes the buttons held on one of the four joypads.
+    pub fn set_player_buttons(&mut self, player: usize, buttons: u8) -> bool {
+        let before = self.lines();
+        self.buttons[player & 0x03] = buttons;
+        (self.lines() & !before) != 0
+    }
+
+    #[inline]
+    pub fn players(&self) -> usize {
+        self.players
+    }
+
+    /// Sets how many joypads are polled in turn: 1, 2 or 4. The first joypad is selected.
+    pub fn set_players(&mut self, players: usize) {
+        self.players = players.clamp(1, 4);
+        self.player = 0;
+    }
+}
+
+impl State for Joypad {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.select);
+        writer.bytes(&self.buttons);
+        writer.u8(self.players as u8);
+        writer.u8(self.player as u8);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.select = reader.u8()? & 0x30;
+        reader.bytes(&mut self.buttons)?;
+        self.players = (reader.u8()? as usize).clamp(1, 4);
+        self.player = reader.u8()? as usize % self.players;
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use super::*;
+
+#[test]
+fn matrix() {
+    let mut joypad = Joypad::default();
+    joypad.write_joyp(0x30);
+    assert_eq!(false, joypad.set_buttons(A | DOWN));
+    assert_eq!(0xFF, joypad.joyp());
+    joypad.write_joyp(0x20);
+    assert_eq!(0xE7, joypad.joyp());
+    joypad.write_joyp(0x10);
+    assert_eq!(0xDE, joypad.joyp());
+}
+
+#[test]
+fn interrupt() {
+    let mut joypad = Joypad::default();
+    joypad.write_joyp(0x10);
+    assert_eq!(false, joypad.set_buttons(UP));
+    assert_eq!(true, joypad.set_buttons(UP | START));
+    assert_eq!(false, joypad.set_buttons(START));
+    assert_eq!(false, joypad.set_buttons(0x00));
+    assert_eq!(0xDF, joypad.joyp());
+}
+
+#[test]
+fn multiplayer() {
+    let mut joypad = Joypad::default();
+    joypad.write_joyp(0x30);
+    assert_eq!(0xFF, joypad.joyp());
+    joypad.set_players(2);
+    joypad.set_player_buttons(1, START);
+    assert_eq!(0xFF, joypad.joyp());
+    joypad.write_joyp(0x10);
+    assert_eq!(0xDF, joypad.joyp());
+    joypad.write_joyp(0x30);
+    assert_eq!(0xFE, joypad.joyp());
+    joypad.write_joyp(0x10);
+    assert_eq!(0xD7, joypad.joyp());
+    joypad.write_joyp(0x30);
+    assert_eq!(0xFF, joypad.joyp());
+}
+
+#[test]
+fn names() {
+    assert_eq!(Some(A | START), parse("a,start"));
+    assert_eq!(Some(UP | LEFT | B | SELECT), parse("Up,left,B,select"));
+    assert_eq!(Some(RIGHT | DOWN), parse("right,down,right"));
+    assert_eq!(None, parse("a,turbo"));
+    assert_eq!(None, parse(""));
+}
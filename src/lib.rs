@@ -1,7 +1,23 @@
-mod cpu;
-mod mmu;
-mod ppu;
+#[cfg(test)]
+extern crate serde_json;
 
-use cpu::*;
-use mmu::*;
-use ppu::*;
\ No newline at end of file
+pub mod cpu;
+pub mod mmu;
+pub mod joypad;
+pub mod ppu;
+pub mod serial;
+pub mod timer;
+pub mod state;
+pub mod sgb;
+pub mod model;
+pub mod debugger;
+pub mod disasm;
+pub mod cheats;
+pub mod search;
+pub mod movie;
+pub mod capture;
+pub mod apu;
+pub mod gbs;
+
+pub use cpu::*;
+pub use mmu::*;
@@ -0,0 +1,258 @@
+use std::mem;
+use model::Model;
+use super::{Board, Corruption, Hardware, Mmu, Port, BIOS};
+
+/// What lives at each part of the address space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// 0x0000-0x7FFF, with the boot ROM over 0x0000-0x00FF until it's unmapped.
+    Rom,
+    /// 0x8000-0x9FFF, banked by VBK on the Game Boy Color.
+    Video,
+    /// 0xA000-0xBFFF, mapped by the cartridge.
+    CartRam,
+    /// 0xC000-0xCFFF.
+    Work,
+    /// 0xD000-0xDFFF, banked by SVBK on the Game Boy Color.
+    WorkBank,
+    /// 0xE000-0xFDFF, mirroring 0xC000-0xDDFF.
+    Echo,
+    /// 0xFE00-0xFE9F.
+    Oam,
+    /// 0xFEA0-0xFEFF, where nothing is connected.
+    Unusable,
+    /// 0xFF00-0xFF7F.
+    Io,
+    /// 0xFF80-0xFFFE.
+    High,
+    /// 0xFFFF, the IE register.
+    Interrupt,
+}
+
+/// The region an address belongs to.
+pub fn region(address: u16) -> Region {
+    match address {
+        0x0000 ..= 0x7FFF => Region::Rom,
+        0x8000 ..= 0x9FFF => Region::Video,
+        0xA000 ..= 0xBFFF => Region::CartRam,
+        0xC000 ..= 0xCFFF => Region::Work,
+        0xD000 ..= 0xDFFF => Region::WorkBank,
+        0xE000 ..= 0xFDFF => Region::Echo,
+        0xFE00 ..= 0xFE9F => Region::Oam,
+        0xFEA0 ..= 0xFEFF => Region::Unusable,
+        0xFF00 ..= 0xFF7F => Region::Io,
+        0xFF80 ..= 0xFFFE => Region::High,
+        _ => Region::Interrupt,
+    }
+}
+
+/// What a memory bank controller adds to the board: its ROM and RAM mapping and its control
+/// registers. Every cartridge gets the same bus from it, with the boot ROM, echo RAM, OAM DMA,
+/// VRAM DMA and the access rules handled once.
+pub trait Cartridge: Hardware {
+    /// Reads 0x0000-0x7FFF.
+    fn read_rom(&self, address: u16) -> u8;
+
+    /// Writes to 0x0000-0x7FFF, which go to the control registers.
+    fn write_rom(&mut self, address: u16, value: u8);
+
+    /// Reads 0xA000-0xBFFF, or `None` when no RAM is enabled there.
+    fn read_ram(&self, address: u16) -> Option<u8>;
+
+    fn write_ram(&mut self, address: u16, value: u8);
+
+    /// The ROM bank mapped at `address`.
+    fn rom_bank(&self, address: u16) -> usize;
+
+    /// The RAM bank mapped at 0xA000.
+    #[inline]
+    fn ram_bank(&self) -> usize {
+        0
+    }
+}
+
+/// Reads the bus without the OAM DMA restriction, as the DMA itself does.
+fn read_bus<C: Cartridge>(cartridge: &C, address: u16) -> u8 {
+    let board = cartridge.board();
+    match region(address) {
+        Region::Rom if board.booting() && address < 0x0100 => BIOS[address as usize],
+        Region::Rom => cartridge.read_rom(address),
+        Region::CartRam => cartridge.read_ram(address).unwrap_or(0xFF),
+        Region::Echo => read_bus(cartridge, address - 0x2000),
+        _ => board.read(address),
+    }
+}
+
+/// Advances the board by one M-cycle, copying the OAM DMA byte due.
+fn tick_board<C: Cartridge>(cartridge: &mut C) {
+    if let Some((source, index)) = cartridge.board_mut().tick() {
+        let value = read_bus(cartridge, source);
+        cartridge.board_mut().ram.oam[index] = value;
+    }
+}
+
+impl<C: Cartridge> Mmu for C {
+    fn read(&self, address: u16) -> u8 {
+        if self.board().dma.blocks(address) {
+            return 0xFF;
+        }
+        read_bus(self, address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match region(address) {
+            Region::Rom => self.write_rom(address, value),
+            Region::CartRam => self.write_ram(address, value),
+            Region::Echo => self.write(address - 0x2000, value),
+            _ => self.board_mut().write(address, value),
+        }
+    }
+
+    #[inline]
+    fn corrupt_oam(&mut self, address: u16, corruption: Corruption) {
+        self.board_mut().corrupt_oam(address, corruption)
+    }
+
+    #[inline]
+    fn io_read(&self, port: Port) -> u8 {
+        self.board().read_io(port as u16)
+    }
+
+    #[inline]
+    fn io_write(&mut self, port: Port, value: u8) {
+        self.board_mut().write_io(port as u16, value)
+    }
+
+    fn tick(&mut self) {
+        tick_board(self);
+        // The CPU is halted while each VRAM DMA block is copied, two bytes an M-cycle
+        while let Some((source, offset)) = self.board_mut().hdma.next() {
+            for i in 0..16 {
+                if (i & 0x01) == 0 {
+                    tick_board(self);
+                    self.board_mut().stalled += 4;
+                }
+                let value = read_bus(self, source.wrapping_add(i));
+                self.board_mut().write_hdma(offset + i, value);
+            }
+        }
+    }
+
+    #[inline]
+    fn take_stalled(&mut self) -> usize {
+        mem::take(&mut self.board_mut().stalled)
+    }
+
+    #[inline]
+    fn take_unsafe_lcd_off(&mut self) -> Option<u8> {
+        self.board_mut().take_unsafe_lcd_off()
+    }
+
+    fn bank(&self, address: u16) -> usize {
+        match region(address) {
+            Region::Rom => self.rom_bank(address),
+            Region::CartRam => self.ram_bank(),
+            _ => self.board().bank(address),
+        }
+    }
+}
+
+impl Board {
+    /// What reads of 0xFEA0-0xFEFF see. Monochrome models read 0x00, or 0xFF while OAM is in
+    /// use; a Game Boy Color (revision E) repeats the upper nibble of the address.
+    fn read_unusable(&self, address: u16) -> u8 {
+        match self.model {
+            Model::Cgb => ((address as u8) & 0xF0) | ((address as u8) >> 4),
+            Model::Agb => 0x00,
+            _ if self.oam_locked() => 0xFF,
+            _ => 0x00,
+        }
+    }
+
+    /// The DMG OAM bug. While the LCD scans OAM in mode 2, having an address in 0xFE00-0xFEFF
+    /// on the bus mixes the row of OAM being scanned with the row before it. The first row is
+    /// never affected, and Game Boy Color hardware doesn't have the bug.
+    fn corrupt_oam(&mut self, address: u16, corruption: Corruption) {
+        if self.model.color() || !(0xFE00..=0xFEFF).contains(&address) {
+            return;
+        }
+        let row = match self.ppu.oam_row() {
+            Some(row) if row > 0 => row,
+            _ => return,
+        };
+        let oam = &mut self.ram.oam;
+        let word = |oam: &[u8; 160], row: usize, index: usize| {
+            u16::from_le_bytes([oam[row * 8 + index * 2], oam[row * 8 + index * 2 + 1]])
+        };
+        let set_word = |oam: &mut [u8; 160], row: usize, value: u16| {
+            oam[row * 8..][..2].copy_from_slice(&value.to_le_bytes());
+        };
+        let copy_row = |oam: &mut [u8; 160], from: usize, to: usize, start: usize| {
+            for i in start..8 {
+                oam[to * 8 + i] = oam[from * 8 + i];
+            }
+        };
+        if corruption == Corruption::ReadIncrement && (4..19).contains(&row) {
+            let (a, b, c, d) = (word(oam, row - 2, 0), word(oam, row - 1, 0), word(oam, row, 0), word(oam, row - 1, 2));
+            set_word(oam, row - 1, (b & (a | c | d)) | (a & c & d));
+            copy_row(oam, row - 1, row, 0);
+            copy_row(oam, row - 1, row - 2, 0);
+        }
+        let (a, b, c) = (word(oam, row, 0), word(oam, row - 1, 0), word(oam, row - 1, 2));
+        let first = match corruption {
+            Corruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+            Corruption::Read | Corruption::ReadIncrement => b | (a & c),
+        };
+        set_word(oam, row, first);
+        copy_row(oam, row - 1, row, 2);
+    }
+
+    /// Whether the LCD is reading VRAM, in mode 3.
+    #[inline]
+    fn vram_locked(&self) -> bool {
+        self.ppu.mode() == 3
+    }
+
+    /// Whether the LCD is reading OAM, in modes 2 and 3.
+    #[inline]
+    fn oam_locked(&self) -> bool {
+        self.ppu.mode() >= 2
+    }
+
+    pub(super) fn read(&self, address: u16) -> u8 {
+        let offset = address as usize;
+        match region(address) {
+            Region::Video if self.vram_locked() => 0xFF,
+            Region::Video => self.ram.video[self.vram_bank()][offset - 0x8000],
+            Region::Work => self.ram.page[0][offset - 0xC000],
+            Region::WorkBank => self.ram.page[self.wram_bank()][offset - 0xD000],
+            Region::Oam if self.oam_locked() => 0xFF,
+            Region::Oam => self.ram.oam[offset - 0xFE00],
+            Region::Unusable => self.read_unusable(address),
+            Region::Io | Region::Interrupt => self.read_io(address),
+            Region::High => self.ram.high[offset - 0xFF80],
+            Region::Rom | Region::CartRam | Region::Echo => 0xFF,
+        }
+    }
+
+    pub(super) fn write(&mut self, address: u16, value: u8) {
+        let offset = address as usize;
+        match region(address) {
+            Region::Video if self.vram_locked() => {}
+            Region::Video => {
+                let bank = self.vram_bank();
+                self.ram.video[bank][offset - 0x8000] = value
+            }
+            Region::Work => self.ram.page[0][offset - 0xC000] = value,
+            Region::WorkBank => {
+                let bank = self.wram_bank();
+                self.ram.page[bank][offset - 0xD000] = value
+            }
+            Region::Oam if self.oam_locked() || self.dma.active => {}
+            Region::Oam => self.ram.oam[offset - 0xFE00] = value,
+            Region::Io | Region::Interrupt => self.write_io(address, value),
+            Region::High => self.ram.high[offset - 0xFF80] = value,
+            Region::Unusable | Region::Rom | Region::CartRam | Region::Echo => {}
+        }
+    }
+}
@@ -1,13 +1,17 @@
-use std::{mem};
+#[cfg(test)]
+mod tests;
+mod bus;
+
+pub use self::bus::{region, Cartridge, Region};
 
 pub enum Port {
     JOYP =  0xFF00,
     SB =    0xFF01,
     SC =    0xFF02,
-    DIV =   0xFF03,
-    TIMA =  0xFF04,
-    TMA =   0xFF05,
-    TAC =   0xFF06,
+    DIV =   0xFF04,
+    TIMA =  0xFF05,
+    TMA =   0xFF06,
+    TAC =   0xFF07,
     KEY1 =  0xFF4D,
     RP =    0xFF56,
 
@@ -69,11 +73,51 @@ pub enum Port {
     NR52 =  0xFF26,
 }
 
+/// The kinds of OAM corruption the DMG's OAM bug causes, named after the access that triggers
+/// them. 16-bit INC and DEC corrupt like writes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    Read,
+    Write,
+    /// A read in the same M-cycle as an increment or decrement, as `ld a,[hli]` and `pop` do.
+    ReadIncrement,
+}
+
 pub trait Mmu {
     fn read(&self, address: u16) -> u8;
 
+    /// Reads without the access being watched or recorded, for tools like the tracer that look
+    /// at memory without taking part in the program.
+    #[inline]
+    fn peek(&self, address: u16) -> u8 {
+        self.read(address)
+    }
+
     fn write(&mut self, address: u16, value: u8);
 
+    /// Advances everything on the bus besides the CPU by one M-cycle (4 clock cycles). The CPU
+    /// calls this before each of its memory accesses and for each internal cycle.
+    #[inline]
+    fn tick(&mut self) {}
+
+    /// The clock cycles the CPU has spent halted by a DMA since the last call, which `tick`
+    /// advances the rest of the machine through.
+    #[inline]
+    fn take_stalled(&mut self) -> usize {
+        0
+    }
+
+    /// The line the LCD was last turned off on outside VBlank, if it has been since the last call.
+    #[inline]
+    fn take_unsafe_lcd_off(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Called by the CPU when `address` is on the bus, before the read or write, so the DMG's OAM
+    /// bug can corrupt OAM when it points into 0xFE00-0xFEFF during mode 2.
+    #[inline]
+    fn corrupt_oam(&mut self, _address: u16, _corruption: Corruption) {}
+
     #[inline]
     fn io_read(&self, port: Port) -> u8 {
         self.read(port as u16)
@@ -83,8 +127,40 @@ pub trait Mmu {
     fn io_write(&mut self, port: Port, value: u8) {
         self.write(port as u16, value)
     }
+
+    /// The bank currently mapped at `address`, for debugger annotations.
+    #[inline]
+    fn bank(&self, address: u16) -> usize {
+        match address {
+            0x4000 ..= 0x7FFF | 0xD000 ..= 0xDFFF => 1,
+            _ => 0,
+        }
+    }
 }
 
+/// A complete machine behind the bus: the shared board devices, the cartridge ROM and all the
+/// state needed to save, restore and reset it.
+pub trait Hardware: Mmu + State {
+    fn board(&self) -> &Board;
+
+    fn board_mut(&mut self) -> &mut Board;
+
+    fn rom(&self) -> &[u8];
+
+    /// Returns everything but the ROM to its power-on state.
+    fn power_on(&mut self);
+}
+
+use std::{io, mem};
+use joypad::Joypad;
+use model::{Model, Palettes};
+use ppu::{Layers, Overlays, Ppu, Renderer, Viewer};
+use serial::Serial;
+use sgb::Sgb;
+use state::{Reader, State, Writer};
+use timer::Timer;
+use apu::Apu;
+
 static BIOS: &'static [u8; 256] = &[
     0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
     0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3, 0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
@@ -104,105 +180,782 @@ static BIOS: &'static [u8; 256] = &[
     0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86, 0x20, 0xFE, 0x3E, 0x01, 0xE0, 0x50
 ];
 
-struct Ram {
-    video: [[u8; 8192]; 2],
-    cart: [u8; 8192],
-    work: [u8; 4096],
-    page: [[u8; 8192]; 8],
-    oam: [u8; 160],
-    io: [u8; 256],
-    high: [u8; 128],
+pub(crate) struct Ram {
+    pub(crate) video: [[u8; 8192]; 2],
+    pub(crate) page: [[u8; 4096]; 8],
+    pub(crate) oam: [u8; 160],
+    pub(crate) io: [u8; 256],
+    pub(crate) high: [u8; 128],
 }
 
 impl Default for Ram {
     fn default() -> Self {
-        unsafe { mem::uninitialized() }
+        Ram {
+            video: [[0; 8192]; 2],
+            page: [[0; 4096]; 8],
+            oam: [0; 160],
+            io: [0; 256],
+            high: [0; 128],
+        }
+    }
+}
+
+impl State for Ram {
+    fn save(&self, writer: &mut Writer) {
+        for bank in &self.video {
+            writer.bytes(bank);
+        }
+        for page in &self.page {
+            writer.bytes(page);
+        }
+        writer.bytes(&self.oam);
+        writer.bytes(&self.io);
+        writer.bytes(&self.high);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        for bank in self.video.iter_mut() {
+            reader.bytes(bank)?;
+        }
+        for page in self.page.iter_mut() {
+            reader.bytes(page)?;
+        }
+        reader.bytes(&mut self.oam)?;
+        reader.bytes(&mut self.io)?;
+        reader.bytes(&mut self.high)
     }
 }
 
+/// OAM DMA. A write to 0xFF46 starts the transfer after a one M-cycle delay, after which one
+/// byte is copied per M-cycle for 160 M-cycles. While it runs, the CPU can only use HRAM and IO.
 #[derive(Default)]
-pub struct Mbc0 {
+struct Dma {
+    requested: Option<u16>,
+    starting: Option<u16>,
+    source: u16,
+    index: usize,
+    active: bool,
+}
+
+impl Dma {
+    #[inline]
+    fn request(&mut self, value: u8) {
+        self.requested = Some((value as u16) << 8);
+    }
+
+    #[inline]
+    fn blocks(&self, address: u16) -> bool {
+        self.active && address < 0xFF00
+    }
+
+    /// Advances the transfer, returning the source address and OAM index to copy this M-cycle.
+    fn tick(&mut self) -> Option<(u16, usize)> {
+        if let Some(source) = self.starting.take() {
+            self.source = source;
+            self.index = 0;
+            self.active = true;
+        }
+        self.starting = self.requested.take();
+        if !self.active {
+            return None;
+        }
+        let index = self.index;
+        self.index += 1;
+        if self.index == 160 {
+            self.active = false;
+        }
+        Some((self.source + index as u16, index))
+    }
+}
+
+impl State for Dma {
+    fn save(&self, writer: &mut Writer) {
+        for stage in &[self.requested, self.starting] {
+            writer.bool(stage.is_some());
+            writer.u16(stage.unwrap_or(0));
+        }
+        writer.u16(self.source);
+        writer.u8(self.index as u8);
+        writer.bool(self.active);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        for stage in &mut [&mut self.requested, &mut self.starting] {
+            let pending = reader.bool()?;
+            let source = reader.u16()?;
+            **stage = if pending { Some(source) } else { None };
+        }
+        self.source = reader.u16()?;
+        self.index = (reader.u8()? as usize).min(159);
+        self.active = reader.bool()?;
+        Ok(())
+    }
+}
+
+/// The Game Boy Color's VRAM DMA. A general-purpose transfer copies everything at once, while an
+/// HBlank transfer copies 16 bytes each time the LCD enters HBlank. The CPU is halted while a
+/// block is copied, for 8 M-cycles each.
+#[derive(Default)]
+struct Hdma {
+    source: u16,
+    destination: u16,
+    blocks: u8,
+    hblank: bool,
+    pending: u8,
+}
+
+impl Hdma {
+    #[inline]
+    fn active(&self) -> bool {
+        self.hblank && self.blocks > 0
+    }
+
+    fn hdma5(&self) -> u8 {
+        let remaining = self.blocks.wrapping_sub(1) & 0x7F;
+        if self.active() { remaining } else { 0x80 | remaining }
+    }
+
+    fn write_hdma5(&mut self, value: u8) {
+        if self.active() && (value & 0x80) == 0 {
+            self.hblank = false;
+            return;
+        }
+        self.blocks = (value & 0x7F) + 1;
+        self.hblank = (value & 0x80) != 0;
+        self.pending = if self.hblank { 0 } else { self.blocks };
+    }
+
+    #[inline]
+    fn hblank(&mut self) {
+        if self.active() {
+            self.pending = 1;
+        }
+    }
+
+    /// The next 16-byte block to copy, as source and VRAM offset.
+    fn next(&mut self) -> Option<(u16, u16)> {
+        if self.pending == 0 || self.blocks == 0 {
+            self.pending = 0;
+            return None;
+        }
+        let block = (self.source, self.destination & 0x1FF0);
+        self.source = self.source.wrapping_add(16);
+        self.destination = self.destination.wrapping_add(16);
+        self.pending -= 1;
+        self.blocks -= 1;
+        Some(block)
+    }
+}
+
+impl State for Hdma {
+    fn save(&self, writer: &mut Writer) {
+        writer.u16(self.source);
+        writer.u16(self.destination);
+        writer.u8(self.blocks);
+        writer.bool(self.hblank);
+        writer.u8(self.pending);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.source = reader.u16()?;
+        self.destination = reader.u16()?;
+        self.blocks = reader.u8()?.min(0x80);
+        self.hblank = reader.bool()?;
+        self.pending = reader.u8()?.min(self.blocks);
+        Ok(())
+    }
+}
+
+/// The devices on the board every cartridge shares: internal RAM, the joypad, the timer, the
+/// serial port, the LCD, the sound controller and OAM DMA, plus the Super Game Boy when playing in one.
+///
+/// The model decides what else exists: the Game Boy Color registers for VRAM and WRAM banks,
+/// palettes, VRAM DMA and KEY1 are only there in CGB mode, and read 0xFF otherwise. Speed
+/// switching isn't emulated, so KEY1 only keeps its prepare bit and always reports normal speed.
+#[derive(Default)]
+pub struct Board {
+    model: Model,
+    cgb_mode: bool,
     ram: Ram,
-    rom: Vec<u8>,
+    joypad: Joypad,
+    timer: Timer,
+    serial: Serial,
+    ppu: Ppu,
+    apu: Apu,
+    dma: Dma,
+    hdma: Hdma,
+    stalled: usize,
+    sgb: Option<Sgb>,
 }
 
-impl Mmu for Mbc0 {
-    fn read(&self, address: u16) -> u8 {
-        let address = address as usize;
+impl Board {
+    pub fn new(model: Model) -> Board {
+        Board {
+            model,
+            cgb_mode: model.color(),
+            ppu: Ppu::new(model),
+            apu: Apu::new(model),
+            sgb: if model == Model::Sgb { Some(Sgb::default()) } else { None },
+            ..Board::default()
+        }
+    }
+
+    /// Returns everything to its power-on state, keeping the renderer and the audio settings.
+    fn reset(&mut self) {
+        let (renderer, layers) = (self.ppu.renderer(), self.ppu.layers());
+        let mut apu = mem::take(&mut self.apu);
+        apu.reset();
+        *self = Board::new(self.model);
+        self.ppu.set_renderer(renderer);
+        self.ppu.set_layers(layers);
+        self.apu = apu;
+    }
+
+    #[inline]
+    pub fn renderer(&self) -> Renderer {
+        self.ppu.renderer()
+    }
+
+    /// Chooses between the scanline renderer and the slower, more exact pixel FIFO.
+    #[inline]
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.ppu.set_renderer(renderer)
+    }
+
+    #[inline]
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    #[inline]
+    pub fn sgb(&self) -> Option<&Sgb> {
+        self.sgb.as_ref()
+    }
+
+    /// Leaves the state the boot ROM hands over with: the boot ROM unmapped, the LCD and sound
+    /// on and, on a Game Boy Color, either CGB mode or DMG compatibility with the given palettes.
+    pub(crate) fn finish_boot(&mut self, cgb_mode: bool, palettes: Option<Palettes>) {
+        self.ram.io[Port::BIOS as usize - 0xFF00] = 0x01;
+        self.ram.io[Port::BGP as usize - 0xFF00] = 0xFC;
+        self.ppu.write_lcdc(0x91);
+        // Sound on and panned as the boot ROM leaves it
+        self.apu.write(0xFF26, 0x80);
+        self.apu.write(0xFF24, 0x77);
+        self.apu.write(0xFF25, 0xF3);
+        self.cgb_mode = self.model.color() && cgb_mode;
+        if let Some(palettes) = palettes {
+            self.ppu.set_compatibility(palettes);
+        }
+    }
+
+    #[inline]
+    fn vram_bank(&self) -> usize {
+        if self.cgb_mode { (self.ram.io[0x4F] as usize) & 0x01 } else { 0 }
+    }
+
+    /// The WRAM bank at 0xD000. Selecting bank 0 selects bank 1.
+    #[inline]
+    fn wram_bank(&self) -> usize {
+        if self.cgb_mode { ((self.ram.io[0x70] as usize) & 0x07).max(1) } else { 1 }
+    }
+
+    /// Sets the buttons held on one of the joypads a Super Game Boy polls.
+    pub fn set_player_buttons(&mut self, player: usize, buttons: u8) {
+        if self.joypad.set_player_buttons(player, buttons) {
+            self.ram.io[Port::IF as usize - 0xFF00] |= 0x10;
+        }
+    }
+
+    /// The last frame the LCD drew, 160x144 pixels as 0xAARRGGBB.
+    #[inline]
+    pub fn frame(&self) -> &[u32] {
+        self.ppu.frame()
+    }
+
+    /// Frames completed since power-on.
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.ppu.frames()
+    }
+
+    #[inline]
+    pub fn layers(&self) -> Layers {
+        self.ppu.layers()
+    }
+
+    /// Hides layers from the displayed frame, for debugging art. The emulated frame, which
+    /// movies and save states see, still has them.
+    #[inline]
+    pub fn set_layers(&mut self, layers: Layers) {
+        self.ppu.set_layers(layers)
+    }
+
+    /// The last frame for display, with hidden layers left out and `overlays` drawn on top.
+    #[inline]
+    pub fn compose(&self, overlays: Overlays) -> Vec<u32> {
+        self.ppu.compose(overlays)
+    }
+
+    /// Images of VRAM, OAM and the palettes as they are now.
+    #[inline]
+    pub fn viewer(&self) -> Viewer<'_> {
+        Viewer::new(&self.ppu, &self.ram)
+    }
+
+    /// The line the game last turned the LCD off on outside VBlank, which can damage a real
+    /// DMG, if it has since the last call. A Game Boy Color's screen is safe from it.
+    #[inline]
+    pub fn take_unsafe_lcd_off(&mut self) -> Option<u8> {
+        let line = self.ppu.take_unsafe_off();
+        if self.model.color() { None } else { line }
+    }
+
+    #[inline]
+    pub fn buttons(&self) -> u8 {
+        self.joypad.buttons()
+    }
+
+    /// Sets the buttons held, as a mask of the `joypad` constants.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        if self.joypad.set_buttons(buttons) {
+            self.ram.io[Port::IF as usize - 0xFF00] |= 0x10;
+        }
+    }
+
+    #[inline]
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// The sound controller, to take samples from and mute or solo its channels.
+    #[inline]
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    /// Every byte the serial port has shifted out.
+    #[inline]
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    fn read_io(&self, address: u16) -> u8 {
         match address {
-            0x0000 ... 0x7FFF => {
-                if self.io_read(Port::BIOS) == 0x00 && address < 0x0100 {
-                    BIOS[address]
-                } else {
-                    self.rom[address]
+            0xFF00 => { self.joypad.joyp() }
+            0xFF01 => { self.serial.sb() }
+            0xFF02 => { self.serial.sc() }
+            0xFF04 => { self.timer.div() }
+            0xFF05 => { self.timer.tima() }
+            0xFF06 => { self.timer.tma() }
+            0xFF07 => { self.timer.tac() }
+            0xFF0F => { self.ram.io[0x0F] | 0xE0 }
+            0xFF10 ..= 0xFF3F => { self.apu.read(address) }
+            0xFF40 => { self.ppu.lcdc() }
+            0xFF41 => { self.ppu.stat() }
+            0xFF44 => { self.ppu.ly() }
+            0xFF45 => { self.ppu.lyc() }
+            0xFF4D | 0xFF4F | 0xFF51 ..= 0xFF56 | 0xFF68 ..= 0xFF6B | 0xFF70 if !self.cgb_mode => { 0xFF }
+            0xFF4D => { 0x7E | (self.ram.io[0x4D] & 0x01) }
+            0xFF4F => { 0xFE | self.ram.io[0x4F] }
+            0xFF51 ..= 0xFF54 => { 0xFF }
+            0xFF55 => { self.hdma.hdma5() }
+            0xFF56 => { 0x3E | (self.ram.io[0x56] & 0xC1) }
+            0xFF68 => { self.ppu.bcps() }
+            0xFF69 => { self.ppu.bcpd() }
+            0xFF6A => { self.ppu.ocps() }
+            0xFF6B => { self.ppu.ocpd() }
+            0xFF70 => { 0xF8 | self.ram.io[0x70] }
+            _ => { self.ram.io[address as usize - 0xFF00] }
+        }
+    }
+
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF00 => {
+                self.joypad.write_joyp(value);
+                if let Some(ref mut sgb) = self.sgb {
+                    if let Some(command) = sgb.write_joyp(value) {
+                        sgb.execute(&command, &self.ram.video[0], self.ppu.lcdc());
+                        if sgb.players() != self.joypad.players() {
+                            self.joypad.set_players(sgb.players());
+                        }
+                    }
                 }
             }
-            0x8000 ... 0x9FFF => {
-                self.ram.video[(self.io_read(Port::VBK) as usize) & 0x01][address - 0x8000]
-            }
-            0xA000 ... 0xBFFF => {
-                self.ram.cart[address - 0xA000]
-            }
-            0xC000 ... 0xCFFF => {
-                self.ram.work[address - 0xC000]
-            }
-            0xD000 ... 0xDFFF => {
-                self.ram.page[(self.io_read(Port::SVBK) as usize) & 0x07][address - 0xD000]
-            }
-            0xE000 ... 0xFDFF => {
-                self.read((address - 0x1000) as u16)
-            }
-            0xFE00 ... 0xFE9F => {
-                self.ram.oam[address - 0xFE00]
+            0xFF01 => { self.serial.write_sb(value) }
+            0xFF02 => { self.serial.write_sc(value) }
+            0xFF04 => { self.timer.write_div() }
+            0xFF05 => { self.timer.write_tima(value) }
+            0xFF06 => { self.timer.write_tma(value) }
+            0xFF07 => { self.timer.write_tac(value) }
+            0xFF10 ..= 0xFF3F => { self.apu.write(address, value) }
+            0xFF40 => { self.ppu.write_lcdc(value) }
+            0xFF41 => {
+                if self.ppu.write_stat(value) {
+                    self.ram.io[Port::IF as usize - 0xFF00] |= 0x02;
+                }
             }
-            0xFF00 ... 0xFF7F | 0xFFFF => {
-                self.ram.io[address - 0xFF00]
+            0xFF44 => { }
+            0xFF45 => { self.ppu.write_lyc(value) }
+            0xFF46 => {
+                self.ram.io[address as usize - 0xFF00] = value;
+                self.dma.request(value);
             }
-            0xFF80 ... 0xFFFE => {
-                self.ram.high[address - 0xFF80]
+            0xFF4D | 0xFF4F | 0xFF51 ..= 0xFF56 | 0xFF68 ..= 0xFF6B | 0xFF70 if !self.cgb_mode => { }
+            0xFF4D => { self.ram.io[0x4D] = value & 0x01 }
+            0xFF4F => { self.ram.io[0x4F] = value & 0x01 }
+            0xFF51 => { self.hdma.source = (self.hdma.source & 0x00F0) | ((value as u16) << 8) }
+            0xFF52 => { self.hdma.source = (self.hdma.source & 0xFF00) | (value as u16 & 0xF0) }
+            0xFF53 => { self.hdma.destination = (self.hdma.destination & 0x00F0) | ((value as u16 & 0x1F) << 8) }
+            0xFF54 => { self.hdma.destination = (self.hdma.destination & 0x1F00) | (value as u16 & 0xF0) }
+            0xFF55 => { self.hdma.write_hdma5(value) }
+            0xFF56 => { self.ram.io[0x56] = value & 0xC1 }
+            0xFF68 => { self.ppu.write_bcps(value) }
+            0xFF69 => { self.ppu.write_bcpd(value) }
+            0xFF6A => { self.ppu.write_ocps(value) }
+            0xFF6B => { self.ppu.write_ocpd(value) }
+            0xFF70 => { self.ram.io[0x70] = value & 0x07 }
+            _ => { self.ram.io[address as usize - 0xFF00] = value }
+        }
+    }
+
+    /// Advances the devices by one M-cycle, returning the OAM DMA copy the cartridge performs.
+    /// VRAM DMA blocks are left in `hdma` for the cartridge to copy with `write_hdma`.
+    fn tick(&mut self) -> Option<(u16, usize)> {
+        let mode = self.ppu.stat() & 0x03;
+        let mut interrupts = self.ppu.tick(&self.ram);
+        if mode == 3 && (self.ppu.stat() & 0x03) == 0 {
+            self.hdma.hblank();
+        }
+        if (interrupts & 0x01) != 0 {
+            if let Some(ref mut sgb) = self.sgb {
+                sgb.compose(self.ppu.shades());
             }
-            _ => { unreachable!() }
         }
+        if self.timer.tick() {
+            interrupts |= 0x04;
+        }
+        if self.serial.tick() {
+            interrupts |= 0x08;
+        }
+        self.apu.tick(self.timer.div());
+        self.ram.io[Port::IF as usize - 0xFF00] |= interrupts;
+        self.dma.tick()
+    }
+
+    /// Copies one byte of a VRAM DMA block into the selected VRAM bank.
+    #[inline]
+    fn write_hdma(&mut self, offset: u16, value: u8) {
+        self.ram.video[self.vram_bank()][offset as usize] = value;
     }
 
-    fn write(&mut self, address: u16, value: u8) {
-        let address = address as usize;
+    #[inline]
+    fn booting(&self) -> bool {
+        self.ram.io[Port::BIOS as usize - 0xFF00] == 0x00
+    }
+
+    fn bank(&self, address: u16) -> usize {
         match address {
-            0x8000 ... 0x9FFF => {
-                self.ram.video[(self.io_read(Port::VBK) as usize) & 0x01][address - 0x8000] = value
-            }
-            0xA000 ... 0xBFFF => {
-                self.ram.cart[address - 0xA000] = value
-            }
-            0xC000 ... 0xCFFF => {
-                self.ram.work[address - 0xC000] = value
-            }
-            0xD000 ... 0xDFFF => {
-                self.ram.page[(self.io_read(Port::SVBK) as usize) & 0x07][address - 0xD000] = value
-            }
-            0xE000 ... 0xFDFF => {
-                self.write((address - 0x1000) as u16, value)
+            0x8000 ..= 0x9FFF => self.vram_bank(),
+            0xD000 ..= 0xDFFF => self.wram_bank(),
+            _ => 0,
+        }
+    }
+}
+
+impl State for Board {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.model as u8);
+        writer.bool(self.cgb_mode);
+        self.ram.save(writer);
+        self.joypad.save(writer);
+        self.timer.save(writer);
+        self.serial.save(writer);
+        self.ppu.save(writer);
+        self.apu.save(writer);
+        self.dma.save(writer);
+        self.hdma.save(writer);
+        if let Some(ref sgb) = self.sgb {
+            sgb.save(writer);
+        }
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        if reader.u8()? != self.model as u8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "save state is for a different model"));
+        }
+        self.cgb_mode = reader.bool()? && self.model.color();
+        self.ram.load(reader)?;
+        self.joypad.load(reader)?;
+        self.timer.load(reader)?;
+        self.serial.load(reader)?;
+        self.ppu.load(reader)?;
+        self.apu.load(reader)?;
+        self.dma.load(reader)?;
+        self.hdma.load(reader)?;
+        match self.sgb {
+            Some(ref mut sgb) => sgb.load(reader),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The size of the RAM a cartridge header declares at 0x0149.
+fn ram_size(rom: &[u8]) -> usize {
+    match rom.get(0x0149) {
+        Some(&0x01) => 0x800,
+        Some(&0x02) => 0x2000,
+        Some(&0x03) => 0x8000,
+        _ => 0,
+    }
+}
+
+/// A cartridge without a controller: 32KB of ROM and up to 8KB of RAM, always enabled.
+#[derive(Default)]
+pub struct Mbc0 {
+    board: Board,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl Mbc0 {
+    pub fn new(rom: Vec<u8>) -> Mbc0 {
+        Mbc0::with_model(rom, Model::Dmg)
+    }
+
+    pub fn with_model(rom: Vec<u8>, model: Model) -> Mbc0 {
+        Mbc0 {
+            board: Board::new(model),
+            ram: vec![0x00; ram_size(&rom).min(0x2000)],
+            rom,
+        }
+    }
+
+    /// Every byte the serial port has shifted out.
+    #[inline]
+    pub fn serial_output(&self) -> &[u8] {
+        self.board.serial.output()
+    }
+}
+
+impl Cartridge for Mbc0 {
+    #[inline]
+    fn read_rom(&self, address: u16) -> u8 {
+        self.rom.get(address as usize).cloned().unwrap_or(0xFF)
+    }
+
+    #[inline]
+    fn write_rom(&mut self, _: u16, _: u8) {}
+
+    #[inline]
+    fn read_ram(&self, address: u16) -> Option<u8> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        Some(self.ram[(address as usize - 0xA000) % self.ram.len()])
+    }
+
+    #[inline]
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram.is_empty() {
+            let len = self.ram.len();
+            self.ram[(address as usize - 0xA000) % len] = value;
+        }
+    }
+
+    #[inline]
+    fn rom_bank(&self, address: u16) -> usize {
+        if address < 0x4000 { 0 } else { 1 }
+    }
+}
+
+impl State for Mbc0 {
+    fn save(&self, writer: &mut Writer) {
+        self.board.save(writer);
+        writer.blob(&self.ram);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.board.load(reader)?;
+        let ram = reader.blob()?;
+        if ram.len() != self.ram.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cartridge RAM size mismatch"));
+        }
+        self.ram = ram;
+        Ok(())
+    }
+}
+
+impl Hardware for Mbc0 {
+    #[inline]
+    fn board(&self) -> &Board {
+        &self.board
+    }
+
+    #[inline]
+    fn board_mut(&mut self) -> &mut Board {
+        &mut self.board
+    }
+
+    #[inline]
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn power_on(&mut self) {
+        self.board.reset();
+        for byte in self.ram.iter_mut() {
+            *byte = 0x00;
+        }
+    }
+}
+
+/// MBC1: up to 2MB of ROM and 32KB of RAM. The 2-bit upper bank register selects either the
+/// upper ROM bank bits or, in mode 1, the RAM bank and the bank mapped at 0x0000.
+pub struct Mbc1 {
+    board: Board,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    lower: u8,
+    upper: u8,
+    mode: u8,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>) -> Mbc1 {
+        Mbc1::with_model(rom, Model::Dmg)
+    }
+
+    pub fn with_model(rom: Vec<u8>, model: Model) -> Mbc1 {
+        Mbc1 {
+            board: Board::new(model),
+            ram: vec![0x00; ram_size(&rom)],
+            rom,
+            ram_enabled: false,
+            lower: 1,
+            upper: 0,
+            mode: 0,
+        }
+    }
+
+    /// Every byte the serial port has shifted out.
+    #[inline]
+    pub fn serial_output(&self) -> &[u8] {
+        self.board.serial.output()
+    }
+
+    #[inline]
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank() * 0x2000 + address as usize - 0xA000) % self.ram.len())
+    }
+}
+
+impl Cartridge for Mbc1 {
+    #[inline]
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = self.rom_bank(address) * 0x4000 + (address as usize & 0x3FFF);
+        self.rom.get(offset).cloned().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000 ..= 0x1FFF => {
+                self.ram_enabled = (value & 0x0F) == 0x0A
             }
-            0xFE00 ... 0xFE9F => {
-                self.ram.oam[address - 0xFE00] = value
+            0x2000 ..= 0x3FFF => {
+                self.lower = (value & 0x1F).max(1)
             }
-            0xFF00 ... 0xFF7F | 0xFFFF => {
-                self.ram.io[address - 0xFF00] = value
+            0x4000 ..= 0x5FFF => {
+                self.upper = value & 0x03
             }
-            0xFF80 ... 0xFFFE => {
-                self.ram.high[address - 0xFF80] = value
+            _ => {
+                self.mode = value & 0x01
             }
-            _ => { }
         }
     }
 
     #[inline]
-    fn io_read(&self, port: Port) -> u8 {
-        self.ram.io[port as usize - 0xFF00]
+    fn read_ram(&self, address: u16) -> Option<u8> {
+        self.ram_address(address).map(|address| self.ram[address])
     }
 
     #[inline]
-    fn io_write(&mut self, port: Port, value: u8) {
-        self.ram.io[port as usize - 0xFF00] = value
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(address) = self.ram_address(address) {
+            self.ram[address] = value
+        }
+    }
+
+    #[inline]
+    fn rom_bank(&self, address: u16) -> usize {
+        let banks = (self.rom.len() / 0x4000).max(1);
+        let bank = match address {
+            0x0000 ..= 0x3FFF if self.mode == 0 => 0,
+            0x0000 ..= 0x3FFF => (self.upper as usize) << 5,
+            _ => ((self.upper as usize) << 5) | (self.lower as usize),
+        };
+        bank & (banks.next_power_of_two() - 1)
+    }
+
+    #[inline]
+    fn ram_bank(&self) -> usize {
+        if self.mode == 1 { self.upper as usize } else { 0 }
+    }
+}
+
+impl State for Mbc1 {
+    fn save(&self, writer: &mut Writer) {
+        self.board.save(writer);
+        writer.blob(&self.ram);
+        writer.bool(self.ram_enabled);
+        writer.u8(self.lower);
+        writer.u8(self.upper);
+        writer.u8(self.mode);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.board.load(reader)?;
+        let ram = reader.blob()?;
+        if ram.len() != self.ram.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cartridge RAM size mismatch"));
+        }
+        self.ram = ram;
+        self.ram_enabled = reader.bool()?;
+        self.lower = (reader.u8()? & 0x1F).max(1);
+        self.upper = reader.u8()? & 0x03;
+        self.mode = reader.u8()? & 0x01;
+        Ok(())
+    }
+}
+
+impl Hardware for Mbc1 {
+    #[inline]
+    fn board(&self) -> &Board {
+        &self.board
+    }
+
+    #[inline]
+    fn board_mut(&mut self) -> &mut Board {
+        &mut self.board
+    }
+
+    #[inline]
+    fn rom(&self) -> &[u8] {
+        &self.rom
     }
-}
\ No newline at end of file
+
+    fn power_on(&mut self) {
+        self.board.reset();
+        for byte in self.ram.iter_mut() {
+            *byte = 0x00;
+        }
+        self.ram_enabled = false;
+        self.lower = 1;
+        self.upper = 0;
+        self.mode = 0;
+    }
+}
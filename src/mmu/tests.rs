@@ -0,0 +1,338 @@
+use super::*;
+use model::Model;
+
+fn mbc0() -> Mbc0 {
+    let mut mmu = Mbc0::new(vec![0x00; 0x8000]);
+    mmu.write(Port::BIOS as u16, 0x01);
+    mmu
+}
+
+#[test]
+fn timer() {
+    let mut mmu = mbc0();
+    mmu.write(0xFF06, 0x42);
+    mmu.write(0xFF05, 0xFF);
+    mmu.write(0xFF07, 0x05);
+    assert_eq!(0xFD, mmu.read(0xFF07));
+    for _ in 0..5 {
+        mmu.tick();
+    }
+    assert_eq!(0x42, mmu.read(0xFF05));
+    assert_eq!(0x04, mmu.io_read(Port::IF) & 0x04);
+}
+
+#[test]
+fn dma() {
+    let mut mmu = mbc0();
+    for i in 0..160 {
+        mmu.write(0xC000 + i, i as u8);
+    }
+    mmu.write(0xFF46, 0xC0);
+    mmu.tick();
+    assert_eq!(0x01, mmu.read(0xC001));
+    mmu.tick();
+    assert_eq!(0xFF, mmu.read(0xC001));
+    assert_eq!(0xFF, mmu.read(0xFE00));
+    assert_eq!(0xC0, mmu.read(0xFF46));
+    for _ in 0..159 {
+        mmu.tick();
+    }
+    assert_eq!(0x01, mmu.read(0xFE01));
+    assert_eq!(0x9F, mmu.read(0xFE9F));
+}
+
+#[test]
+fn echo() {
+    let mut mmu = mbc0();
+    mmu.write(0xC123, 0x42);
+    assert_eq!(0x42, mmu.read(0xE123));
+}
+
+#[test]
+fn mbc1() {
+    let mut rom = vec![0x00; 0x4000 * 64];
+    for bank in 0..64 {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    rom[0x0149] = 0x03;
+    let mut mmu = Mbc1::new(rom);
+    mmu.write(Port::BIOS as u16, 0x01);
+    assert_eq!(0x01, mmu.read(0x4000));
+    mmu.write(0x2000, 0x00);
+    assert_eq!(0x01, mmu.read(0x4000));
+    mmu.write(0x2000, 0x05);
+    mmu.write(0x4000, 0x01);
+    assert_eq!(0x25, mmu.read(0x4000));
+    assert_eq!(0x00, mmu.read(0x0000));
+    mmu.write(0x6000, 0x01);
+    assert_eq!(0x20, mmu.read(0x0000));
+
+    assert_eq!(0xFF, mmu.read(0xA000));
+    mmu.write(0x0000, 0x0A);
+    mmu.write(0xA000, 0x42);
+    assert_eq!(0x42, mmu.read(0xA000));
+    mmu.write(0x4000, 0x02);
+    assert_eq!(0x00, mmu.read(0xA000));
+    mmu.write(0x4000, 0x01);
+    assert_eq!(0x42, mmu.read(0xA000));
+}
+
+#[test]
+fn serial() {
+    let mut mmu = mbc0();
+    mmu.write(0xFF01, b'P');
+    mmu.write(0xFF02, 0x81);
+    for _ in 0..256 {
+        mmu.tick();
+    }
+    assert_eq!(b"P", mmu.serial_output());
+    assert_eq!(0x08, mmu.io_read(Port::IF) & 0x08);
+}
+
+#[test]
+fn joypad() {
+    let mut mmu = mbc0();
+    mmu.write(0xFF00, 0x20);
+    assert_eq!(0xEF, mmu.read(0xFF00));
+    mmu.board_mut().set_buttons(::joypad::LEFT);
+    assert_eq!(0xED, mmu.read(0xFF00));
+    assert_eq!(0x10, mmu.io_read(Port::IF) & 0x10);
+}
+
+#[test]
+fn sgb() {
+    let mut mmu = Mbc0::with_model(vec![0x00; 0x8000], Model::Sgb);
+    mmu.write(Port::BIOS as u16, 0x01);
+    // MLT_REQ for two players, bit-banged through JOYP
+    let mut packet = [0; 16];
+    packet[0] = (::sgb::MLT_REQ << 3) | 0x01;
+    packet[1] = 0x01;
+    mmu.write(0xFF00, 0x00);
+    mmu.write(0xFF00, 0x30);
+    for i in 0..129 {
+        let bit = i < 128 && (packet[i / 8] >> (i % 8)) & 0x01 != 0;
+        mmu.write(0xFF00, if bit { 0x10 } else { 0x20 });
+        mmu.write(0xFF00, 0x30);
+    }
+    assert_eq!(2, mmu.board().sgb().unwrap().players());
+    assert_eq!(0xFF, mmu.read(0xFF00));
+    mmu.write(0xFF00, 0x10);
+    mmu.write(0xFF00, 0x30);
+    assert_eq!(0xFE, mmu.read(0xFF00));
+}
+
+#[test]
+fn banking() {
+    let mut mmu = Mbc0::with_model(vec![0x00; 0x8000], Model::Cgb);
+    mmu.write(0xC000, 0x10);
+    for bank in 0..8 {
+        mmu.write(Port::SVBK as u16, bank);
+        mmu.write(0xD000, 0x20 + bank);
+    }
+    // Bank 0 can't be selected at 0xD000, so its writes went to bank 1
+    mmu.write(Port::SVBK as u16, 0x00);
+    assert_eq!(0xF8, mmu.read(Port::SVBK as u16));
+    assert_eq!(0x21, mmu.read(0xD000));
+    mmu.write(Port::SVBK as u16, 0x07);
+    assert_eq!(0x27, mmu.read(0xD000));
+    assert_eq!(0x10, mmu.read(0xC000));
+    assert_eq!(0x27, mmu.read(0xF000));
+
+    mmu.write(Port::VBK as u16, 0x01);
+    mmu.write(0x8000, 0x42);
+    assert_eq!(0xFF, mmu.read(Port::VBK as u16));
+    mmu.write(Port::VBK as u16, 0x00);
+    assert_eq!(0x00, mmu.read(0x8000));
+}
+
+#[test]
+fn monochrome() {
+    let mut mmu = mbc0();
+    mmu.write(0xD000, 0x42);
+    for &port in &[Port::VBK as u16, Port::SVBK as u16, Port::KEY1 as u16, Port::HDMA5 as u16,
+                   Port::BCPS as u16, Port::BCPD as u16, Port::RP as u16] {
+        mmu.write(port, 0x01);
+        assert_eq!(0xFF, mmu.read(port));
+    }
+    mmu.write(0x8000, 0x24);
+    assert_eq!(0x42, mmu.read(0xD000));
+    assert_eq!(0x24, mmu.read(0x8000));
+}
+
+#[test]
+fn key1() {
+    let mut mmu = Mbc0::with_model(vec![0x00; 0x8000], Model::Cgb);
+    mmu.write(Port::BIOS as u16, 0x01);
+    assert_eq!(0x7E, mmu.read(Port::KEY1 as u16));
+    // The prepare bit sticks, but the speed never changes
+    mmu.write(Port::KEY1 as u16, 0xFF);
+    assert_eq!(0x7F, mmu.read(Port::KEY1 as u16));
+
+    let mut mmu = mbc0();
+    mmu.write(Port::KEY1 as u16, 0x01);
+    assert_eq!(0xFF, mmu.read(Port::KEY1 as u16));
+}
+
+#[test]
+fn palettes() {
+    let mut mmu = Mbc0::with_model(vec![0x00; 0x8000], Model::Cgb);
+    mmu.write(Port::BCPS as u16, 0x80 | 0x3E);
+    mmu.write(Port::BCPD as u16, 0x11);
+    mmu.write(Port::BCPD as u16, 0x22);
+    mmu.write(Port::BCPD as u16, 0x33);
+    assert_eq!(0xC1, mmu.read(Port::BCPS as u16));
+    assert_eq!(0xFF, mmu.read(Port::BCPD as u16));
+    mmu.write(Port::BCPS as u16, 0x3F);
+    assert_eq!(0x22, mmu.read(Port::BCPD as u16));
+    mmu.write(Port::BCPS as u16, 0x00);
+    assert_eq!(0x33, mmu.read(Port::BCPD as u16));
+    mmu.write(Port::OCPS as u16, 0x02);
+    mmu.write(Port::OCPD as u16, 0x44);
+    mmu.write(Port::OCPD as u16, 0x55);
+    assert_eq!(0x42, mmu.read(Port::OCPS as u16));
+    assert_eq!(0x55, mmu.read(Port::OCPD as u16));
+}
+
+#[test]
+fn hdma() {
+    let mut mmu = Mbc0::with_model(vec![0x00; 0x8000], Model::Cgb);
+    mmu.write(Port::BIOS as u16, 0x01);
+    for i in 0..64 {
+        mmu.write(0xC000 + i, i as u8);
+    }
+    mmu.write(Port::VBK as u16, 0x01);
+    mmu.write(Port::HDMA1 as u16, 0xC0);
+    mmu.write(Port::HDMA2 as u16, 0x00);
+    mmu.write(Port::HDMA3 as u16, 0x81);
+    mmu.write(Port::HDMA4 as u16, 0x00);
+    mmu.write(Port::HDMA5 as u16, 0x01);
+    mmu.tick();
+    assert_eq!(0xFF, mmu.read(Port::HDMA5 as u16));
+    assert_eq!(0x1F, mmu.read(0x811F));
+    // The CPU was halted for 8 M-cycles a block
+    assert_eq!(64, mmu.take_stalled());
+    assert_eq!(0, mmu.take_stalled());
+
+    // One block per HBlank
+    mmu.write(Port::HDMA3 as u16, 0x82);
+    mmu.write(Port::HDMA4 as u16, 0x00);
+    mmu.write(Port::LCDC as u16, 0x91);
+    mmu.write(Port::HDMA5 as u16, 0x81);
+    assert_eq!(0x01, mmu.read(Port::HDMA5 as u16));
+    for _ in 0..114 {
+        mmu.tick();
+    }
+    assert_eq!(32, mmu.take_stalled());
+    assert_eq!(0x00, mmu.read(Port::HDMA5 as u16));
+    assert_eq!(0x2F, mmu.read(0x820F));
+    assert_eq!(0x00, mmu.read(0x8210));
+    mmu.write(Port::HDMA5 as u16, 0x00);
+    assert_eq!(0x80, mmu.read(Port::HDMA5 as u16));
+}
+
+#[test]
+fn regions() {
+    assert_eq!(Region::Rom, region(0x7FFF));
+    assert_eq!(Region::Echo, region(0xFDFF));
+    assert_eq!(Region::Unusable, region(0xFEA0));
+    assert_eq!(Region::High, region(0xFFFE));
+    assert_eq!(Region::Interrupt, region(0xFFFF));
+
+    let mut mmu = mbc0();
+    mmu.write(0xFEA0, 0x42);
+    assert_eq!(0x00, mmu.read(0xFEA0));
+    mmu.write(Port::LCDC as u16, 0x91);
+    assert_eq!(0xFF, mmu.read(0xFEFF));
+    let mmu = Mbc0::with_model(vec![0x00; 0x8000], Model::Cgb);
+    assert_eq!(0xBB, mmu.read(0xFEB4));
+    let mmu = Mbc0::with_model(vec![0x00; 0x8000], Model::Agb);
+    assert_eq!(0x00, mmu.read(0xFEB4));
+}
+
+#[test]
+fn locked() {
+    let mut mmu = mbc0();
+    mmu.write(0x8000, 0x11);
+    mmu.write(0xFE00, 0x22);
+    mmu.write(Port::LCDC as u16, 0x91);
+    // Mode 2: OAM is in use
+    assert_eq!(0x11, mmu.read(0x8000));
+    assert_eq!(0xFF, mmu.read(0xFE00));
+    mmu.write(0xFE00, 0x33);
+    for _ in 0..20 {
+        mmu.tick();
+    }
+    // Mode 3: so is VRAM
+    assert_eq!(0x03, mmu.read(Port::STAT as u16) & 0x03);
+    assert_eq!(0xFF, mmu.read(0x8000));
+    mmu.write(0x8000, 0x44);
+    for _ in 0..50 {
+        mmu.tick();
+    }
+    assert_eq!(0x00, mmu.read(Port::STAT as u16) & 0x03);
+    assert_eq!(0x11, mmu.read(0x8000));
+    assert_eq!(0x22, mmu.read(0xFE00));
+}
+
+#[test]
+fn cart_ram() {
+    let mut mmu = mbc0();
+    mmu.write(0xA000, 0x42);
+    assert_eq!(0xFF, mmu.read(0xA000));
+    let mut rom = vec![0x00; 0x8000];
+    rom[0x0149] = 0x02;
+    let mut mmu = Mbc0::new(rom);
+    mmu.write(0xA000, 0x42);
+    assert_eq!(0x42, mmu.read(0xA000));
+}
+
+#[test]
+fn oam_bug() {
+    let oam: Vec<u8> = (0..160).map(|i| i as u8).collect();
+    let scanning = |model: Model| {
+        let mut mmu = Mbc0::with_model(vec![0x00; 0x8000], model);
+        mmu.board_mut().ram.oam.copy_from_slice(&oam);
+        mmu.write(Port::LCDC as u16, 0x91);
+        // Five M-cycles into mode 2, the LCD is reading row 5
+        for _ in 0..5 {
+            mmu.tick();
+        }
+        mmu
+    };
+
+    let mut mmu = scanning(Model::Dmg);
+    mmu.corrupt_oam(0xC000, Corruption::Write);
+    assert_eq!(&oam[..], &mmu.board().ram.oam[..]);
+    mmu.corrupt_oam(0xFE00, Corruption::Write);
+    let (a, b, c) = (0x2928u16, 0x2120u16, 0x2524u16);
+    assert_eq!((((a ^ c) & (b ^ c)) ^ c).to_le_bytes(), mmu.board().ram.oam[40..42]);
+    assert_eq!(oam[34..40], mmu.board().ram.oam[42..48]);
+    assert_eq!(oam[48..], mmu.board().ram.oam[48..]);
+
+    let mut mmu = scanning(Model::Dmg);
+    mmu.corrupt_oam(0xFE00, Corruption::Read);
+    assert_eq!((b | (a & c)).to_le_bytes(), mmu.board().ram.oam[40..42]);
+
+    let mut mmu = scanning(Model::Dmg);
+    mmu.corrupt_oam(0xFEFF, Corruption::ReadIncrement);
+    // The row before is copied over the two around it
+    assert_eq!(oam[32..40], mmu.board().ram.oam[24..32]);
+    assert_eq!(oam[34..40], mmu.board().ram.oam[42..48]);
+
+    let mut mmu = scanning(Model::Cgb);
+    mmu.corrupt_oam(0xFE00, Corruption::Write);
+    assert_eq!(&oam[..], &mmu.board().ram.oam[..]);
+}
+
+#[test]
+fn stat() {
+    let mut mmu = mbc0();
+    mmu.write(Port::LYC as u16, 0x90);
+    mmu.write(Port::LCDC as u16, 0x91);
+    mmu.write(Port::STAT as u16, 0x20);
+    assert_eq!(0x00, mmu.io_read(Port::IF) & 0x02);
+    for _ in 0..114 {
+        mmu.tick();
+    }
+    assert_eq!(0x02, mmu.io_read(Port::IF) & 0x02);
+}
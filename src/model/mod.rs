@@ -0,0 +1,145 @@
+#[cfg(test)]
+mod tests;
+
+use cpu::{Cpu, Registers};
+use joypad;
+use mmu::Hardware;
+
+/// The Game Boy being emulated. It decides which registers exist, how the screen is colored and
+/// how the machine boots.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    #[default]
+    Dmg,
+    /// Game Boy Pocket.
+    Mgb,
+    /// Super Game Boy, with command packets, palettes and the border.
+    Sgb,
+    /// Game Boy Color.
+    Cgb,
+    /// Game Boy Advance, running Game Boy Color software.
+    Agb,
+}
+
+impl Model {
+    pub fn parse(name: &str) -> Option<Model> {
+        match name.to_lowercase().as_str() {
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "cgb" => Some(Model::Cgb),
+            "agb" => Some(Model::Agb),
+            _ => None,
+        }
+    }
+
+    /// The model saved as `model as u8`, as in save states and movies.
+    pub fn from_byte(byte: u8) -> Option<Model> {
+        [Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb, Model::Agb].iter().cloned()
+            .find(|&model| model as u8 == byte)
+    }
+
+    /// The model a ROM is best played on: a Game Boy Color for carts with the CGB flag at
+    /// 0x0143, otherwise the original Game Boy.
+    pub fn preferred(rom: &[u8]) -> Model {
+        match rom.get(0x0143) {
+            Some(&flag) if (flag & 0x80) != 0 => Model::Cgb,
+            _ => Model::Dmg,
+        }
+    }
+
+    /// Whether this is Game Boy Color hardware.
+    #[inline]
+    pub fn color(self) -> bool {
+        self == Model::Cgb || self == Model::Agb
+    }
+
+    /// Whether a ROM runs in the Game Boy Color's own mode on this model, rather than the DMG
+    /// compatibility mode (or on a monochrome model at all).
+    #[inline]
+    pub fn cgb_mode(self, rom: &[u8]) -> bool {
+        self.color() && rom.get(0x0143).is_some_and(|&flag| (flag & 0x80) != 0)
+    }
+
+    /// The registers the boot ROM leaves behind, starting the cartridge at 0x0100.
+    pub fn boot_registers(self, rom: &[u8]) -> Registers {
+        let (af, bc, de, hl) = match self {
+            Model::Dmg => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Cgb if self.cgb_mode(rom) => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::Cgb => (0x1180, 0x0000, 0x0008, 0x007C),
+            Model::Agb if self.cgb_mode(rom) => (0x1100, 0x0100, 0xFF56, 0x000D),
+            Model::Agb => (0x1100, 0x0100, 0x0008, 0x007C),
+        };
+        let mut registers = Registers::default();
+        registers.set_af(af);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
+        registers.set_sp(0xFFFE);
+        registers.set_pc(0x0100);
+        registers
+    }
+}
+
+/// The DMG compatibility palettes the Game Boy Color boot ROM offers, as background, first
+/// object and second object palettes of BGR555 colors.
+pub type Palettes = [[u16; 4]; 3];
+
+const BROWN: [u16; 4] = [0x7FFF, 0x32BF, 0x00D0, 0x0000];
+const RED: [u16; 4] = [0x7FFF, 0x421F, 0x1CF2, 0x0000];
+const GREEN: [u16; 4] = [0x7FFF, 0x1BEF, 0x0200, 0x0000];
+const BLUE: [u16; 4] = [0x7FFF, 0x7E8C, 0x7C00, 0x0000];
+
+/// The palettes picked by holding a direction, optionally with A or B, while the boot ROM shows
+/// the logo, as `(buttons, palettes)`.
+const MANUAL: [(u8, Palettes); 12] = [
+    (joypad::UP, [BROWN, BROWN, BROWN]),
+    (joypad::UP | joypad::A, [RED, GREEN, BLUE]),
+    (joypad::UP | joypad::B, [[0x639F, 0x4279, 0x15B0, 0x04CB]; 3]),
+    (joypad::LEFT, [BLUE, RED, GREEN]),
+    (joypad::LEFT | joypad::A, [[0x7FFF, 0x6E31, 0x454A, 0x0000], RED, BROWN]),
+    (joypad::LEFT | joypad::B, [[0x7FFF, 0x5294, 0x294A, 0x0000]; 3]),
+    (joypad::DOWN, [[0x53FF, 0x4A5F, 0x7E52, 0x0000]; 3]),
+    (joypad::DOWN | joypad::A, [[0x7FFF, 0x03FF, 0x001F, 0x0000]; 3]),
+    (joypad::DOWN | joypad::B, [[0x7FFF, 0x03FF, 0x012F, 0x0000], BLUE, GREEN]),
+    (joypad::RIGHT, [[0x7FFF, 0x03EA, 0x011F, 0x0000]; 3]),
+    (joypad::RIGHT | joypad::A, [[0x7FFF, 0x1BEF, 0x6180, 0x0000], RED, RED]),
+    (joypad::RIGHT | joypad::B, [[0x0000, 0x4200, 0x037F, 0x7FFF]; 3]),
+];
+
+/// The palettes the boot ROM gives a monochrome game: the ones picked with the buttons held
+/// at boot, or else the green and red default. The per-title palettes the boot ROM assigns to
+/// Nintendo's own games aren't included, so those get the default too.
+pub fn compatibility_palettes(buttons: u8) -> Palettes {
+    let direction = [joypad::RIGHT, joypad::LEFT, joypad::UP, joypad::DOWN].iter()
+        .cloned()
+        .find(|&direction| (buttons & direction) != 0);
+    let combination = direction.map(|direction| {
+        direction | (buttons & joypad::A) | if (buttons & joypad::A) == 0 { buttons & joypad::B } else { 0 }
+    });
+    MANUAL.iter()
+        .find(|&&(buttons, _)| Some(buttons) == combination)
+        .map_or(MANUAL[10].1, |&(_, palettes)| palettes)
+}
+
+/// Starts the machine as its boot ROM would. The DMG runs the real boot ROM from 0x0000; the
+/// other models start at 0x0100 with the state their boot ROM leaves behind, including the
+/// compatibility palettes a Game Boy Color picks for monochrome games.
+pub fn boot<M: Hardware>(cpu: &mut Cpu, mmu: &mut M) {
+    let model = mmu.board().model();
+    if model == Model::Dmg {
+        cpu.set_registers(Registers::default());
+        return;
+    }
+    let cgb_mode = model.cgb_mode(mmu.rom());
+    let registers = model.boot_registers(mmu.rom());
+    let palettes = if model.color() && !cgb_mode {
+        Some(compatibility_palettes(mmu.board().buttons()))
+    } else {
+        None
+    };
+    mmu.board_mut().finish_boot(cgb_mode, palettes);
+    cpu.set_registers(registers);
+}
@@ -0,0 +1,58 @@
+use super::*;
+use mmu::{Mbc0, Mmu, Port};
+
+fn rom(flag: u8) -> Vec<u8> {
+    let mut rom = vec![0x00; 0x8000];
+    rom[0x0143] = flag;
+    rom
+}
+
+#[test]
+fn preferred() {
+    assert_eq!(Model::Dmg, Model::preferred(&rom(0x00)));
+    assert_eq!(Model::Cgb, Model::preferred(&rom(0x80)));
+    assert_eq!(Model::Cgb, Model::preferred(&rom(0xC0)));
+    assert_eq!(Some(Model::Agb), Model::parse("AGB"));
+    assert_eq!(None, Model::parse("gba"));
+}
+
+#[test]
+fn registers() {
+    let registers = Model::Cgb.boot_registers(&rom(0x80));
+    assert_eq!((0x1180, 0xFF56, 0x0100), (registers.af(), registers.de(), registers.pc()));
+    let registers = Model::Cgb.boot_registers(&rom(0x00));
+    assert_eq!((0x0008, 0x007C), (registers.de(), registers.hl()));
+    assert_eq!(0x0100, Model::Agb.boot_registers(&rom(0x80)).bc());
+    assert_eq!(0xFFB0, Model::Mgb.boot_registers(&rom(0x00)).af());
+}
+
+#[test]
+fn palettes() {
+    assert_eq!(MANUAL[10].1, compatibility_palettes(0));
+    assert_eq!([BLUE, RED, GREEN], compatibility_palettes(joypad::LEFT));
+    assert_eq!([RED, GREEN, BLUE], compatibility_palettes(joypad::UP | joypad::A | joypad::START));
+    // A wins when both are held
+    assert_eq!(MANUAL[7].1, compatibility_palettes(joypad::DOWN | joypad::A | joypad::B));
+}
+
+#[test]
+fn boot_compatibility() {
+    let mut cpu = Cpu::default();
+    let mut mmu = Mbc0::with_model(rom(0x00), Model::Cgb);
+    boot(&mut cpu, &mut mmu);
+    assert_eq!(0x0100, cpu.registers().pc());
+    assert_eq!(0x91, mmu.read(Port::LCDC as u16));
+    // Monochrome games can't see the color registers
+    assert_eq!(0xFF, mmu.read(Port::SVBK as u16));
+    assert_eq!(0xFF, mmu.read(Port::BCPD as u16));
+    assert_eq!(Model::Cgb, mmu.board().model());
+}
+
+#[test]
+fn boot_dmg() {
+    let mut cpu = Cpu::default();
+    let mut mmu = Mbc0::new(rom(0x80));
+    boot(&mut cpu, &mut mmu);
+    assert_eq!(0x0000, cpu.registers().pc());
+    assert_eq!(0x00, mmu.read(Port::BIOS as u16));
+}
@@ -0,0 +1,219 @@
+#[cfg(test)]
+mod tests;
+
+use std::{error, fmt, io};
+use std::io::{Read, Write};
+use cpu::Cpu;
+use mmu::Hardware;
+use model::{self, Model};
+use state::{self, Reader, Writer};
+
+/// Identifies a movie file, followed by a version byte.
+const MAGIC: &[u8; 8] = b"GB18MOVI";
+
+/// Version 2 records the model, and version 3 starts power-on movies with the sound on, as the
+/// boot ROM leaves it, so earlier recordings would desync.
+const VERSION: u8 = 3;
+
+/// Where a movie's input begins.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Start {
+    PowerOn,
+    /// A save state from `state::save`.
+    State(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The movie was recorded with another ROM.
+    Rom { expected: u64, actual: u64 },
+    /// The movie was recorded on another model.
+    Model { expected: Model, actual: Model },
+    /// The embedded save state didn't load.
+    State(io::Error),
+    /// The final frame differs from the recording.
+    Desync { expected: u64, actual: u64 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Rom { expected, actual } => {
+                write!(f, "movie was recorded with ROM {:016X}, not {:016X}", expected, actual)
+            }
+            Error::Model { expected, actual } => {
+                write!(f, "movie was recorded on {:?}, not {:?}", expected, actual)
+            }
+            Error::State(ref err) => write!(f, "movie start state: {}", err),
+            Error::Desync { expected, actual } => {
+                write!(f, "desync: final frame hash {:016X}, expected {:016X}", actual, expected)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The buttons held during every frame of a run, from power-on or a save state. As the machine
+/// is deterministic, replaying the input reproduces the run exactly; the hash of the final frame
+/// is kept to check that it did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    rom_hash: u64,
+    model: Model,
+    start: Start,
+    inputs: Vec<u8>,
+    frame_hash: Option<u64>,
+}
+
+impl Movie {
+    /// Resets the machine to power-on and starts recording.
+    pub fn power_on<M: Hardware>(cpu: &mut Cpu, mmu: &mut M) -> Movie {
+        mmu.power_on();
+        model::boot(cpu, mmu);
+        Movie {
+            rom_hash: state::hash(mmu.rom()),
+            model: mmu.board().model(),
+            start: Start::PowerOn,
+            inputs: Vec::new(),
+            frame_hash: None,
+        }
+    }
+
+    /// Starts recording from the machine's current state, which is embedded in the movie.
+    pub fn from_state<M: Hardware>(cpu: &Cpu, mmu: &M) -> Movie {
+        Movie {
+            rom_hash: state::hash(mmu.rom()),
+            model: mmu.board().model(),
+            start: Start::State(state::save(cpu, mmu)),
+            inputs: Vec::new(),
+            frame_hash: None,
+        }
+    }
+
+    #[inline]
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    #[inline]
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    #[inline]
+    pub fn start(&self) -> &Start {
+        &self.start
+    }
+
+    /// The buttons held in each frame, as masks of the `joypad` constants.
+    #[inline]
+    pub fn inputs(&self) -> &[u8] {
+        &self.inputs
+    }
+
+    #[inline]
+    pub fn frame_hash(&self) -> Option<u64> {
+        self.frame_hash
+    }
+
+    /// Runs one frame with `buttons` held and records it.
+    pub fn record<M: Hardware>(&mut self, cpu: &mut Cpu, mmu: &mut M, buttons: u8) {
+        mmu.board_mut().set_buttons(buttons);
+        cpu.frame(mmu);
+        self.inputs.push(buttons);
+    }
+
+    /// Ends the recording, keeping the hash of the final frame for playback to check.
+    pub fn finish<M: Hardware>(&mut self, mmu: &M) {
+        self.frame_hash = Some(state::hash_frame(mmu.board().frame()));
+    }
+
+    /// Puts the machine where the movie starts, then replays every frame of input and checks
+    /// the final frame against the recording.
+    pub fn play<M: Hardware>(&self, cpu: &mut Cpu, mmu: &mut M) -> Result<(), Error> {
+        self.play_with(cpu, mmu, |_| {})
+    }
+
+    /// Plays the movie, calling `each` after every frame, as for capturing it.
+    pub fn play_with<M: Hardware, F: FnMut(&mut M)>(&self, cpu: &mut Cpu, mmu: &mut M, mut each: F) -> Result<(), Error> {
+        let actual = state::hash(mmu.rom());
+        if actual != self.rom_hash {
+            return Err(Error::Rom { expected: self.rom_hash, actual });
+        }
+        let actual = mmu.board().model();
+        if actual != self.model {
+            return Err(Error::Model { expected: self.model, actual });
+        }
+        match self.start {
+            Start::PowerOn => {
+                mmu.power_on();
+                model::boot(cpu, mmu);
+            }
+            Start::State(ref data) => state::load(cpu, mmu, data).map_err(Error::State)?,
+        }
+        for &buttons in &self.inputs {
+            mmu.board_mut().set_buttons(buttons);
+            cpu.frame(mmu);
+            each(mmu);
+        }
+        if let Some(expected) = self.frame_hash {
+            let actual = state::hash_frame(mmu.board().frame());
+            if actual != expected {
+                return Err(Error::Desync { expected, actual });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut writer = Writer::new();
+        writer.bytes(MAGIC);
+        writer.u8(VERSION);
+        writer.u64(self.rom_hash);
+        writer.u8(self.model as u8);
+        match self.start {
+            Start::PowerOn => writer.u8(0),
+            Start::State(ref data) => {
+                writer.u8(1);
+                writer.blob(data);
+            }
+        }
+        writer.blob(&self.inputs);
+        writer.bool(self.frame_hash.is_some());
+        writer.u64(self.frame_hash.unwrap_or(0));
+        out.write_all(&writer.into_inner())
+    }
+
+    pub fn read<R: Read>(mut input: R) -> io::Result<Movie> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let mut reader = Reader::new(&data);
+        let mut magic = [0; 8];
+        reader.bytes(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a movie"));
+        }
+        if reader.u8()? != VERSION {
+            return Err(invalid("unsupported movie version"));
+        }
+        let rom_hash = reader.u64()?;
+        let model = Model::from_byte(reader.u8()?).ok_or_else(|| invalid("unknown model"))?;
+        let start = match reader.u8()? {
+            0 => Start::PowerOn,
+            1 => Start::State(reader.blob()?),
+            _ => return Err(invalid("unknown movie start")),
+        };
+        let inputs = reader.blob()?;
+        let finished = reader.bool()?;
+        let frame_hash = reader.u64()?;
+        Ok(Movie {
+            rom_hash,
+            model,
+            start,
+            inputs,
+            frame_hash: if finished { Some(frame_hash) } else { None },
+        })
+    }
+}
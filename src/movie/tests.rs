@@ -0,0 +1,99 @@
+use super::*;
+use joypad;
+use cpu::Registers;
+use mmu::{Mbc0, Mmu, Port};
+
+/// Shows the direction keys in BGP, so the frame depends on the input.
+fn machine() -> (Cpu, Mbc0) {
+    let mut rom = vec![0x00; 0x8000];
+    rom[0x0100..0x010C].copy_from_slice(&[
+        0x3E, 0x91,         // ld a, $91
+        0xE0, 0x40,         // ldh [$FF40], a
+        0x3E, 0x20,         // ld a, $20
+        0xE0, 0x00,         // ldh [$FF00], a
+        0xF0, 0x00,         // loop: ldh a, [$FF00]
+        0xE0, 0x47,         // ldh [$FF47], a
+    ]);
+    rom[0x010C..0x010E].copy_from_slice(&[0x18, 0xFA]);  // jr loop
+    let mut mmu = Mbc0::new(rom);
+    mmu.io_write(Port::BIOS, 0x01);
+    (Cpu::builder().pc(0x0100).sp(0xFFFE).build(), mmu)
+}
+
+fn record(inputs: &[u8]) -> Movie {
+    let (mut cpu, mut mmu) = machine();
+    let mut movie = Movie::from_state(&cpu, &mmu);
+    for &buttons in inputs {
+        movie.record(&mut cpu, &mut mmu, buttons);
+    }
+    movie.finish(&mmu);
+    movie
+}
+
+#[test]
+fn playback() {
+    let movie = record(&[0x00, joypad::RIGHT, joypad::RIGHT | joypad::A, 0x00, joypad::LEFT]);
+    let mut file = Vec::new();
+    movie.write(&mut file).unwrap();
+    let movie = Movie::read(&file[..]).unwrap();
+    assert_eq!(5, movie.inputs().len());
+
+    let (mut cpu, mut mmu) = machine();
+    // Playback starts from the embedded state, whatever the machine was doing
+    for _ in 0..3 {
+        cpu.frame(&mut mmu);
+    }
+    let mut frames = Vec::new();
+    movie.play_with(&mut cpu, &mut mmu, |mmu| frames.push(mmu.board().frames())).unwrap();
+    assert_eq!(vec![1, 2, 3, 4, 5], frames);
+    assert_eq!(0xED, mmu.read(0xFF47));
+}
+
+#[test]
+fn desync() {
+    let movie = record(&[0x00, joypad::RIGHT]);
+    let mut file = Vec::new();
+    movie.write(&mut file).unwrap();
+    // Change the input of the last frame
+    let last = file.len() - 10;
+    file[last] = 0x00;
+    let movie = Movie::read(&file[..]).unwrap();
+    let (mut cpu, mut mmu) = machine();
+    match movie.play(&mut cpu, &mut mmu) {
+        Err(Error::Desync { .. }) => {}
+        result => panic!("expected a desync, got {:?}", result),
+    }
+
+    let mut other = Mbc0::new(vec![0xFF; 0x8000]);
+    match movie.play(&mut cpu, &mut other) {
+        Err(Error::Rom { expected, .. }) => assert_eq!(movie.rom_hash(), expected),
+        result => panic!("expected a ROM mismatch, got {:?}", result),
+    }
+
+    let mut color = Mbc0::with_model(mmu.rom().to_vec(), Model::Cgb);
+    match movie.play(&mut cpu, &mut color) {
+        Err(Error::Model { expected: Model::Dmg, actual: Model::Cgb }) => {}
+        result => panic!("expected a model mismatch, got {:?}", result),
+    }
+}
+
+#[test]
+fn power_on() {
+    let (mut cpu, mut mmu) = machine();
+    cpu.frame(&mut mmu);
+    let mut movie = Movie::power_on(&mut cpu, &mut mmu);
+    assert_eq!(0, mmu.board().frames());
+    assert_eq!(Registers::default(), cpu.registers());
+    for _ in 0..3 {
+        movie.record(&mut cpu, &mut mmu, joypad::START);
+    }
+    movie.finish(&mmu);
+    let registers = cpu.registers();
+
+    let mut file = Vec::new();
+    movie.write(&mut file).unwrap();
+    assert_eq!(movie, Movie::read(&file[..]).unwrap());
+    movie.play(&mut cpu, &mut mmu).unwrap();
+    assert_eq!(registers, cpu.registers());
+    assert_eq!(Start::PowerOn, *movie.start());
+}
@@ -0,0 +1,305 @@
+use std::collections::VecDeque;
+use std::{io, mem};
+use mmu::Ram;
+use state::{Reader, State, Writer};
+use super::{Ppu, LINE_DOTS, SCREEN_WIDTH};
+use super::objects::{Object, ObjectPixel};
+
+/// The dot of each line at which mode 3 starts.
+const MODE3_DOT: usize = 80;
+
+/// Dots the fetcher spends before the first tile of a line, whose fetch is thrown away.
+const FIRST_FETCH_DOTS: u8 = 6;
+
+/// Dots an object fetch stalls the pixel pipeline for, once the background fetcher is ready.
+const OBJECT_DOTS: u8 = 6;
+
+/// The state of the pixel pipeline during a line: the background fetcher, both FIFOs and the
+/// objects the OAM scan found.
+#[derive(Default)]
+pub(super) struct Fifo {
+    x: u8,
+    discard: u8,
+    stall: u8,
+    step: u8,
+    fetch_x: u8,
+    tile: u8,
+    attributes: u8,
+    row: u8,
+    low: u8,
+    high: u8,
+    window: bool,
+    window_drawn: bool,
+    wy_triggered: bool,
+    background: VecDeque<(u8, u8)>,
+    objects: VecDeque<ObjectPixel>,
+    line: Vec<Object>,
+    next: usize,
+    fetching: Option<(usize, u8)>,
+}
+
+impl Fifo {
+    /// Forgets the WY match at the start of a frame.
+    #[inline]
+    pub(super) fn start_frame(&mut self) {
+        self.wy_triggered = false;
+    }
+
+    /// Leaves the current line alone, so only lines the FIFO starts itself get drawn.
+    #[inline]
+    pub(super) fn abandon_line(&mut self) {
+        self.x = SCREEN_WIDTH as u8;
+    }
+}
+
+impl Ppu {
+    /// Runs the pixel pipeline for the current dot of a visible line.
+    pub(super) fn fifo_dot(&mut self, ram: &Ram) {
+        if self.dot == MODE3_DOT {
+            self.start_fifo_line(ram);
+        }
+        // A line the FIFO didn't start, after switching renderers, is left to finish its timing
+        if self.dot < MODE3_DOT || self.dot >= self.mode3_end || self.fifo.x as usize >= SCREEN_WIDTH {
+            return;
+        }
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return;
+        }
+        self.check_window(ram);
+        self.check_objects();
+        if let Some((index, dots)) = self.fifo.fetching {
+            // Objects wait for the background fetcher to have a tile ready
+            if self.fifo.step < 6 || self.fifo.background.is_empty() {
+                self.fetcher_dot(ram);
+            } else if dots > 1 {
+                self.fifo.fetching = Some((index, dots - 1));
+            } else {
+                self.fifo.fetching = None;
+                self.fetch_object(ram, index);
+            }
+            return;
+        }
+        self.fetcher_dot(ram);
+        if let Some((color, attributes)) = self.fifo.background.pop_front() {
+            if self.fifo.discard > 0 {
+                self.fifo.discard -= 1;
+                return;
+            }
+            let object = self.fifo.objects.pop_front().unwrap_or_default();
+            self.push_pixel(ram, color, attributes, object);
+        }
+    }
+
+    /// Scans OAM for the objects on this line and resets the pipeline as mode 3 begins.
+    fn start_fifo_line(&mut self, ram: &Ram) {
+        let mut line = mem::take(&mut self.fifo.line);
+        self.scan_oam(ram, &mut line);
+        let fifo = &mut self.fifo;
+        fifo.line = line;
+        if self.ly == ram.io[0x4A] {
+            fifo.wy_triggered = true;
+        }
+        // The sort is stable, so objects sharing an X are fetched in OAM order
+        fifo.line.sort_by_key(|object| object.x);
+        fifo.next = 0;
+        fifo.fetching = None;
+        fifo.x = 0;
+        fifo.discard = ram.io[0x43] & 0x07;
+        fifo.stall = FIRST_FETCH_DOTS;
+        fifo.step = 0;
+        fifo.fetch_x = 0;
+        fifo.window = false;
+        fifo.window_drawn = false;
+        fifo.background.clear();
+        fifo.objects.clear();
+        self.mode3_end = LINE_DOTS;
+    }
+
+    /// Switches the fetcher to the window once the pipeline reaches WX.
+    fn check_window(&mut self, ram: &Ram) {
+        let wx = ram.io[0x4B];
+        let fifo = &mut self.fifo;
+        if fifo.window || !fifo.wy_triggered || (self.lcdc & 0x20) == 0 || wx > 166 {
+            return;
+        }
+        if (fifo.x == 0 && fifo.discard > 0) || (fifo.x as u16) + 7 < wx as u16 {
+            return;
+        }
+        fifo.window = true;
+        fifo.window_drawn = true;
+        fifo.discard = 7u8.saturating_sub(wx);
+        fifo.background.clear();
+        fifo.step = 0;
+        fifo.fetch_x = 0;
+    }
+
+    /// Starts fetching the next object once the pipeline reaches its left edge.
+    fn check_objects(&mut self) {
+        let fifo = &mut self.fifo;
+        if fifo.fetching.is_some() || fifo.discard > 0 || (self.lcdc & 0x02) == 0 {
+            return;
+        }
+        if let Some(object) = fifo.line.get(fifo.next) {
+            if object.x as u16 <= fifo.x as u16 + 8 {
+                fifo.fetching = Some((fifo.next, OBJECT_DOTS));
+                fifo.next += 1;
+            }
+        }
+    }
+
+    /// Advances the background fetcher by a dot: two dots each for the tile number and the
+    /// two data bytes, then pushing the row once the FIFO has emptied.
+    fn fetcher_dot(&mut self, ram: &Ram) {
+        match self.fifo.step {
+            1 => {
+                let (tile, attributes) = if self.fifo.window {
+                    self.map_entry(ram, (self.lcdc & 0x40) != 0, self.fifo.fetch_x.wrapping_mul(8), self.window_line)
+                } else {
+                    let (scy, scx) = (ram.io[0x42], ram.io[0x43]);
+                    let x = ((scx / 8).wrapping_add(self.fifo.fetch_x) & 0x1F) * 8;
+                    self.map_entry(ram, (self.lcdc & 0x08) != 0, x, scy.wrapping_add(self.ly))
+                };
+                let y = if self.fifo.window { self.window_line } else { ram.io[0x42].wrapping_add(self.ly) };
+                self.fifo.tile = tile;
+                self.fifo.attributes = attributes;
+                self.fifo.row = y % 8;
+            }
+            3 | 5 => {
+                // LCDC bit 4 is read for each byte
+                let bank = ((self.fifo.attributes >> 3) & 0x01) as usize;
+                let address = self.tile_address(self.fifo.tile, self.fifo.attributes, self.fifo.row as usize);
+                if self.fifo.step == 3 {
+                    self.fifo.low = ram.video[bank][address];
+                } else {
+                    self.fifo.high = ram.video[bank][address + 1];
+                }
+            }
+            _ => {}
+        }
+        let fifo = &mut self.fifo;
+        if fifo.step < 6 {
+            fifo.step += 1;
+        } else if fifo.background.is_empty() {
+            let flip = (fifo.attributes & 0x20) != 0;
+            for column in 0..8 {
+                let bit = if flip { column } else { 7 - column };
+                let color = (((fifo.high >> bit) & 0x01) << 1) | ((fifo.low >> bit) & 0x01);
+                fifo.background.push_back((color, fifo.attributes));
+            }
+            fifo.fetch_x = fifo.fetch_x.wrapping_add(1);
+            fifo.step = 0;
+        }
+    }
+
+    /// Reads an object's row and mixes it into the object FIFO. Pixels already there keep
+    /// their place, except that in CGB mode the object first in OAM wins.
+    fn fetch_object(&mut self, ram: &Ram, index: usize) {
+        let object = self.fifo.line[index];
+        let colors = self.object_row(ram, &object);
+        let left = object.x as i16 - 8 - self.fifo.x as i16;
+        let fifo = &mut self.fifo;
+        for (column, &color) in colors.iter().enumerate() {
+            let position = left + column as i16;
+            if position < 0 {
+                continue;
+            }
+            let pixel = ObjectPixel { color, attributes: object.attributes, index: object.index };
+            let position = position as usize;
+            while fifo.objects.len() <= position {
+                fifo.objects.push_back(ObjectPixel::default());
+            }
+            let current = fifo.objects[position];
+            if pixel.color != 0 && (current.color == 0 || (self.cgb_mode && pixel.index < current.index)) {
+                fifo.objects[position] = pixel;
+            }
+        }
+    }
+
+    /// Mixes a background and object pixel into the frame, ending mode 3 after the last one.
+    fn push_pixel(&mut self, ram: &Ram, color: u8, attributes: u8, object: ObjectPixel) {
+        let (x, window) = (self.fifo.x as usize, self.fifo.window);
+        if x >= SCREEN_WIDTH {
+            return;
+        }
+        self.draw(ram, x, color, attributes, window, object);
+        self.fifo.x += 1;
+        if self.fifo.x as usize == SCREEN_WIDTH {
+            self.mode3_end = self.dot + 1;
+            let window_drawn = self.fifo.window_drawn;
+            self.record_line(ram, window_drawn);
+            if self.fifo.window_drawn {
+                self.window_line += 1;
+            }
+        }
+    }
+}
+
+impl State for Fifo {
+    fn save(&self, writer: &mut Writer) {
+        for &value in &[self.x, self.discard, self.stall, self.step, self.fetch_x, self.tile, self.attributes,
+                        self.row, self.low, self.high] {
+            writer.u8(value);
+        }
+        writer.bool(self.window);
+        writer.bool(self.window_drawn);
+        writer.bool(self.wy_triggered);
+        writer.u8(self.background.len() as u8);
+        for &(color, attributes) in &self.background {
+            writer.u8(color);
+            writer.u8(attributes);
+        }
+        writer.u8(self.objects.len() as u8);
+        for pixel in &self.objects {
+            writer.u8(pixel.color);
+            writer.u8(pixel.attributes);
+            writer.u8(pixel.index);
+        }
+        writer.u8(self.line.len() as u8);
+        for object in &self.line {
+            writer.bytes(&[object.index, object.y, object.x, object.tile, object.attributes]);
+        }
+        writer.u8(self.next as u8);
+        writer.bool(self.fetching.is_some());
+        let (index, dots) = self.fetching.unwrap_or((0, 0));
+        writer.u8(index as u8);
+        writer.u8(dots);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid pixel FIFO state");
+        for value in &mut [&mut self.x, &mut self.discard, &mut self.stall, &mut self.step, &mut self.fetch_x,
+                           &mut self.tile, &mut self.attributes, &mut self.row, &mut self.low, &mut self.high] {
+            **value = reader.u8()?;
+        }
+        self.row &= 0x07;
+        self.window = reader.bool()?;
+        self.window_drawn = reader.bool()?;
+        self.wy_triggered = reader.bool()?;
+        self.background.clear();
+        for _ in 0..reader.u8()? {
+            let pixel = (reader.u8()? & 0x03, reader.u8()?);
+            self.background.push_back(pixel);
+        }
+        self.objects.clear();
+        for _ in 0..reader.u8()? {
+            let pixel = ObjectPixel { color: reader.u8()? & 0x03, attributes: reader.u8()?, index: reader.u8()? };
+            self.objects.push_back(pixel);
+        }
+        self.line.clear();
+        for _ in 0..reader.u8()? {
+            let mut entry = [0; 5];
+            reader.bytes(&mut entry)?;
+            self.line.push(Object { index: entry[0] % 40, y: entry[1], x: entry[2], tile: entry[3], attributes: entry[4] });
+        }
+        self.next = reader.u8()? as usize;
+        let fetching = reader.bool()?;
+        let (index, dots) = (reader.u8()? as usize, reader.u8()?);
+        self.fetching = if fetching { Some((index, dots)) } else { None };
+        if self.line.len() > 10 || self.next > self.line.len() || self.x as usize > SCREEN_WIDTH
+            || self.background.len() > 8 || self.objects.len() > 8 || self.fetching.is_some_and(|(index, _)| index >= self.line.len()) {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+}
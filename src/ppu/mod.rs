@@ -0,0 +1,515 @@
+#[cfg(test)]
+mod tests;
+mod fifo;
+mod objects;
+mod overlay;
+mod viewer;
+
+use std::io;
+use mmu::Ram;
+use model::{Model, Palettes};
+use state::{Reader, State, Writer};
+use self::fifo::Fifo;
+use self::overlay::Line;
+
+pub use self::overlay::{Layers, Overlays};
+pub use self::viewer::{Image, Sprite, Viewer};
+
+pub const SCREEN_WIDTH: usize = 160;
+
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// Dots (4 MHz clocks) per scanline.
+const LINE_DOTS: usize = 456;
+
+/// Scanlines per frame, including the 10 lines of VBlank.
+const FRAME_LINES: u8 = 154;
+
+/// The dot at which mode 3 ends and the finished line is drawn.
+const HBLANK_DOT: usize = 252;
+
+/// The four DMG shades as 0xAARRGGBB, lightest first.
+const SHADES: [u32; 4] = [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000];
+
+/// Converts a BGR555 color, as Game Boy Color and SNES palettes hold them, to 0xAARRGGBB.
+#[inline]
+pub fn rgb555(color: u16) -> u32 {
+    let expand = |value: u16| {
+        let value = (value & 0x1F) as u32;
+        (value << 3) | (value >> 2)
+    };
+    0xFF000000 | (expand(color) << 16) | (expand(color >> 5) << 8) | expand(color >> 10)
+}
+
+/// How lines are drawn.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    /// Each line at once, when mode 3 ends after a fixed 172 dots.
+    #[default]
+    Scanline,
+    /// Dot by dot through the pixel FIFOs, so mode 3 stretches with the fine scroll, the window
+    /// and objects, and registers written mid-line take effect at the pixel being drawn.
+    Fifo,
+}
+
+/// The LCD controller: LY, the STAT mode, the VBlank interrupt and a scanline renderer drawing
+/// the background, window and objects into a 160x144 0xAARRGGBB framebuffer.
+///
+/// On Game Boy Color hardware, colors come from the BCPS/BCPD palette memory. In CGB mode each
+/// tile map entry has attributes in VRAM bank 1 choosing its palette, tile bank and flips, and
+/// the background can't be turned off; monochrome games map BGP shades through palette 0.
+pub struct Ppu {
+    lcdc: u8,
+    stat: u8,
+    ly: u8,
+    lyc: u8,
+    dot: usize,
+    window_line: u8,
+    frames: u64,
+    frame: Vec<u32>,
+    shades: Vec<u8>,
+    color: bool,
+    cgb_mode: bool,
+    bcps: u8,
+    ocps: u8,
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+    stat_line: bool,
+    renderer: Renderer,
+    mode3_end: usize,
+    fifo: Fifo,
+    blank: bool,
+    unsafe_off: Option<u8>,
+    layers: Layers,
+    layered: Vec<u32>,
+    lines: Vec<Line>,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Ppu::new(Model::Dmg)
+    }
+}
+
+impl Ppu {
+    pub(crate) fn new(model: Model) -> Ppu {
+        Ppu {
+            lcdc: 0,
+            stat: 0,
+            ly: 0,
+            lyc: 0,
+            dot: 0,
+            window_line: 0,
+            frames: 0,
+            frame: vec![SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            shades: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            color: model.color(),
+            cgb_mode: model.color(),
+            bcps: 0,
+            ocps: 0,
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0xFF; 64],
+            stat_line: false,
+            renderer: Renderer::Scanline,
+            mode3_end: HBLANK_DOT,
+            fifo: Fifo::default(),
+            blank: false,
+            unsafe_off: None,
+            layers: Layers::default(),
+            layered: Vec::new(),
+            lines: vec![Line::default(); SCREEN_HEIGHT],
+        }
+    }
+
+    #[inline]
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    /// Switches renderers, starting with the next line.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        if renderer == self.renderer {
+            return;
+        }
+        self.renderer = renderer;
+        self.mode3_end = HBLANK_DOT;
+        self.fifo.abandon_line();
+    }
+
+    /// Leaves CGB mode for the DMG compatibility mode, with the palettes the boot ROM chose.
+    pub(crate) fn set_compatibility(&mut self, palettes: Palettes) {
+        self.cgb_mode = false;
+        let write = |memory: &mut [u8; 64], palette: usize, colors: &[u16; 4]| {
+            for (i, color) in colors.iter().enumerate() {
+                memory[palette * 8 + i * 2..][..2].copy_from_slice(&color.to_le_bytes());
+            }
+        };
+        write(&mut self.bg_palettes, 0, &palettes[0]);
+        write(&mut self.obj_palettes, 0, &palettes[1]);
+        write(&mut self.obj_palettes, 1, &palettes[2]);
+    }
+
+    #[inline]
+    fn enabled(&self) -> bool {
+        (self.lcdc & 0x80) != 0
+    }
+
+    #[inline]
+    pub(crate) fn mode(&self) -> u8 {
+        if !self.enabled() {
+            0
+        } else if self.ly >= 144 {
+            1
+        } else if self.dot < 80 {
+            2
+        } else if self.dot < self.mode3_end {
+            3
+        } else {
+            0
+        }
+    }
+
+    /// The row of OAM, of 8 bytes, the LCD is scanning in mode 2.
+    #[inline]
+    pub(crate) fn oam_row(&self) -> Option<usize> {
+        if self.mode() == 2 { Some(self.dot / 4) } else { None }
+    }
+
+    /// Advances the LCD by one M-cycle, returning the interrupt flags it requests.
+    pub(crate) fn tick(&mut self, ram: &Ram) -> u8 {
+        if !self.enabled() {
+            // Frames keep their pace with the LCD off so front-ends still see them end
+            self.dot += 4;
+            if self.dot >= LINE_DOTS * FRAME_LINES as usize {
+                self.dot = 0;
+                self.frames += 1;
+            }
+            return 0x00;
+        }
+        match self.renderer {
+            Renderer::Scanline => {
+                let before = self.dot;
+                self.dot += 4;
+                if self.ly < 144 && before < HBLANK_DOT && self.dot >= HBLANK_DOT {
+                    self.render_line(ram);
+                }
+            }
+            Renderer::Fifo => {
+                for _ in 0..4 {
+                    if self.ly < 144 {
+                        self.fifo_dot(ram);
+                    }
+                    self.dot += 1;
+                }
+            }
+        }
+        let mut interrupts = 0x00;
+        if self.dot >= LINE_DOTS {
+            self.dot -= LINE_DOTS;
+            self.ly = (self.ly + 1) % FRAME_LINES;
+            match self.ly {
+                0 => {
+                    self.window_line = 0;
+                    self.fifo.start_frame();
+                }
+                144 => {
+                    self.blank = false;
+                    self.frames += 1;
+                    interrupts |= 0x01;
+                }
+                _ => {}
+            }
+        }
+        let line = self.stat_sources(self.stat);
+        if line && !self.stat_line {
+            interrupts |= 0x02;
+        }
+        self.stat_line = line;
+        interrupts
+    }
+
+    /// The STAT interrupt line: the OR of the sources enabled in `stat`. The interrupt is only
+    /// requested as the line rises, so a source becoming active while another holds the line
+    /// high goes unnoticed.
+    fn stat_sources(&self, stat: u8) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        let mode = self.mode();
+        ((stat & 0x40) != 0 && self.ly() == self.lyc)
+            || ((stat & 0x08) != 0 && mode == 0)
+            || ((stat & 0x10) != 0 && mode == 1)
+            // The OAM source also fires as VBlank begins
+            || ((stat & 0x20) != 0 && (mode == 2 || (self.ly == 144 && self.dot < 4)))
+    }
+
+    /// The tile number at `x`, `y` in a tile map, and its attributes in CGB mode.
+    fn map_entry(&self, ram: &Ram, high_map: bool, x: u8, y: u8) -> (u8, u8) {
+        let map = if high_map { 0x1C00 } else { 0x1800 };
+        let entry = map + (y as usize / 8) * 32 + (x as usize / 8);
+        let attributes = if self.cgb_mode { ram.video[1][entry] } else { 0x00 };
+        (ram.video[0][entry], attributes)
+    }
+
+    /// The address of the low byte of a row of a background or window tile, after flipping.
+    fn tile_address(&self, tile: u8, attributes: u8, row: usize) -> usize {
+        let address = if (self.lcdc & 0x10) != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as isize) * 16) as usize
+        };
+        let row = if (attributes & 0x40) != 0 { 7 - row } else { row };
+        address + row * 2
+    }
+
+    /// The color index (0-3) of the background or window pixel at `x`, `y` in a tile map, and
+    /// the attributes of its tile in CGB mode.
+    fn tile_pixel(&self, ram: &Ram, high_map: bool, x: u8, y: u8) -> (u8, u8) {
+        let (tile, attributes) = self.map_entry(ram, high_map, x, y);
+        let video = &ram.video[((attributes >> 3) & 0x01) as usize];
+        let row = self.tile_address(tile, attributes, y as usize % 8);
+        let column = x % 8;
+        let bit = if (attributes & 0x20) != 0 { column } else { 7 - column };
+        ((((video[row + 1] >> bit) & 0x01) << 1) | ((video[row] >> bit) & 0x01), attributes)
+    }
+
+    #[inline]
+    fn palette_color(memory: &[u8; 64], palette: usize, color: u8) -> u32 {
+        let index = palette * 8 + color as usize * 2;
+        rgb555(u16::from_le_bytes([memory[index], memory[index + 1]]))
+    }
+
+    /// The shade and color of a background or window pixel, through BGP or the color palettes.
+    fn background_pixel(&self, ram: &Ram, color: u8, attributes: u8) -> (u8, u32) {
+        if self.cgb_mode {
+            return (color, Ppu::palette_color(&self.bg_palettes, (attributes & 0x07) as usize, color));
+        }
+        let shade = (ram.io[0x47] >> (color * 2)) & 0x03;
+        let pixel = if self.color { Ppu::palette_color(&self.bg_palettes, 0, shade) } else { SHADES[shade as usize] };
+        (shade, pixel)
+    }
+
+    /// The shade and color of an object pixel, through OBP0/OBP1 or the color palettes.
+    fn object_pixel(&self, ram: &Ram, color: u8, attributes: u8) -> (u8, u32) {
+        if self.cgb_mode {
+            return (color, Ppu::palette_color(&self.obj_palettes, (attributes & 0x07) as usize, color));
+        }
+        let palette = ((attributes >> 4) & 0x01) as usize;
+        let shade = (ram.io[0x48 + palette] >> (color * 2)) & 0x03;
+        let pixel = if self.color { Ppu::palette_color(&self.obj_palettes, palette, shade) } else { SHADES[shade as usize] };
+        (shade, pixel)
+    }
+
+    fn render_line(&mut self, ram: &Ram) {
+        let (scy, scx) = (ram.io[0x42], ram.io[0x43]);
+        let (wy, wx) = (ram.io[0x4A], ram.io[0x4B]);
+        let background = self.cgb_mode || (self.lcdc & 0x01) != 0;
+        let window = background && (self.lcdc & 0x20) != 0 && self.ly >= wy && wx <= 166;
+        let mut window_drawn = false;
+        let objects = self.object_line(ram);
+        for x in 0..SCREEN_WIDTH as u8 {
+            let in_window = window && x as u16 + 7 >= wx as u16;
+            let (color, attributes) = if !background {
+                (0, 0)
+            } else if in_window {
+                window_drawn = true;
+                self.tile_pixel(ram, (self.lcdc & 0x40) != 0, x + 7 - wx, self.window_line)
+            } else {
+                self.tile_pixel(ram, (self.lcdc & 0x08) != 0, scx.wrapping_add(x), scy.wrapping_add(self.ly))
+            };
+            self.draw(ram, x as usize, color, attributes, in_window, objects[x as usize]);
+        }
+        self.record_line(ram, window_drawn);
+        if window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    /// The last drawn frame, row by row.
+    #[inline]
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    /// The last drawn frame as DMG shades, 0 (lightest) to 3, for colorizing.
+    #[inline]
+    pub fn shades(&self) -> &[u8] {
+        &self.shades
+    }
+
+    /// Frames completed since power-on. A frame completes as VBlank starts, or every 70224
+    /// clocks while the LCD is off.
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    #[inline]
+    pub fn lcdc(&self) -> u8 {
+        self.lcdc
+    }
+
+    #[inline]
+    pub fn stat(&self) -> u8 {
+        let coincidence = if self.ly() == self.lyc { 0x04 } else { 0x00 };
+        0x80 | (self.stat & 0x78) | coincidence | self.mode()
+    }
+
+    /// LY. Line 153 reads as 0 after its first M-cycle, and compares with LYC that way too.
+    #[inline]
+    pub fn ly(&self) -> u8 {
+        if self.ly == FRAME_LINES - 1 && self.dot >= 4 { 0 } else { self.ly }
+    }
+
+    #[inline]
+    pub fn lyc(&self) -> u8 {
+        self.lyc
+    }
+
+    /// Turning the LCD on or off restarts LY and the line timing. The screen goes white while
+    /// it's off, and stays white through the first frame after it's turned back on.
+    pub fn write_lcdc(&mut self, value: u8) {
+        if ((value ^ self.lcdc) & 0x80) != 0 {
+            if (value & 0x80) == 0 {
+                if self.mode() != 1 {
+                    self.unsafe_off = Some(self.ly);
+                }
+                for pixel in self.frame.iter_mut().chain(self.layered.iter_mut()) {
+                    *pixel = SHADES[0];
+                }
+                for shade in self.shades.iter_mut() {
+                    *shade = 0;
+                }
+            }
+            self.ly = 0;
+            self.dot = 0;
+            self.window_line = 0;
+            self.stat_line = false;
+            self.fifo.start_frame();
+            if self.renderer == Renderer::Scanline {
+                self.mode3_end = HBLANK_DOT;
+            }
+            self.blank = (value & 0x80) != 0;
+        }
+        self.lcdc = value;
+    }
+
+    /// The line the LCD was last turned off on outside VBlank, if it was since the last call.
+    /// Doing that can damage a real DMG's screen.
+    #[inline]
+    pub fn take_unsafe_off(&mut self) -> Option<u8> {
+        self.unsafe_off.take()
+    }
+
+    /// Writes STAT, returning whether a STAT interrupt is requested. On monochrome models the
+    /// write briefly enables every source but OAM, so it requests one in HBlank, VBlank or
+    /// when LY matches LYC, unless the line was already high.
+    pub fn write_stat(&mut self, value: u8) -> bool {
+        let spurious = !self.color && !self.stat_line && self.stat_sources(0x58);
+        self.stat = value & 0x78;
+        self.stat_line |= spurious;
+        spurious
+    }
+
+    #[inline]
+    pub fn write_lyc(&mut self, value: u8) {
+        self.lyc = value;
+    }
+
+    #[inline]
+    pub fn bcps(&self) -> u8 {
+        0x40 | self.bcps
+    }
+
+    #[inline]
+    pub fn bcpd(&self) -> u8 {
+        self.bg_palettes[(self.bcps & 0x3F) as usize]
+    }
+
+    #[inline]
+    pub fn ocps(&self) -> u8 {
+        0x40 | self.ocps
+    }
+
+    #[inline]
+    pub fn ocpd(&self) -> u8 {
+        self.obj_palettes[(self.ocps & 0x3F) as usize]
+    }
+
+    #[inline]
+    pub fn write_bcps(&mut self, value: u8) {
+        self.bcps = value & 0xBF;
+    }
+
+    /// Writes the byte BCPS points at, moving on to the next when BCPS bit 7 is set.
+    pub fn write_bcpd(&mut self, value: u8) {
+        self.bg_palettes[(self.bcps & 0x3F) as usize] = value;
+        if (self.bcps & 0x80) != 0 {
+            self.bcps = 0x80 | (self.bcps.wrapping_add(1) & 0x3F);
+        }
+    }
+
+    #[inline]
+    pub fn write_ocps(&mut self, value: u8) {
+        self.ocps = value & 0xBF;
+    }
+
+    pub fn write_ocpd(&mut self, value: u8) {
+        self.obj_palettes[(self.ocps & 0x3F) as usize] = value;
+        if (self.ocps & 0x80) != 0 {
+            self.ocps = 0x80 | (self.ocps.wrapping_add(1) & 0x3F);
+        }
+    }
+}
+
+impl State for Ppu {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.lcdc);
+        writer.u8(self.stat);
+        writer.u8(self.ly);
+        writer.u8(self.lyc);
+        writer.u16(self.dot as u16);
+        writer.u8(self.window_line);
+        writer.u64(self.frames);
+        for &pixel in &self.frame {
+            writer.u32(pixel);
+        }
+        writer.bytes(&self.shades);
+        writer.bool(self.cgb_mode);
+        writer.u8(self.bcps);
+        writer.u8(self.ocps);
+        writer.bytes(&self.bg_palettes);
+        writer.bytes(&self.obj_palettes);
+        writer.bool(self.stat_line);
+        writer.u16(self.mode3_end as u16);
+        self.fifo.save(writer);
+        writer.bool(self.blank);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.lcdc = reader.u8()?;
+        self.stat = reader.u8()?;
+        self.ly = reader.u8()?;
+        self.lyc = reader.u8()?;
+        self.dot = reader.u16()? as usize;
+        self.window_line = reader.u8()?;
+        self.frames = reader.u64()?;
+        for pixel in self.frame.iter_mut() {
+            *pixel = reader.u32()?;
+        }
+        reader.bytes(&mut self.shades)?;
+        self.cgb_mode = reader.bool()? && self.color;
+        self.bcps = reader.u8()? & 0xBF;
+        self.ocps = reader.u8()? & 0xBF;
+        reader.bytes(&mut self.bg_palettes)?;
+        reader.bytes(&mut self.obj_palettes)?;
+        self.stat_line = reader.bool()?;
+        self.mode3_end = (reader.u16()? as usize).min(LINE_DOTS);
+        self.fifo.load(reader)?;
+        self.blank = reader.bool()?;
+        if self.renderer == Renderer::Scanline {
+            self.mode3_end = HBLANK_DOT;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use mmu::Ram;
+use super::{Ppu, SCREEN_WIDTH};
+
+/// Objects the OAM scan can find on a line.
+const LINE_OBJECTS: usize = 10;
+
+/// An object found on a line by the OAM scan.
+#[derive(Copy, Clone, Debug, Default)]
+pub(super) struct Object {
+    pub(super) index: u8,
+    pub(super) y: u8,
+    pub(super) x: u8,
+    pub(super) tile: u8,
+    pub(super) attributes: u8,
+}
+
+/// An object pixel, waiting to be mixed with the background.
+#[derive(Copy, Clone, Debug, Default)]
+pub(super) struct ObjectPixel {
+    pub(super) color: u8,
+    pub(super) attributes: u8,
+    pub(super) index: u8,
+}
+
+impl Ppu {
+    /// Finds the objects on this line: the first 10 in OAM whose rows cover it. Their X doesn't
+    /// matter, so objects off the sides still use up the limit.
+    pub(super) fn scan_oam(&self, ram: &Ram, objects: &mut Vec<Object>) {
+        let height = if (self.lcdc & 0x04) != 0 { 16 } else { 8 };
+        let line = self.ly as usize + 16;
+        objects.clear();
+        for (index, entry) in ram.oam.chunks(4).enumerate() {
+            let y = entry[0] as usize;
+            if line >= y && line < y + height && objects.len() < LINE_OBJECTS {
+                objects.push(Object { index: index as u8, y: entry[0], x: entry[1], tile: entry[2], attributes: entry[3] });
+            }
+        }
+    }
+
+    /// The color indices (0-3) of an object's row on this line, left to right after flipping.
+    /// 8x16 objects use the even tile on top and the odd one below.
+    pub(super) fn object_row(&self, ram: &Ram, object: &Object) -> [u8; 8] {
+        let tall = (self.lcdc & 0x04) != 0;
+        let height = if tall { 16 } else { 8 };
+        let mut row = (self.ly as usize + 16).wrapping_sub(object.y as usize) & (height - 1);
+        if (object.attributes & 0x40) != 0 {
+            row = height - 1 - row;
+        }
+        let tile = if tall { object.tile & 0xFE } else { object.tile } as usize;
+        let bank = if self.cgb_mode { ((object.attributes >> 3) & 0x01) as usize } else { 0 };
+        let address = tile * 16 + row * 2;
+        let (low, high) = (ram.video[bank][address], ram.video[bank][address + 1]);
+        let mut colors = [0; 8];
+        for (column, color) in colors.iter_mut().enumerate() {
+            let bit = if (object.attributes & 0x20) != 0 { column } else { 7 - column };
+            *color = (((high >> bit) & 0x01) << 1) | ((low >> bit) & 0x01);
+        }
+        colors
+    }
+
+    /// The object pixels of this line. Where objects overlap, the one with the lowest X wins,
+    /// then the one first in OAM; in CGB mode only the OAM order counts. A transparent pixel
+    /// lets the next object show through.
+    pub(super) fn object_line(&self, ram: &Ram) -> [ObjectPixel; SCREEN_WIDTH] {
+        let mut line = [ObjectPixel::default(); SCREEN_WIDTH];
+        if (self.lcdc & 0x02) == 0 {
+            return line;
+        }
+        let mut objects = Vec::with_capacity(LINE_OBJECTS);
+        self.scan_oam(ram, &mut objects);
+        if !self.cgb_mode {
+            // The sort is stable, so objects sharing an X keep their OAM order
+            objects.sort_by_key(|object| object.x);
+        }
+        for object in &objects {
+            for (column, &color) in self.object_row(ram, object).iter().enumerate() {
+                let x = object.x as usize + column;
+                if color == 0 || !(8..SCREEN_WIDTH + 8).contains(&x) || line[x - 8].color != 0 {
+                    continue;
+                }
+                line[x - 8] = ObjectPixel { color, attributes: object.attributes, index: object.index };
+            }
+        }
+        line
+    }
+
+    /// Mixes a background and object pixel, returning the shade and color shown. Objects with
+    /// attribute bit 7 set go behind background colors 1-3. In CGB mode the tile attributes
+    /// can put the background in front too, unless LCDC bit 0 takes priority away from it.
+    pub(super) fn mix(&self, ram: &Ram, color: u8, attributes: u8, object: ObjectPixel) -> (u8, u32) {
+        let background = self.cgb_mode || (self.lcdc & 0x01) != 0;
+        let color = if background { color } else { 0 };
+        let object_shown = object.color != 0 && (self.lcdc & 0x02) != 0 && if self.cgb_mode {
+            color == 0 || (self.lcdc & 0x01) == 0 || ((attributes | object.attributes) & 0x80) == 0
+        } else {
+            color == 0 || (object.attributes & 0x80) == 0
+        };
+        if object_shown {
+            self.object_pixel(ram, object.color, object.attributes)
+        } else {
+            self.background_pixel(ram, color, attributes)
+        }
+    }
+}
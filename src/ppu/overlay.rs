@@ -0,0 +1,172 @@
+use std::mem;
+use mmu::Ram;
+use super::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use super::objects::{Object, ObjectPixel};
+
+/// Object bounding boxes.
+pub(super) const OBJECT_COLOR: u32 = 0xFFFF00FF;
+
+/// The window's left and top edges.
+pub(super) const WINDOW_COLOR: u32 = 0xFF00FFFF;
+
+pub(super) const SCX_COLOR: u32 = 0xFFFFFF00;
+
+pub(super) const SCY_COLOR: u32 = 0xFF00FF00;
+
+/// The layers drawn into the displayed frame. Hidden layers are still fetched, so timing and
+/// the emulated frame don't change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Layers {
+    pub background: bool,
+    pub window: bool,
+    pub objects: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Layers { background: true, window: true, objects: true }
+    }
+}
+
+impl Layers {
+    /// Every layer but those named in a comma-separated list of `background`, `window` and
+    /// `objects`.
+    pub fn hiding(names: &str) -> Option<Layers> {
+        let mut layers = Layers::default();
+        for name in names.split(',') {
+            match name {
+                "background" => layers.background = false,
+                "window" => layers.window = false,
+                "objects" => layers.objects = false,
+                _ => return None,
+            }
+        }
+        Some(layers)
+    }
+}
+
+/// Debugging aids drawn over the displayed frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Overlays {
+    /// Boxes around the objects found on each line.
+    pub objects: bool,
+    /// The window's top and left edges, from WX and WY.
+    pub window: bool,
+    /// SCX and SCY of each line, plotted across it from 0 on the left to 255 on the right.
+    pub scroll: bool,
+}
+
+impl Overlays {
+    /// The overlays named in a comma-separated list of `objects`, `window` and `scroll`.
+    pub fn parse(names: &str) -> Option<Overlays> {
+        let mut overlays = Overlays::default();
+        for name in names.split(',') {
+            match name {
+                "objects" => overlays.objects = true,
+                "window" => overlays.window = true,
+                "scroll" => overlays.scroll = true,
+                _ => return None,
+            }
+        }
+        Some(overlays)
+    }
+}
+
+/// What the overlays need to know about a drawn line.
+#[derive(Clone, Default)]
+pub(super) struct Line {
+    scx: u8,
+    scy: u8,
+    wx: u8,
+    window: bool,
+    height: u8,
+    objects: Vec<Object>,
+}
+
+impl Ppu {
+    #[inline]
+    pub fn layers(&self) -> Layers {
+        self.layers
+    }
+
+    /// Hides or shows layers, starting with the next pixel drawn.
+    pub fn set_layers(&mut self, layers: Layers) {
+        if self.layered.is_empty() && layers != Layers::default() {
+            self.layered = self.frame.clone();
+        }
+        self.layers = layers;
+    }
+
+    /// Draws a pixel of the current line into the frame and, with layers hidden, the displayed
+    /// frame.
+    pub(super) fn draw(&mut self, ram: &Ram, x: usize, color: u8, attributes: u8, window: bool, object: ObjectPixel) {
+        if self.blank {
+            return;
+        }
+        let offset = self.ly as usize * SCREEN_WIDTH + x;
+        let (shade, pixel) = self.mix(ram, color, attributes, object);
+        self.shades[offset] = shade;
+        self.frame[offset] = pixel;
+        if self.layers != Layers::default() {
+            let shown = if window { self.layers.window } else { self.layers.background };
+            let color = if shown { color } else { 0 };
+            let object = if self.layers.objects { object } else { ObjectPixel::default() };
+            self.layered[offset] = self.mix(ram, color, attributes, object).1;
+        }
+    }
+
+    /// Notes what the overlays need as a line is finished.
+    pub(super) fn record_line(&mut self, ram: &Ram, window: bool) {
+        let ly = self.ly as usize;
+        let mut objects = mem::take(&mut self.lines[ly].objects);
+        self.scan_oam(ram, &mut objects);
+        self.lines[ly] = Line {
+            scx: ram.io[0x43],
+            scy: ram.io[0x42],
+            wx: ram.io[0x4B],
+            window,
+            height: if (self.lcdc & 0x04) != 0 { 16 } else { 8 },
+            objects,
+        };
+    }
+
+    /// The frame for display: the last drawn frame without the hidden layers, with `overlays`
+    /// drawn on top.
+    pub fn compose(&self, overlays: Overlays) -> Vec<u32> {
+        let mut frame = if self.layers == Layers::default() { self.frame.clone() } else { self.layered.clone() };
+        {
+            let mut plot = |x: usize, y: usize, color: u32| {
+                if x < SCREEN_WIDTH {
+                    frame[y * SCREEN_WIDTH + x] = color;
+                }
+            };
+            for (y, line) in self.lines.iter().enumerate().take(SCREEN_HEIGHT) {
+                if overlays.scroll {
+                    plot(line.scx as usize * SCREEN_WIDTH / 256, y, SCX_COLOR);
+                    plot(line.scy as usize * SCREEN_WIDTH / 256, y, SCY_COLOR);
+                }
+                if overlays.window && line.window {
+                    let left = line.wx.saturating_sub(7) as usize;
+                    let top = y == 0 || !self.lines[y - 1].window;
+                    let right = if top { SCREEN_WIDTH } else { left + 1 };
+                    for x in left..right {
+                        plot(x, y, WINDOW_COLOR);
+                    }
+                }
+                if overlays.objects {
+                    for object in &line.objects {
+                        let row = (y + 16).wrapping_sub(object.y as usize);
+                        let edge = row == 0 || row + 1 == line.height as usize;
+                        for column in 0..8 {
+                            let x = (object.x as usize + column).wrapping_sub(8);
+                            if edge || column == 0 || column == 7 {
+                                plot(x, y, OBJECT_COLOR);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        frame
+    }
+}
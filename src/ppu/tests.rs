@@ -0,0 +1,633 @@
+use super::*;
+use model::Model;
+use super::overlay::{OBJECT_COLOR, SCX_COLOR, SCY_COLOR, WINDOW_COLOR};
+use super::viewer::VIEWPORT_COLOR;
+
+/// Turns the LCD on and runs through the first frame, which isn't shown.
+fn turn_on(ppu: &mut Ppu, ram: &Ram, lcdc: u8) {
+    ppu.write_lcdc(lcdc);
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(ram);
+    }
+}
+
+#[test]
+fn frame() {
+    let ram = Ram::default();
+    let mut ppu = Ppu::default();
+    ppu.write_lcdc(0x91);
+    assert_eq!(0x82, ppu.stat() & 0x83);
+    for _ in 0..20 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(0x03, ppu.stat() & 0x03);
+    let mut vblanks = 0;
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        if ppu.tick(&ram) & 0x01 != 0 {
+            vblanks += 1;
+            assert_eq!(144, ppu.ly());
+            assert_eq!(0x01, ppu.stat() & 0x03);
+        }
+    }
+    assert_eq!(1, vblanks);
+    assert_eq!(0, ppu.ly());
+}
+
+#[test]
+fn disable() {
+    let ram = Ram::default();
+    let mut ppu = Ppu::default();
+    ppu.write_lcdc(0x80);
+    for _ in 0..(LINE_DOTS / 4) * 3 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(3, ppu.ly());
+    ppu.write_lcdc(0x00);
+    assert_eq!(0, ppu.ly());
+    assert_eq!(0x00, ppu.tick(&ram));
+}
+
+#[test]
+fn first_frame() {
+    let mut ram = Ram::default();
+    for byte in ram.video[0][..16].iter_mut() {
+        *byte = 0xFF;
+    }
+    ram.io[0x47] = 0xE4;
+    let mut ppu = Ppu::default();
+    ppu.write_lcdc(0x91);
+    let mut vblanks = 0;
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        vblanks += ppu.tick(&ram) & 0x01;
+    }
+    // The frame still ends with VBlank, but nothing is shown
+    assert_eq!(1, vblanks);
+    assert!(ppu.frame().iter().all(|&pixel| pixel == SHADES[0]));
+    for _ in 0..(LINE_DOTS / 4) * 144 {
+        ppu.tick(&ram);
+    }
+    assert!(ppu.frame().iter().all(|&pixel| pixel == SHADES[3]));
+
+    // Off in VBlank is safe, and the screen goes white
+    ppu.write_lcdc(0x11);
+    assert_eq!(None, ppu.take_unsafe_off());
+    assert_eq!(SHADES[0], ppu.frame()[0]);
+
+    ppu.write_lcdc(0x91);
+    for _ in 0..(LINE_DOTS / 4) * 3 {
+        ppu.tick(&ram);
+    }
+    ppu.write_lcdc(0x11);
+    assert_eq!(Some(3), ppu.take_unsafe_off());
+    assert_eq!(None, ppu.take_unsafe_off());
+}
+
+#[test]
+fn coincidence() {
+    let ram = Ram::default();
+    let mut ppu = Ppu::default();
+    ppu.write_lyc(0x01);
+    assert_eq!(0x00, ppu.stat() & 0x04);
+    ppu.write_lcdc(0x80);
+    for _ in 0..LINE_DOTS / 4 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(0x04, ppu.stat() & 0x04);
+}
+
+#[test]
+fn frames() {
+    let ram = Ram::default();
+    let mut ppu = Ppu::default();
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(&ram);
+    }
+    assert_eq!(1, ppu.frames());
+    ppu.write_lcdc(0x80);
+    for _ in 0..(LINE_DOTS / 4) * 144 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(2, ppu.frames());
+}
+
+#[test]
+fn background() {
+    let mut ram = Ram::default();
+    // Tile 1 is color 3 on its left half and color 1 on its right half
+    for row in 0..8 {
+        ram.video[0][16 + row * 2] = 0xFF;
+        ram.video[0][16 + row * 2 + 1] = 0xF0;
+    }
+    ram.video[0][0x1800] = 0x01;
+    ram.io[0x47] = 0xE4;
+    ram.io[0x43] = 0x02;
+    let mut ppu = Ppu::default();
+    turn_on(&mut ppu, &ram, 0x91);
+    for _ in 0..(LINE_DOTS / 4) * 144 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(&[SHADES[3], SHADES[3], SHADES[1], SHADES[1], SHADES[1], SHADES[1], SHADES[0]], &ppu.frame()[..7]);
+    assert_eq!(SHADES[0], ppu.frame()[8 * SCREEN_WIDTH]);
+
+    ppu.write_lcdc(0x90);
+    ppu.write_lcdc(0x00);
+    turn_on(&mut ppu, &ram, 0x90);
+    for _ in 0..LINE_DOTS / 4 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(SHADES[0], ppu.frame()[0]);
+}
+
+#[test]
+fn window() {
+    let mut ram = Ram::default();
+    for byte in ram.video[0][16..32].iter_mut() {
+        *byte = 0xFF;
+    }
+    ram.video[0][0x1C00] = 0x01;
+    ram.io[0x47] = 0xE4;
+    ram.io[0x4A] = 0x02;
+    ram.io[0x4B] = 0x07 + 4;
+    let mut ppu = Ppu::default();
+    turn_on(&mut ppu, &ram, 0xF1);
+    for _ in 0..(LINE_DOTS / 4) * 144 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(SHADES[0], ppu.frame()[SCREEN_WIDTH + 4]);
+    let line = 2 * SCREEN_WIDTH;
+    assert_eq!(&[SHADES[0], SHADES[3], SHADES[3]], &ppu.frame()[line + 3..line + 6]);
+    let line = 9 * SCREEN_WIDTH;
+    assert_eq!(SHADES[3], ppu.frame()[line + 11]);
+    assert_eq!(SHADES[0], ppu.frame()[line + 12]);
+    let line = 10 * SCREEN_WIDTH;
+    assert_eq!(SHADES[0], ppu.frame()[line + 4]);
+}
+
+#[test]
+fn color() {
+    let mut ram = Ram::default();
+    let mut ppu = Ppu::new(Model::Cgb);
+    // Tile 1 in bank 1 is solid color 1, drawn with palette 2 and flipped
+    ram.video[0][0x1800] = 0x01;
+    ram.video[1][0x1800] = 0x08 | 0x02 | 0x20;
+    for row in 0..8 {
+        ram.video[1][16 + row * 2] = 0xFF;
+    }
+    ppu.write_bcps(0x80 | 0x12);
+    ppu.write_bcpd(0x1F);
+    ppu.write_bcpd(0x00);
+    // LCDC bit 0 doesn't hide the background in CGB mode
+    turn_on(&mut ppu, &ram, 0x90);
+    for _ in 0..LINE_DOTS / 4 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(0xFFFF0000, ppu.frame()[0]);
+    assert_eq!(0xFFFFFFFF, ppu.frame()[8]);
+
+    ppu.set_compatibility([[0x7FFF, 0x001F, 0x03E0, 0x7C00]; 3]);
+    ram.io[0x47] = 0xE4;
+    ppu.write_lcdc(0x00);
+    turn_on(&mut ppu, &ram, 0x91);
+    for _ in 0..LINE_DOTS / 4 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(0xFFFFFFFF, ppu.frame()[0]);
+}
+
+/// Tile 1 is solid color 1, tile 2 solid color 3 and tile 3 solid color 2.
+fn object_tiles(ram: &mut Ram) {
+    for row in 0..8 {
+        ram.video[0][16 + row * 2] = 0xFF;
+        ram.video[0][32 + row * 2] = 0xFF;
+        ram.video[0][32 + row * 2 + 1] = 0xFF;
+        ram.video[0][48 + row * 2 + 1] = 0xFF;
+    }
+}
+
+#[test]
+fn objects() {
+    let mut ram = Ram::default();
+    object_tiles(&mut ram);
+    ram.io[0x47] = 0xE4;
+    ram.io[0x48] = 0xE4;
+    // On line 0, the object further left wins where they overlap, though it's later in OAM
+    ram.oam[..8].copy_from_slice(&[16, 20, 1, 0x00, 16, 16, 2, 0x00]);
+    // On line 8, ten objects off the left edge use up the limit
+    for entry in ram.oam[8..48].chunks_mut(4) {
+        entry.copy_from_slice(&[24, 0, 2, 0x00]);
+    }
+    ram.oam[48..52].copy_from_slice(&[24, 8, 2, 0x00]);
+    // On line 16, an object behind the background
+    ram.video[0][0x1800 + 2 * 32] = 0x01;
+    ram.oam[52..56].copy_from_slice(&[32, 12, 2, 0x80]);
+    // On line 32, 8x16 objects, the second flipped vertically
+    ram.oam[56..64].copy_from_slice(&[48, 40, 3, 0x00, 48, 48, 3, 0x40]);
+    let mut ppu = Ppu::default();
+    turn_on(&mut ppu, &ram, 0x93);
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(&ram);
+    }
+    let shades = ppu.shades();
+    assert_eq!(&[0, 3, 3, 3, 3, 3, 3, 3, 3, 1, 1, 1, 1, 0], &shades[7..21]);
+    assert_eq!(&[0, 0], &shades[8 * SCREEN_WIDTH + 7..][..2]);
+    let line = 16 * SCREEN_WIDTH;
+    assert_eq!(&[1, 1, 1, 3, 3, 3, 3, 0], &shades[line + 5..line + 13]);
+
+    // Without the background, objects behind it show
+    ppu.write_lcdc(0x92);
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(&ram);
+    }
+    assert_eq!(&[0, 3, 3], &ppu.shades()[line + 3..line + 6]);
+
+    ppu.write_lcdc(0x97);
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(&ram);
+    }
+    let shades = ppu.shades();
+    for &(line, left, right) in &[(32, 3, 2), (39, 3, 2), (40, 2, 3), (47, 2, 3)] {
+        assert_eq!(left, shades[line * SCREEN_WIDTH + 32]);
+        assert_eq!(right, shades[line * SCREEN_WIDTH + 40]);
+    }
+}
+
+#[test]
+fn color_objects() {
+    let (red, blue) = (0xFFFF0000, 0xFF0000FF);
+    let mut ram = Ram::default();
+    object_tiles(&mut ram);
+    let mut ppu = Ppu::new(Model::Cgb);
+    ppu.write_ocps(0x80 | 0x02);
+    for &byte in &[0x1F, 0x00, 0xFF, 0xFF, 0x00, 0x7C] {
+        ppu.write_ocpd(byte);
+    }
+    // The object first in OAM wins, whatever the X
+    ram.oam[..8].copy_from_slice(&[16, 20, 1, 0x00, 16, 16, 2, 0x00]);
+    // A background tile with priority over objects
+    ram.video[0][0x1800 + 2 * 32] = 0x01;
+    ram.video[1][0x1800 + 2 * 32] = 0x80;
+    ram.oam[8..12].copy_from_slice(&[32, 8, 2, 0x00]);
+    turn_on(&mut ppu, &ram, 0x93);
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(&ram);
+    }
+    assert_eq!(&[blue, red, red], &ppu.frame()[11..14]);
+    assert_eq!(0xFFFFFFFF, ppu.frame()[16 * SCREEN_WIDTH]);
+
+    // LCDC bit 0 takes priority away from the background
+    ppu.write_lcdc(0x92);
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(&ram);
+    }
+    assert_eq!(blue, ppu.frame()[16 * SCREEN_WIDTH]);
+}
+
+#[test]
+fn layers() {
+    assert_eq!(Some(Layers { background: false, window: true, objects: false }), Layers::hiding("objects,background"));
+    assert_eq!(None, Layers::hiding("sprites"));
+    let mut ram = Ram::default();
+    object_tiles(&mut ram);
+    ram.io[0x47] = 0xE4;
+    ram.io[0x48] = 0xE4;
+    // Background tile 1 on the left, the window from X 8 and an object over both
+    ram.video[0][0x1800] = 0x01;
+    ram.video[0][0x1C00] = 0x03;
+    ram.io[0x4B] = 0x07 + 8;
+    ram.oam[..4].copy_from_slice(&[16, 12, 2, 0x00]);
+    for &renderer in &[Renderer::Scanline, Renderer::Fifo] {
+        let mut ppu = Ppu::default();
+        ppu.set_renderer(renderer);
+        ppu.set_layers(Layers::hiding("objects").unwrap());
+        turn_on(&mut ppu, &ram, 0xF3);
+        for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+            ppu.tick(&ram);
+        }
+        // The emulated frame keeps every layer
+        assert_eq!(&[SHADES[3]; 4], &ppu.frame()[6..10]);
+        let frame = ppu.compose(Overlays::default());
+        assert_eq!(&[SHADES[1], SHADES[1], SHADES[2], SHADES[2]], &frame[6..10]);
+
+        ppu.set_layers(Layers::hiding("background,window").unwrap());
+        for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+            ppu.tick(&ram);
+        }
+        let frame = ppu.compose(Overlays::default());
+        assert_eq!(&[SHADES[0], SHADES[0], SHADES[3], SHADES[3]], &frame[2..6]);
+        assert_eq!(&[SHADES[3], SHADES[3], SHADES[0], SHADES[0]], &frame[10..14]);
+    }
+}
+
+#[test]
+fn overlays() {
+    assert_eq!(Some(Overlays { objects: true, window: false, scroll: true }), Overlays::parse("scroll,objects"));
+    assert_eq!(None, Overlays::parse("grid"));
+    let mut ram = Ram::default();
+    ram.io[0x4A] = 100;
+    ram.io[0x4B] = 0x07 + 40;
+    ram.io[0x43] = 128;
+    ram.oam[..4].copy_from_slice(&[16 + 20, 8 + 30, 0, 0x00]);
+    let mut ppu = Ppu::default();
+    turn_on(&mut ppu, &ram, 0xF3);
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(&ram);
+    }
+    assert!(ppu.compose(Overlays::default()) == ppu.frame());
+    let frame = ppu.compose(Overlays { objects: true, window: true, scroll: true });
+    let pixel = |x: usize, y: usize| frame[y * SCREEN_WIDTH + x];
+    // The object's box, from (30, 20) to (37, 27)
+    assert_eq!(&[OBJECT_COLOR; 8], &frame[20 * SCREEN_WIDTH + 30..][..8]);
+    assert_eq!(OBJECT_COLOR, pixel(30, 24));
+    assert_eq!(SHADES[0], pixel(31, 24));
+    assert_eq!(OBJECT_COLOR, pixel(37, 24));
+    assert_eq!(OBJECT_COLOR, pixel(34, 27));
+    // The window's top edge across the screen, then its left edge
+    assert_eq!(SHADES[0], pixel(40, 99));
+    assert_eq!(&[WINDOW_COLOR; 120], &frame[100 * SCREEN_WIDTH + 40..][..120]);
+    assert_eq!(WINDOW_COLOR, pixel(40, 143));
+    assert_eq!(SHADES[0], pixel(41, 143));
+    // SCX 128 halfway across, SCY 0 on the left
+    assert_eq!(SCX_COLOR, pixel(80, 0));
+    assert_eq!(SCY_COLOR, pixel(0, 0));
+}
+
+/// Where mode 3 of the first line ends with the pixel FIFO.
+fn mode3_end(ram: &Ram, lcdc: u8) -> usize {
+    let mut ppu = Ppu::default();
+    ppu.set_renderer(Renderer::Fifo);
+    ppu.write_lcdc(lcdc);
+    for _ in 0..LINE_DOTS / 4 - 1 {
+        ppu.tick(ram);
+    }
+    ppu.mode3_end
+}
+
+#[test]
+fn fifo_timing() {
+    let mut ram = Ram::default();
+    assert_eq!(80 + 172, mode3_end(&ram, 0x91));
+    ram.io[0x43] = 0x03;
+    assert_eq!(80 + 175, mode3_end(&ram, 0x91));
+    ram.io[0x43] = 0x00;
+    ram.io[0x4B] = 0x07 + 80;
+    assert_eq!(80 + 178, mode3_end(&ram, 0xB1));
+    ram.io[0x4B] = 0xFF;
+    // An object at X 40, then two more sharing X 80
+    for (i, &x) in [48, 88, 88].iter().enumerate() {
+        ram.oam[i * 4] = 16;
+        ram.oam[i * 4 + 1] = x;
+    }
+    assert_eq!(80 + 172, mode3_end(&ram, 0x91));
+    assert_eq!(80 + 172 + 32, mode3_end(&ram, 0x93));
+}
+
+#[test]
+fn fifo_switch_mid_line() {
+    let ram = Ram::default();
+    let mut ppu = Ppu::default();
+    ppu.set_renderer(Renderer::Fifo);
+    turn_on(&mut ppu, &ram, 0x91);
+    for _ in 0..LINE_DOTS / 4 {
+        ppu.tick(&ram);
+    }
+    // Back to the FIFO in mode 3 of a line the scanline renderer started
+    ppu.set_renderer(Renderer::Scanline);
+    for _ in 0..100 / 4 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(0x03, ppu.stat() & 0x03);
+    ppu.set_renderer(Renderer::Fifo);
+    for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+        ppu.tick(&ram);
+    }
+    assert_eq!(80 + 172, mode3_end(&ram, 0x91));
+    assert_eq!(LINE_DOTS, ppu.mode3_end);
+}
+
+#[test]
+fn fifo_matches_scanline() {
+    let mut ram = Ram::default();
+    for (i, byte) in ram.video[0][..0x1800].iter_mut().enumerate() {
+        *byte = (i * 7 + i / 13) as u8;
+    }
+    for (i, byte) in ram.video[0][0x1800..].iter_mut().enumerate() {
+        *byte = (i * 3) as u8;
+    }
+    ram.io[0x47] = 0xE4;
+    ram.io[0x42] = 0x05;
+    ram.io[0x43] = 0x0B;
+    ram.io[0x4A] = 0x30;
+    ram.io[0x4B] = 0x07 + 50;
+    ram.io[0x48] = 0xE4;
+    ram.io[0x49] = 0x1B;
+    for (i, entry) in ram.oam.chunks_mut(4).enumerate() {
+        entry.copy_from_slice(&[(i * 13 % 170) as u8, (i * 29 % 176) as u8, (i * 5) as u8, (i * 0x30) as u8 & 0xF0]);
+    }
+    let mut scanline = Ppu::default();
+    let mut fifo = Ppu::default();
+    fifo.set_renderer(Renderer::Fifo);
+    for ppu in &mut [&mut scanline, &mut fifo] {
+        turn_on(ppu, &ram, 0xF3);
+        for _ in 0..(LINE_DOTS / 4) * FRAME_LINES as usize {
+            ppu.tick(&ram);
+        }
+    }
+    assert!(scanline.frame() == fifo.frame());
+}
+
+#[test]
+fn fifo_mid_line() {
+    let mut ram = Ram::default();
+    for byte in ram.video[0][..16].iter_mut() {
+        *byte = 0xFF;
+    }
+    ram.io[0x47] = 0xE4;
+    let mut ppu = Ppu::default();
+    ppu.set_renderer(Renderer::Fifo);
+    turn_on(&mut ppu, &ram, 0x91);
+    // 40 pixels in
+    for _ in 0..(92 + 40) / 4 {
+        ppu.tick(&ram);
+    }
+    ram.io[0x47] = 0x00;
+    for _ in 0..LINE_DOTS / 4 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(SHADES[3], ppu.frame()[39]);
+    assert_eq!(SHADES[0], ppu.frame()[44]);
+}
+
+#[test]
+fn fifo_objects() {
+    let mut ram = Ram::default();
+    // Tile 1 is solid color 1, tile 2 solid color 3
+    for row in 0..8 {
+        ram.video[0][16 + row * 2] = 0xFF;
+        ram.video[0][32 + row * 2] = 0xFF;
+        ram.video[0][32 + row * 2 + 1] = 0xFF;
+    }
+    ram.video[0][0x1801] = 0x01;
+    ram.io[0x47] = 0xE4;
+    ram.io[0x48] = 0xE4;
+    ram.io[0x49] = 0x1B;
+    // One object over the background with priority to it, one over color 0 and one off the left
+    ram.oam[..12].copy_from_slice(&[16, 12, 2, 0x80, 16, 40, 2, 0x10, 16, 4, 2, 0x00]);
+    let mut ppu = Ppu::default();
+    ppu.set_renderer(Renderer::Fifo);
+    turn_on(&mut ppu, &ram, 0x93);
+    for _ in 0..LINE_DOTS / 4 {
+        ppu.tick(&ram);
+    }
+    let line = ppu.frame();
+    assert_eq!(SHADES[3], line[3]);
+    assert_eq!(SHADES[3], line[7]);
+    assert_eq!(SHADES[1], line[8]);
+    assert_eq!(SHADES[0], line[32]);
+    assert_eq!(SHADES[0], line[40]);
+}
+
+#[test]
+fn stat_interrupts() {
+    let ram = Ram::default();
+    let mut ppu = Ppu::default();
+    ppu.write_lcdc(0x91);
+    ppu.write_stat(0x08);
+    let mut requests = Vec::new();
+    for tick in 0..(LINE_DOTS / 4) * 3 {
+        if ppu.tick(&ram) & 0x02 != 0 {
+            requests.push(tick);
+        }
+    }
+    // Once per line, as HBlank starts
+    assert_eq!(vec![62, 176, 290], requests);
+
+    // HBlank holds the line high through the LYC match on the next line, so it is blocked
+    ppu.write_stat(0x48);
+    ppu.write_lyc(4);
+    let mut requests = 0;
+    for _ in 0..LINE_DOTS / 4 * 2 {
+        if ppu.tick(&ram) & 0x02 != 0 {
+            requests += 1;
+        }
+    }
+    assert_eq!(5, ppu.ly());
+    assert_eq!(1, requests);
+}
+
+#[test]
+fn spurious_stat() {
+    let ram = Ram::default();
+    let mut ppu = Ppu::default();
+    ppu.write_lcdc(0x91);
+    ppu.write_lyc(0x90);
+    assert_eq!(false, ppu.write_stat(0x00));
+    for _ in 0..70 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(0x00, ppu.stat() & 0x03);
+    assert_eq!(true, ppu.write_stat(0x00));
+    assert_eq!(0x00, ppu.tick(&ram));
+
+    let mut ppu = Ppu::new(Model::Cgb);
+    ppu.write_lcdc(0x91);
+    for _ in 0..70 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(false, ppu.write_stat(0x00));
+}
+
+#[test]
+fn line_153() {
+    let ram = Ram::default();
+    let mut ppu = Ppu::default();
+    ppu.write_lcdc(0x91);
+    ppu.write_lyc(0);
+    ppu.write_stat(0x40);
+    for _ in 0..(LINE_DOTS / 4) * 153 - 1 {
+        ppu.tick(&ram);
+    }
+    assert_eq!(152, ppu.ly());
+    assert_eq!(0x00, ppu.tick(&ram));
+    assert_eq!(153, ppu.ly());
+    // LY reads 0 from the second M-cycle, matching LYC a line early
+    assert_eq!(0x02, ppu.tick(&ram));
+    assert_eq!(0, ppu.ly());
+    assert_eq!(0x04, ppu.stat() & 0x04);
+}
+
+#[test]
+fn viewer() {
+    let mut ram = Ram::default();
+    object_tiles(&mut ram);
+    // Tile 383 has color 1 in its top-left corner, and tile 0 in bank 1 color 2
+    ram.video[0][383 * 16] = 0x80;
+    ram.video[1][1] = 0x80;
+    ram.video[0][0x1C00 + 33] = 0x02;
+    ram.io[0x47] = 0xE4;
+    ram.io[0x48] = 0xE4;
+    ram.io[0x42] = 200;
+    ram.io[0x43] = 120;
+    ram.oam[..8].copy_from_slice(&[16, 8, 1, 0x00, 100, 50, 2, 0xF0]);
+    let mut ppu = Ppu::default();
+    ppu.write_lcdc(0x91);
+    let viewer = Viewer::new(&ppu, &ram);
+
+    let tiles = viewer.tiles(0);
+    assert_eq!((128, 192), (tiles.width, tiles.height));
+    assert_eq!(SHADES[1], tiles.pixels[8]);
+    assert_eq!(SHADES[1], tiles.pixels[184 * 128 + 120]);
+    assert_eq!(SHADES[0], tiles.pixels[184 * 128 + 121]);
+    assert_eq!(SHADES[2], viewer.tiles(1).pixels[0]);
+
+    // Tile 2 at (8, 8) in the high map, under the viewport's corners, which wrap around
+    let map = viewer.tile_map(true);
+    assert_eq!((256, 256), (map.width, map.height));
+    assert_eq!(SHADES[3], map.pixels[8 * 256 + 8]);
+    assert_eq!(SHADES[0], viewer.tile_map(false).pixels[8 * 256 + 8]);
+    for &(x, y) in &[(120, 200), (23, 200), (120, 87), (23, 87)] {
+        assert_eq!(VIEWPORT_COLOR, map.pixels[y * 256 + x]);
+    }
+
+    let sprites = viewer.sprites();
+    assert_eq!(40, sprites.len());
+    assert_eq!((true, true, true, 1), (sprites[1].behind_background(), sprites[1].flip_y(), sprites[1].flip_x(),
+                                       sprites[1].palette()));
+    assert_eq!(" 1 x=  42 y=  84 tile=$02 bank=0 pal=1 PYX", sprites[1].to_string());
+    let mut table = Vec::new();
+    viewer.write_sprites(&mut table).unwrap();
+    assert_eq!(40, table.split(|&byte| byte == b'\n').filter(|line| !line.is_empty()).count());
+
+    let sheet = viewer.sprite_sheet();
+    assert_eq!((64, 80), (sheet.width, sheet.height));
+    assert_eq!(SHADES[1], sheet.pixels[0]);
+    // Sprite 1 is drawn with OBP1, which is 0x00, and the rest of its cell is transparent
+    assert_eq!(SHADES[0], sheet.pixels[8]);
+    assert_eq!(0x00000000, sheet.pixels[8 * 64 + 8]);
+
+    let palettes = viewer.palettes();
+    assert_eq!((32, 24), (palettes.width, palettes.height));
+    assert_eq!(SHADES[3], palettes.pixels[31]);
+    assert_eq!(SHADES[0], palettes.pixels[16 * 32 + 31]);
+
+    let mut ppu = Ppu::new(Model::Cgb);
+    ppu.write_ocps(0x80 | 0x3E);
+    ppu.write_ocpd(0x1F);
+    ppu.write_ocpd(0x00);
+    let palettes = Viewer::new(&ppu, &ram).palettes();
+    assert_eq!((32, 128), (palettes.width, palettes.height));
+    assert_eq!(0xFFFF0000, palettes.pixels[127 * 32 + 31]);
+}
+
+#[test]
+fn export() {
+    let ram = Ram::default();
+    let ppu = Ppu::default();
+    let dir = ::std::env::temp_dir().join(format!("gb18-vram-{}", ::std::process::id()));
+    Viewer::new(&ppu, &ram).export(&dir).unwrap();
+    for name in &["tiles0.png", "tiles1.png", "map9800.png", "map9C00.png", "oam.png", "palettes.png"] {
+        let png = ::std::fs::read(dir.join(name)).unwrap();
+        assert_eq!(b"\x89PNG\r\n\x1A\n", &png[..8]);
+    }
+    assert!(::std::fs::read_to_string(dir.join("oam.txt")).unwrap().starts_with(" 0 x=  -8 y= -16 tile=$00"));
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
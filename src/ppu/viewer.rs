@@ -0,0 +1,245 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use capture;
+use mmu::Ram;
+use super::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH, SHADES};
+
+/// Tiles across a tile sheet.
+const SHEET_COLUMNS: usize = 16;
+
+/// Tiles in 0x8000-0x97FF.
+const SHEET_TILES: usize = 384;
+
+/// Sprites across the sprite sheet.
+const SPRITE_COLUMNS: usize = 8;
+
+/// The width and height of a palette swatch.
+const SWATCH: usize = 8;
+
+/// The outline of the area SCX and SCY show.
+pub(super) const VIEWPORT_COLOR: u32 = 0xFFFF0000;
+
+/// An image for display, as 0xAARRGGBB pixels row by row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Image {
+        Image { width, height, pixels: vec![0x00000000; width * height] }
+    }
+
+    #[inline]
+    fn set(&mut self, x: usize, y: usize, pixel: u32) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    #[inline]
+    pub fn write_png<W: Write>(&self, out: W) -> io::Result<()> {
+        capture::write_png(out, &self.pixels, self.width, self.height)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+}
+
+/// An entry of OAM.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Sprite {
+    pub index: u8,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    cgb_mode: bool,
+}
+
+impl Sprite {
+    #[inline]
+    pub fn behind_background(&self) -> bool {
+        (self.attributes & 0x80) != 0
+    }
+
+    #[inline]
+    pub fn flip_y(&self) -> bool {
+        (self.attributes & 0x40) != 0
+    }
+
+    #[inline]
+    pub fn flip_x(&self) -> bool {
+        (self.attributes & 0x20) != 0
+    }
+
+    /// The VRAM bank of its tiles, which is always 0 outside CGB mode.
+    #[inline]
+    pub fn bank(&self) -> usize {
+        if self.cgb_mode { ((self.attributes >> 3) & 0x01) as usize } else { 0 }
+    }
+
+    /// OBP0 or OBP1, or one of the 8 color palettes in CGB mode.
+    #[inline]
+    pub fn palette(&self) -> usize {
+        if self.cgb_mode { (self.attributes & 0x07) as usize } else { ((self.attributes >> 4) & 0x01) as usize }
+    }
+}
+
+/// A line of the OAM table: its index, the screen position, tile and flags.
+impl fmt::Display for Sprite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |set: bool, name| if set { name } else { "-" };
+        write!(f, "{:2} x={:4} y={:4} tile=${:02X} bank={} pal={} {}{}{}", self.index, self.x as i16 - 8,
+               self.y as i16 - 16, self.tile, self.bank(), self.palette(), flag(self.behind_background(), "P"),
+               flag(self.flip_y(), "Y"), flag(self.flip_x(), "X"))
+    }
+}
+
+/// Renders what's in VRAM, OAM and the palettes as images, as a debugger shows them.
+pub struct Viewer<'a> {
+    ppu: &'a Ppu,
+    ram: &'a Ram,
+}
+
+impl<'a> Viewer<'a> {
+    pub(crate) fn new(ppu: &'a Ppu, ram: &'a Ram) -> Viewer<'a> {
+        Viewer { ppu, ram }
+    }
+
+    /// The color indices (0-3) of a row of the tile at `address` in a VRAM bank.
+    fn tile_row(&self, bank: usize, address: usize, flip: bool) -> [u8; 8] {
+        let (low, high) = (self.ram.video[bank][address], self.ram.video[bank][address + 1]);
+        let mut colors = [0; 8];
+        for (column, color) in colors.iter_mut().enumerate() {
+            let bit = if flip { column } else { 7 - column };
+            *color = (((high >> bit) & 0x01) << 1) | ((low >> bit) & 0x01);
+        }
+        colors
+    }
+
+    /// The 384 tiles of 0x8000-0x97FF in a VRAM bank, 16 across, in the four DMG shades by
+    /// color index.
+    pub fn tiles(&self, bank: usize) -> Image {
+        let mut image = Image::new(SHEET_COLUMNS * 8, SHEET_TILES / SHEET_COLUMNS * 8);
+        for tile in 0..SHEET_TILES {
+            let (left, top) = (tile % SHEET_COLUMNS * 8, tile / SHEET_COLUMNS * 8);
+            for row in 0..8 {
+                for (column, &color) in self.tile_row(bank, tile * 16 + row * 2, false).iter().enumerate() {
+                    image.set(left + column, top + row, SHADES[color as usize]);
+                }
+            }
+        }
+        image
+    }
+
+    /// The 256x256 tile map at 0x9800, or 0x9C00 if `high`, as the background would draw it,
+    /// with the area SCX and SCY show outlined.
+    pub fn tile_map(&self, high: bool) -> Image {
+        let mut image = Image::new(256, 256);
+        for y in 0..256 {
+            for x in 0..256 {
+                let (color, attributes) = self.ppu.tile_pixel(self.ram, high, x as u8, y as u8);
+                image.set(x, y, self.ppu.background_pixel(self.ram, color, attributes).1);
+            }
+        }
+        let (scx, scy) = (self.ram.io[0x43] as usize, self.ram.io[0x42] as usize);
+        for i in 0..SCREEN_WIDTH {
+            image.set((scx + i) % 256, scy, VIEWPORT_COLOR);
+            image.set((scx + i) % 256, (scy + SCREEN_HEIGHT - 1) % 256, VIEWPORT_COLOR);
+        }
+        for i in 0..SCREEN_HEIGHT {
+            image.set(scx, (scy + i) % 256, VIEWPORT_COLOR);
+            image.set((scx + SCREEN_WIDTH - 1) % 256, (scy + i) % 256, VIEWPORT_COLOR);
+        }
+        image
+    }
+
+    /// The 40 entries of OAM.
+    pub fn sprites(&self) -> Vec<Sprite> {
+        self.ram.oam.chunks(4).enumerate().map(|(index, entry)| Sprite {
+            index: index as u8,
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attributes: entry[3],
+            cgb_mode: self.ppu.cgb_mode,
+        }).collect()
+    }
+
+    /// Every sprite, 8 across in OAM order, in its palette and flipped as it's drawn. Each has
+    /// a cell 16 pixels tall, filled in 8x16 mode; color 0 is transparent.
+    pub fn sprite_sheet(&self) -> Image {
+        let sprites = self.sprites();
+        let tall = (self.ppu.lcdc & 0x04) != 0;
+        let height = if tall { 16 } else { 8 };
+        let mut image = Image::new(SPRITE_COLUMNS * 8, sprites.len() / SPRITE_COLUMNS * 16);
+        for sprite in &sprites {
+            let (left, top) = (sprite.index as usize % SPRITE_COLUMNS * 8, sprite.index as usize / SPRITE_COLUMNS * 16);
+            let tile = if tall { sprite.tile & 0xFE } else { sprite.tile } as usize;
+            for row in 0..height {
+                let source = if sprite.flip_y() { height - 1 - row } else { row };
+                let colors = self.tile_row(sprite.bank(), tile * 16 + source * 2, sprite.flip_x());
+                for (column, &color) in colors.iter().enumerate() {
+                    if color != 0 {
+                        image.set(left + column, top + row, self.ppu.object_pixel(self.ram, color, sprite.attributes).1);
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// Writes the OAM table, a sprite to a line.
+    pub fn write_sprites<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for sprite in self.sprites() {
+            writeln!(out, "{}", sprite)?;
+        }
+        Ok(())
+    }
+
+    /// A row of four 8x8 swatches for each palette: the 8 background then the 8 object
+    /// palettes in CGB mode, or BGP, OBP0 and OBP1 otherwise.
+    pub fn palettes(&self) -> Image {
+        let count = if self.ppu.cgb_mode { 8 } else { 1 };
+        let rows = (0..count).map(|palette| (false, palette as u8))
+            .chain((0..count.max(2)).map(|palette| (true, palette as u8)))
+            .collect::<Vec<_>>();
+        let mut image = Image::new(4 * SWATCH, rows.len() * SWATCH);
+        for (row, &(object, palette)) in rows.iter().enumerate() {
+            // Attributes choosing the palette, as a tile or sprite would
+            let attributes = if self.ppu.cgb_mode { palette } else { palette << 4 };
+            for color in 0..4 {
+                let pixel = if object {
+                    self.ppu.object_pixel(self.ram, color, attributes).1
+                } else {
+                    self.ppu.background_pixel(self.ram, color, attributes).1
+                };
+                for y in 0..SWATCH {
+                    for x in 0..SWATCH {
+                        image.set(color as usize * SWATCH + x, row * SWATCH + y, pixel);
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// Saves every view to a directory as PNG files, with the OAM table in `oam.txt`.
+    pub fn export(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        self.tiles(0).save(dir.join("tiles0.png"))?;
+        self.tiles(1).save(dir.join("tiles1.png"))?;
+        self.tile_map(false).save(dir.join("map9800.png"))?;
+        self.tile_map(true).save(dir.join("map9C00.png"))?;
+        self.sprite_sheet().save(dir.join("oam.png"))?;
+        self.palettes().save(dir.join("palettes.png"))?;
+        let mut table = BufWriter::new(File::create(dir.join("oam.txt"))?);
+        self.write_sprites(&mut table)?;
+        table.flush()
+    }
+}
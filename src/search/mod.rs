@@ -0,0 +1,177 @@
+#[cfg(test)]
+mod tests;
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use mmu::Mmu;
+
+/// The writable memory regions a search can cover. Banked regions are searched through
+/// whatever bank is currently mapped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// Cartridge RAM, 0xA000-0xBFFF. Reads 0xFF while the cartridge has it disabled.
+    Cart,
+    /// Work RAM, 0xC000-0xDFFF.
+    Work,
+    /// High RAM, 0xFF80-0xFFFE.
+    High,
+}
+
+impl Region {
+    pub fn range(self) -> RangeInclusive<u16> {
+        match self {
+            Region::Cart => 0xA000..=0xBFFF,
+            Region::Work => 0xC000..=0xDFFF,
+            Region::High => 0xFF80..=0xFFFE,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    /// Little-endian 16-bit values, as the CPU stores them.
+    Word,
+}
+
+impl Width {
+    fn read(self, mmu: &impl Mmu, address: u16) -> u16 {
+        match self {
+            Width::Byte => mmu.read(address) as u16,
+            Width::Word => (mmu.read(address) as u16) | ((mmu.read(address.wrapping_add(1)) as u16) << 8),
+        }
+    }
+}
+
+/// How a candidate's current value must relate to its value at the previous snapshot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    /// The current value is exactly this.
+    Value(u16),
+}
+
+impl Comparison {
+    #[inline]
+    fn matches(self, previous: u16, current: u16) -> bool {
+        match self {
+            Comparison::Equal => current == previous,
+            Comparison::Changed => current != previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+            Comparison::Value(value) => current == value,
+        }
+    }
+}
+
+/// Narrows down the addresses holding some value by filtering snapshots taken across frames.
+pub struct Search {
+    width: Width,
+    candidates: Vec<(u16, u16)>,
+}
+
+impl Search {
+    /// Takes the first snapshot, with every address in `regions` a candidate.
+    pub fn new(mmu: &impl Mmu, regions: &[Region], width: Width) -> Search {
+        let mut candidates = Vec::new();
+        for region in regions {
+            let range = region.range();
+            let end = match width {
+                Width::Byte => *range.end(),
+                Width::Word => *range.end() - 1,
+            };
+            for address in *range.start()..=end {
+                candidates.push((address, width.read(mmu, address)));
+            }
+        }
+        Search { width, candidates }
+    }
+
+    #[inline]
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    /// Takes a new snapshot, keeping only the candidates whose value passes `comparison`. Returns
+    /// how many remain.
+    pub fn filter(&mut self, mmu: &impl Mmu, comparison: Comparison) -> usize {
+        let width = self.width;
+        self.candidates = self.candidates.iter()
+            .map(|&(address, previous)| (address, previous, width.read(mmu, address)))
+            .filter(|&(_, previous, current)| comparison.matches(previous, current))
+            .map(|(address, _, current)| (address, current))
+            .collect();
+        self.candidates.len()
+    }
+
+    /// The remaining addresses and their values at the last snapshot.
+    #[inline]
+    pub fn candidates(&self) -> &[(u16, u16)] {
+        &self.candidates
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    pub label: String,
+    pub address: u16,
+    pub width: Width,
+}
+
+impl Watch {
+    pub fn value(&self, mmu: &impl Mmu) -> u16 {
+        self.width.read(mmu, self.address)
+    }
+}
+
+/// A list of labelled addresses to keep an eye on.
+#[derive(Default)]
+pub struct WatchList {
+    watches: Vec<Watch>,
+}
+
+impl WatchList {
+    pub fn add(&mut self, label: &str, address: u16, width: Width) -> usize {
+        self.watches.push(Watch { label: label.to_string(), address, width });
+        self.watches.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watch> {
+        if index < self.watches.len() {
+            Some(self.watches.remove(index))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Reads every watch, for display.
+    pub fn values<'a, M: Mmu>(&'a self, mmu: &'a M) -> Values<'a, M> {
+        Values { watches: self, mmu }
+    }
+}
+
+/// The watch list's current values, one `label $ADDR = $VALUE` per line.
+pub struct Values<'a, M: Mmu + 'a> {
+    watches: &'a WatchList,
+    mmu: &'a M,
+}
+
+impl<'a, M: Mmu> fmt::Display for Values<'a, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for watch in &self.watches.watches {
+            match watch.width {
+                Width::Byte => writeln!(f, "{} ${:04X} = ${:02X}", watch.label, watch.address, watch.value(self.mmu))?,
+                Width::Word => writeln!(f, "{} ${:04X} = ${:04X}", watch.label, watch.address, watch.value(self.mmu))?,
+            }
+        }
+        Ok(())
+    }
+}
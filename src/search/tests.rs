@@ -0,0 +1,49 @@
+use super::*;
+
+#[test]
+fn bytes() {
+    let mut mmu = vec![0x00; 0x10000];
+    mmu[0xC100] = 3;
+    mmu[0xC200] = 3;
+    mmu[0xFF90] = 3;
+    let mut search = Search::new(&mmu, &[Region::Work, Region::High], Width::Byte);
+    assert_eq!(0x2000 + 0x7F, search.candidates().len());
+    assert_eq!(3, search.filter(&mmu, Comparison::Value(3)));
+
+    mmu[0xC100] = 2;
+    mmu[0xC200] = 4;
+    assert_eq!(2, search.filter(&mmu, Comparison::Changed));
+    mmu[0xC100] = 1;
+    mmu[0xC200] = 5;
+    assert_eq!(1, search.filter(&mmu, Comparison::Decreased));
+    assert_eq!(&[(0xC100, 1)], search.candidates());
+    assert_eq!(1, search.filter(&mmu, Comparison::Equal));
+    mmu[0xC100] = 2;
+    assert_eq!(1, search.filter(&mmu, Comparison::Increased));
+}
+
+#[test]
+fn words() {
+    let mut mmu = vec![0x00; 0x10000];
+    let mut search = Search::new(&mmu, &[Region::High], Width::Word);
+    assert_eq!(0x7E, search.candidates().len());
+    mmu[0xFF80] = 0x34;
+    mmu[0xFF81] = 0x12;
+    assert_eq!(1, search.filter(&mmu, Comparison::Value(0x1234)));
+    assert_eq!(&[(0xFF80, 0x1234)], search.candidates());
+}
+
+#[test]
+fn watches() {
+    let mut mmu = vec![0x00; 0x10000];
+    mmu[0xC0A5] = 0x63;
+    mmu[0xFF80] = 0x34;
+    mmu[0xFF81] = 0x12;
+    let mut watches = WatchList::default();
+    watches.add("lives", 0xC0A5, Width::Byte);
+    let score = watches.add("score", 0xFF80, Width::Word);
+    assert_eq!(0x1234, watches.watches()[score].value(&mmu));
+    assert_eq!("lives $C0A5 = $63\nscore $FF80 = $1234\n", watches.values(&mmu).to_string());
+    watches.remove(0);
+    assert_eq!("score $FF80 = $1234\n", watches.values(&mmu).to_string());
+}
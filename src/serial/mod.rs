@@ -0,0 +1,81 @@
+#[cfg(test)]
+mod tests;
+
+use std::io;
+use state::{Reader, State, Writer};
+
+/// The serial port, with nothing plugged into the other end.
+///
+/// A transfer started with the internal clock shifts out SB over 8 bits at 8192 Hz, after which
+/// SB reads 0xFF and the serial interrupt is requested. Every byte sent is kept so test ROMs can
+/// report their results.
+#[derive(Default)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cycles: usize,
+    output: Vec<u8>,
+}
+
+/// M-cycles to shift out one byte with the internal clock.
+const TRANSFER_CYCLES: usize = 256;
+
+impl Serial {
+    /// Advances the port by one M-cycle, returning `true` when the serial interrupt is requested.
+    pub fn tick(&mut self) -> bool {
+        if self.cycles == 0 {
+            return false;
+        }
+        self.cycles -= 1;
+        if self.cycles != 0 {
+            return false;
+        }
+        self.output.push(self.sb);
+        self.sb = 0xFF;
+        self.sc &= 0x7F;
+        true
+    }
+
+    #[inline]
+    pub fn sb(&self) -> u8 {
+        self.sb
+    }
+
+    #[inline]
+    pub fn sc(&self) -> u8 {
+        self.sc | 0x7E
+    }
+
+    #[inline]
+    pub fn write_sb(&mut self, value: u8) {
+        self.sb = value;
+    }
+
+    /// Only transfers on the internal clock ever complete; an external clock never arrives.
+    pub fn write_sc(&mut self, value: u8) {
+        self.sc = value & 0x81;
+        self.cycles = if value & 0x81 == 0x81 { TRANSFER_CYCLES } else { 0 };
+    }
+
+    /// Every byte shifted out so far.
+    #[inline]
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+/// The output log isn't part of the state.
+impl State for Serial {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.sb);
+        writer.u8(self.sc);
+        writer.u32(self.cycles as u32);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.sb = reader.u8()?;
+        self.sc = reader.u8()?;
+        self.cycles = reader.u32()? as usize;
+        Ok(())
+    }
+}
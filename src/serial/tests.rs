@@ -0,0 +1,28 @@
+use super::*;
+
+#[test]
+fn transfer() {
+    let mut serial = Serial::default();
+    serial.write_sb(0x42);
+    serial.write_sc(0x81);
+    assert_eq!(0xFF, serial.sc());
+    for _ in 0..TRANSFER_CYCLES - 1 {
+        assert_eq!(false, serial.tick());
+    }
+    assert_eq!(true, serial.tick());
+    assert_eq!(0xFF, serial.sb());
+    assert_eq!(0x7F, serial.sc());
+    assert_eq!(&[0x42], serial.output());
+}
+
+#[test]
+fn external_clock() {
+    let mut serial = Serial::default();
+    serial.write_sb(0x42);
+    serial.write_sc(0x80);
+    for _ in 0..TRANSFER_CYCLES * 2 {
+        assert_eq!(false, serial.tick());
+    }
+    assert_eq!(0x42, serial.sb());
+    assert!(serial.output().is_empty());
+}
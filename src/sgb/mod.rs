@@ -0,0 +1,435 @@
+#[cfg(test)]
+mod tests;
+
+use std::io;
+use ppu::{self, SCREEN_HEIGHT, SCREEN_WIDTH};
+use state::{Reader, State, Writer};
+
+pub const BORDER_WIDTH: usize = 256;
+
+pub const BORDER_HEIGHT: usize = 224;
+
+/// Where the Game Boy screen sits within the border.
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+/// The screen is colorized in 8x8 cells.
+const CELLS_WIDE: usize = SCREEN_WIDTH / 8;
+const CELLS_HIGH: usize = SCREEN_HEIGHT / 8;
+
+/// The palette the SGB BIOS starts every system palette with.
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+pub const PAL01: u8 = 0x00;
+pub const PAL23: u8 = 0x01;
+pub const PAL03: u8 = 0x02;
+pub const PAL12: u8 = 0x03;
+pub const ATTR_BLK: u8 = 0x04;
+pub const ATTR_LIN: u8 = 0x05;
+pub const ATTR_DIV: u8 = 0x06;
+pub const ATTR_CHR: u8 = 0x07;
+pub const MLT_REQ: u8 = 0x11;
+pub const CHR_TRN: u8 = 0x13;
+pub const PCT_TRN: u8 = 0x14;
+pub const MASK_EN: u8 = 0x17;
+
+/// Whether a ROM's header asks for Super Game Boy functions: the SGB flag, with the new
+/// licensee code the SGB BIOS requires.
+pub fn supported(rom: &[u8]) -> bool {
+    rom.get(0x0146) == Some(&0x03) && rom.get(0x014B) == Some(&0x33)
+}
+
+/// What MASK_EN shows in place of the game screen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mask {
+    None,
+    /// Keeps showing the last frame.
+    Freeze,
+    Black,
+    /// Shows color 0 of palette 0.
+    Color0,
+}
+
+/// The Super Game Boy side of the link: decodes the command packets a game bit-bangs through
+/// JOYP, keeps the system palettes, the attribute map and the border, and composes the 256x224
+/// picture the SNES shows.
+///
+/// A packet starts with a reset pulse (both JOYP select lines low), followed by 128 bits sent
+/// least significant first as pulses on P14 (a 0) or P15 (a 1), then a 0 stop bit. The first
+/// byte gives the command in its upper 5 bits and how many packets it spans in its lower 3.
+pub struct Sgb {
+    select: u8,
+    receiving: bool,
+    bits: usize,
+    packet: [u8; 16],
+    command: Vec<u8>,
+    palettes: [[u16; 4]; 4],
+    attributes: [u8; CELLS_WIDE * CELLS_HIGH],
+    mask: Mask,
+    players: usize,
+    tiles: Vec<u8>,
+    map: Vec<u8>,
+    border_palettes: [[u16; 16]; 4],
+    frame: Vec<u32>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Sgb {
+            select: 0x30,
+            receiving: false,
+            bits: 0,
+            packet: [0; 16],
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: [0; CELLS_WIDE * CELLS_HIGH],
+            mask: Mask::None,
+            players: 1,
+            tiles: vec![0; 0x2000],
+            map: vec![0; 0x800],
+            border_palettes: [[0; 16]; 4],
+            frame: vec![ppu::rgb555(DEFAULT_PALETTE[0]); BORDER_WIDTH * BORDER_HEIGHT],
+        }
+    }
+}
+
+impl Sgb {
+    /// Follows a write to JOYP, returning the bytes of a command once all its packets arrived.
+    pub fn write_joyp(&mut self, value: u8) -> Option<Vec<u8>> {
+        let select = value & 0x30;
+        let previous = self.select;
+        self.select = select;
+        if select == previous {
+            return None;
+        }
+        match select {
+            0x00 => {
+                self.receiving = true;
+                self.bits = 0;
+                self.packet = [0; 16];
+                None
+            }
+            0x10 | 0x20 if self.receiving && previous == 0x30 => self.receive(select == 0x10),
+            _ => None,
+        }
+    }
+
+    fn receive(&mut self, bit: bool) -> Option<Vec<u8>> {
+        if self.bits == 128 {
+            self.receiving = false;
+            if bit {
+                // A packet must end with a 0 stop bit
+                self.command.clear();
+                return None;
+            }
+            self.command.extend_from_slice(&self.packet);
+            let packets = (self.command[0] & 0x07).max(1) as usize;
+            if self.command.len() < packets * 16 {
+                return None;
+            }
+            return Some(self.command.split_off(0));
+        }
+        if bit {
+            self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+        }
+        self.bits += 1;
+        None
+    }
+
+    /// Carries out a command. Transfers read the 4KB the game shows on its background: the
+    /// first 256 tiles of the tile map selected by `lcdc`, left to right and top to bottom.
+    pub fn execute(&mut self, command: &[u8], video: &[u8], lcdc: u8) {
+        match command[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, &command[1..15]),
+            PAL23 => self.set_palettes(2, 3, &command[1..15]),
+            PAL03 => self.set_palettes(0, 3, &command[1..15]),
+            PAL12 => self.set_palettes(1, 2, &command[1..15]),
+            ATTR_BLK => self.attr_blk(command),
+            ATTR_LIN => self.attr_lin(command),
+            ATTR_DIV => self.attr_div(command),
+            ATTR_CHR => self.attr_chr(command),
+            MLT_REQ => {
+                self.players = match command[1] & 0x03 {
+                    0x01 => 2,
+                    0x03 => 4,
+                    _ => 1,
+                }
+            }
+            CHR_TRN => {
+                let data = transfer(video, lcdc);
+                let offset = (command[1] & 0x01) as usize * 0x1000;
+                self.tiles[offset..offset + 0x1000].copy_from_slice(&data);
+            }
+            PCT_TRN => {
+                let data = transfer(video, lcdc);
+                self.map.copy_from_slice(&data[..0x800]);
+                for (i, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (j, color) in palette.iter_mut().enumerate() {
+                        let offset = 0x800 + i * 32 + j * 2;
+                        *color = u16::from_le_bytes([data[offset], data[offset + 1]]);
+                    }
+                }
+            }
+            MASK_EN => {
+                self.mask = match command[1] & 0x03 {
+                    0x01 => Mask::Freeze,
+                    0x02 => Mask::Black,
+                    0x03 => Mask::Color0,
+                    _ => Mask::None,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Color 0 is shared by every palette, so setting it through either palette sets it for all.
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color = |index: usize| u16::from_le_bytes([data[index * 2], data[index * 2 + 1]]);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for i in 1..4 {
+            self.palettes[first][i] = color(i);
+            self.palettes[second][i] = color(i + 3);
+        }
+    }
+
+    fn attr_blk(&mut self, command: &[u8]) {
+        let sets = (command[1] & 0x1F) as usize;
+        for set in command[2..].chunks(6).take(sets).filter(|set| set.len() == 6) {
+            let mut control = set[0] & 0x07;
+            let (inside, outside) = (set[1] & 0x03, (set[1] >> 4) & 0x03);
+            // With only the inside or the outside changing, the border changes along with it
+            let border = match control {
+                0x01 => inside,
+                0x04 => outside,
+                _ => (set[1] >> 2) & 0x03,
+            };
+            if control == 0x01 || control == 0x04 {
+                control |= 0x02;
+            }
+            let (x1, y1, x2, y2) = (set[2] & 0x1F, set[3] & 0x1F, set[4] & 0x1F, set[5] & 0x1F);
+            for y in 0..CELLS_HIGH as u8 {
+                for x in 0..CELLS_WIDE as u8 {
+                    let (area, palette) = if x > x1 && x < x2 && y > y1 && y < y2 {
+                        (0x01, inside)
+                    } else if x < x1 || x > x2 || y < y1 || y > y2 {
+                        (0x04, outside)
+                    } else {
+                        (0x02, border)
+                    };
+                    if (control & area) != 0 {
+                        self.attributes[y as usize * CELLS_WIDE + x as usize] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, command: &[u8]) {
+        let lines = command[1] as usize;
+        for &line in command[2..].iter().take(lines) {
+            let (index, palette) = ((line & 0x1F) as usize, (line >> 5) & 0x03);
+            if (line & 0x80) != 0 {
+                if index < CELLS_HIGH {
+                    for x in 0..CELLS_WIDE {
+                        self.attributes[index * CELLS_WIDE + x] = palette;
+                    }
+                }
+            } else if index < CELLS_WIDE {
+                for y in 0..CELLS_HIGH {
+                    self.attributes[y * CELLS_WIDE + index] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, command: &[u8]) {
+        let (after, before, on) = (command[1] & 0x03, (command[1] >> 2) & 0x03, (command[1] >> 4) & 0x03);
+        let line = (command[2] & 0x1F) as usize;
+        let horizontal = (command[1] & 0x40) != 0;
+        for y in 0..CELLS_HIGH {
+            for x in 0..CELLS_WIDE {
+                let position = if horizontal { y } else { x };
+                self.attributes[y * CELLS_WIDE + x] = if position < line {
+                    before
+                } else if position == line {
+                    on
+                } else {
+                    after
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, command: &[u8]) {
+        let (mut x, mut y) = ((command[1] as usize).min(CELLS_WIDE - 1), (command[2] as usize).min(CELLS_HIGH - 1));
+        let count = (u16::from_le_bytes([command[3], command[4]]) as usize).min(CELLS_WIDE * CELLS_HIGH);
+        let vertical = (command[5] & 0x01) != 0;
+        for i in 0..count {
+            let byte = match command.get(6 + i / 4) {
+                Some(&byte) => byte,
+                None => break,
+            };
+            self.attributes[y * CELLS_WIDE + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+            if vertical {
+                y += 1;
+                if y == CELLS_HIGH {
+                    y = 0;
+                    x = (x + 1) % CELLS_WIDE;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_WIDE {
+                    x = 0;
+                    y = (y + 1) % CELLS_HIGH;
+                }
+            }
+        }
+    }
+
+    /// How many joypads MLT_REQ asked for.
+    #[inline]
+    pub fn players(&self) -> usize {
+        self.players
+    }
+
+    #[inline]
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    /// The system palette colorizing each 8x8 cell of the screen.
+    #[inline]
+    pub fn attributes(&self) -> &[u8] {
+        &self.attributes
+    }
+
+    /// The four system palettes as BGR555 colors.
+    #[inline]
+    pub fn palettes(&self) -> &[[u16; 4]; 4] {
+        &self.palettes
+    }
+
+    /// The last composed picture, 256x224 pixels as 0xAARRGGBB.
+    #[inline]
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    /// The color index (0-15) of a border pixel, given the SNES 4bpp tile it falls in.
+    fn border_pixel(&self, entry: u16, x: usize, y: usize) -> u8 {
+        let tile = &self.tiles[(entry & 0xFF) as usize * 32..][..32];
+        let x = if (entry & 0x4000) != 0 { x } else { 7 - x };
+        let y = if (entry & 0x8000) != 0 { 7 - y } else { y };
+        let bit = |byte: u8| (byte >> x) & 0x01;
+        bit(tile[y * 2]) | (bit(tile[y * 2 + 1]) << 1) | (bit(tile[16 + y * 2]) << 2) | (bit(tile[17 + y * 2]) << 3)
+    }
+
+    /// Redraws the picture from a finished frame's shades.
+    pub fn compose(&mut self, shades: &[u8]) {
+        let backdrop = ppu::rgb555(self.palettes[0][0]);
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    Mask::Freeze => continue,
+                    Mask::Black => 0xFF000000,
+                    Mask::Color0 => backdrop,
+                    Mask::None => {
+                        let palette = self.attributes[(y / 8) * CELLS_WIDE + x / 8] as usize;
+                        ppu::rgb555(self.palettes[palette][(shades[y * SCREEN_WIDTH + x] & 0x03) as usize])
+                    }
+                };
+                self.frame[(SCREEN_Y + y) * BORDER_WIDTH + SCREEN_X + x] = color;
+            }
+        }
+        for y in 0..BORDER_HEIGHT {
+            for x in 0..BORDER_WIDTH {
+                let cell = (y / 8) * 32 + x / 8;
+                let entry = u16::from_le_bytes([self.map[cell * 2], self.map[cell * 2 + 1]]);
+                let color = self.border_pixel(entry, x % 8, y % 8);
+                let screen = (SCREEN_X..SCREEN_X + SCREEN_WIDTH).contains(&x) && (SCREEN_Y..SCREEN_Y + SCREEN_HEIGHT).contains(&y);
+                if color != 0 {
+                    let palette = ((entry >> 10) & 0x07) as usize;
+                    // Only palettes 4-7 are loaded by PCT_TRN
+                    let palette = palette.max(4) - 4;
+                    self.frame[y * BORDER_WIDTH + x] = ppu::rgb555(self.border_palettes[palette][color as usize]);
+                } else if !screen {
+                    self.frame[y * BORDER_WIDTH + x] = backdrop;
+                }
+            }
+        }
+    }
+}
+
+/// The 4KB a transfer command reads from the screen.
+fn transfer(video: &[u8], lcdc: u8) -> Vec<u8> {
+    let map = if (lcdc & 0x08) != 0 { 0x1C00 } else { 0x1800 };
+    let mut data = Vec::with_capacity(0x1000);
+    for i in 0..256 {
+        let tile = video[map + (i / CELLS_WIDE) * 32 + i % CELLS_WIDE];
+        let address = if (lcdc & 0x10) != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as isize) * 16) as usize
+        };
+        data.extend_from_slice(&video[address..address + 16]);
+    }
+    data
+}
+
+/// The composed picture isn't saved; it's redrawn at the next VBlank.
+impl State for Sgb {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.select);
+        writer.bool(self.receiving);
+        writer.u8(self.bits as u8);
+        writer.bytes(&self.packet);
+        writer.blob(&self.command);
+        for &color in self.palettes.iter().flat_map(|palette| palette.iter()) {
+            writer.u16(color);
+        }
+        writer.bytes(&self.attributes);
+        writer.u8(match self.mask {
+            Mask::None => 0,
+            Mask::Freeze => 1,
+            Mask::Black => 2,
+            Mask::Color0 => 3,
+        });
+        writer.u8(self.players as u8);
+        writer.bytes(&self.tiles);
+        writer.bytes(&self.map);
+        for &color in self.border_palettes.iter().flat_map(|palette| palette.iter()) {
+            writer.u16(color);
+        }
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.select = reader.u8()? & 0x30;
+        self.receiving = reader.bool()?;
+        self.bits = (reader.u8()? as usize).min(128);
+        reader.bytes(&mut self.packet)?;
+        self.command = reader.blob()?;
+        self.command.truncate(7 * 16);
+        for color in self.palettes.iter_mut().flat_map(|palette| palette.iter_mut()) {
+            *color = reader.u16()?;
+        }
+        reader.bytes(&mut self.attributes)?;
+        for attribute in self.attributes.iter_mut() {
+            *attribute &= 0x03;
+        }
+        self.mask = match reader.u8()? & 0x03 {
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => Mask::None,
+        };
+        self.players = (reader.u8()? as usize).clamp(1, 4);
+        reader.bytes(&mut self.tiles)?;
+        reader.bytes(&mut self.map)?;
+        for color in self.border_palettes.iter_mut().flat_map(|palette| palette.iter_mut()) {
+            *color = reader.u16()?;
+        }
+        Ok(())
+    }
+}
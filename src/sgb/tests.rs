@@ -0,0 +1,139 @@
+use super::*;
+
+/// Bit-bangs packets through JOYP, returning the command they complete.
+fn send(sgb: &mut Sgb, data: &[u8]) -> Option<Vec<u8>> {
+    let mut command = None;
+    for packet in data.chunks(16) {
+        sgb.write_joyp(0x00);
+        sgb.write_joyp(0x30);
+        for i in 0..129 {
+            let bit = i < 128 && (packet[i / 8] >> (i % 8)) & 0x01 != 0;
+            command = sgb.write_joyp(if bit { 0x10 } else { 0x20 });
+            sgb.write_joyp(0x30);
+        }
+    }
+    command
+}
+
+fn packet(command: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; 16];
+    packet[0] = command << 3 | 0x01;
+    packet[1..1 + data.len()].copy_from_slice(data);
+    packet
+}
+
+fn execute(sgb: &mut Sgb, command: u8, data: &[u8]) {
+    let command = send(sgb, &packet(command, data)).unwrap();
+    sgb.execute(&command, &[0; 0x2000], 0x91);
+}
+
+#[test]
+fn packets() {
+    let mut sgb = Sgb::default();
+    let data = packet(MASK_EN, &[0x02]);
+    assert_eq!(Some(data.clone()), send(&mut sgb, &data));
+
+    // A command spanning two packets only completes with the second
+    let mut data = vec![0xAA; 32];
+    data[0] = ATTR_BLK << 3 | 0x02;
+    assert_eq!(None, send(&mut sgb, &data[..16]));
+    assert_eq!(Some(data.clone()), send(&mut sgb, &data[16..]));
+
+    // A 1 stop bit drops the packet
+    sgb.write_joyp(0x00);
+    sgb.write_joyp(0x30);
+    for _ in 0..129 {
+        assert_eq!(None, sgb.write_joyp(0x10));
+        sgb.write_joyp(0x30);
+    }
+}
+
+#[test]
+fn palettes() {
+    let mut sgb = Sgb::default();
+    execute(&mut sgb, PAL12, &[0x00, 0x7C, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04, 0x00, 0x05, 0x00, 0x06, 0x00]);
+    assert_eq!([0x7C00, 0x0001, 0x0002, 0x0003], sgb.palettes()[1]);
+    assert_eq!([0x7C00, 0x0004, 0x0005, 0x0006], sgb.palettes()[2]);
+    assert_eq!([0x7C00, DEFAULT_PALETTE[1], DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]], sgb.palettes()[0]);
+    assert_eq!(0xFF0000FF, ppu::rgb555(0x7C00));
+    assert_eq!(0xFFFFFFFF, ppu::rgb555(0x7FFF));
+}
+
+#[test]
+fn attributes() {
+    let mut sgb = Sgb::default();
+    execute(&mut sgb, ATTR_DIV, &[0x40 | 0x20 | 0x04 | 0x03, 0x05]);
+    assert_eq!(1, sgb.attributes()[4 * CELLS_WIDE]);
+    assert_eq!(2, sgb.attributes()[5 * CELLS_WIDE]);
+    assert_eq!(3, sgb.attributes()[6 * CELLS_WIDE + 19]);
+
+    execute(&mut sgb, ATTR_LIN, &[0x02, 0x20 | 0x02, 0x80 | 0x40 | 0x11]);
+    assert_eq!(1, sgb.attributes()[2]);
+    assert_eq!(1, sgb.attributes()[16 * CELLS_WIDE + 2]);
+    assert_eq!(2, sgb.attributes()[17 * CELLS_WIDE]);
+
+    // Only the inside set, so the border follows it
+    execute(&mut sgb, ATTR_BLK, &[0x01, 0x01, 0x03, 0x01, 0x01, 0x03, 0x03]);
+    assert_eq!(3, sgb.attributes()[2 * CELLS_WIDE + 2]);
+    assert_eq!(3, sgb.attributes()[CELLS_WIDE + 1]);
+    assert_eq!(1, sgb.attributes()[4 * CELLS_WIDE + 5]);
+
+    execute(&mut sgb, ATTR_CHR, &[0x13, 0x00, 0x03, 0x00, 0x00, 0b01_10_11_00]);
+    assert_eq!(&[1, 2, 3], &sgb.attributes()[19..22]);
+    assert_eq!(3, sgb.attributes()[22]);
+}
+
+/// VRAM showing `data` the way games lay out transfers: tile n in map cell n.
+fn screen(data: &[u8]) -> Vec<u8> {
+    let mut video = vec![0; 0x2000];
+    video[..data.len()].copy_from_slice(data);
+    for i in 0..256 {
+        video[0x1800 + (i / 20) * 32 + i % 20] = i as u8;
+    }
+    video
+}
+
+#[test]
+fn compose() {
+    let mut sgb = Sgb::default();
+    // Border tile 1 has color 1 down its left column
+    let mut tiles = vec![0; 0x1000];
+    for row in 0..8 {
+        tiles[32 + row * 2] = 0x80;
+    }
+    let command = send(&mut sgb, &packet(CHR_TRN, &[0x00])).unwrap();
+    sgb.execute(&command, &screen(&tiles), 0x91);
+    // The top left of the border is tile 1 in palette 4, whose color 1 is red
+    let mut picture = vec![0; 0x1000];
+    picture[0x0000..0x0002].copy_from_slice(&[0x01, 0x10]);
+    picture[0x0802..0x0804].copy_from_slice(&[0x1F, 0x00]);
+    let command = send(&mut sgb, &packet(PCT_TRN, &[])).unwrap();
+    sgb.execute(&command, &screen(&picture), 0x91);
+
+    let shades = vec![3; SCREEN_WIDTH * SCREEN_HEIGHT];
+    sgb.compose(&shades);
+    let frame = sgb.frame();
+    assert_eq!(BORDER_WIDTH * BORDER_HEIGHT, frame.len());
+    assert_eq!(0xFFFF0000, frame[0]);
+    assert_eq!(0xFFFF0000, frame[7 * BORDER_WIDTH]);
+    assert_eq!(ppu::rgb555(DEFAULT_PALETTE[0]), frame[1]);
+    assert_eq!(ppu::rgb555(DEFAULT_PALETTE[3]), frame[SCREEN_Y * BORDER_WIDTH + SCREEN_X]);
+
+    execute(&mut sgb, MASK_EN, &[0x02]);
+    sgb.compose(&shades);
+    assert_eq!(0xFF000000, sgb.frame()[SCREEN_Y * BORDER_WIDTH + SCREEN_X]);
+    execute(&mut sgb, MASK_EN, &[0x01]);
+    sgb.compose(&vec![0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+    assert_eq!(0xFF000000, sgb.frame()[SCREEN_Y * BORDER_WIDTH + SCREEN_X]);
+    assert_eq!(Mask::Freeze, sgb.mask());
+}
+
+#[test]
+fn header() {
+    let mut rom = vec![0x00; 0x8000];
+    assert_eq!(false, supported(&rom));
+    rom[0x0146] = 0x03;
+    rom[0x014B] = 0x33;
+    assert_eq!(true, supported(&rom));
+    assert_eq!(false, supported(&rom[..0x100]));
+}
@@ -0,0 +1,191 @@
+#[cfg(test)]
+mod tests;
+
+use std::io;
+use cpu::Cpu;
+use mmu::Hardware;
+
+/// Identifies a save state, followed by a version byte.
+const MAGIC: &[u8; 8] = b"GB18SAVE";
+
+const VERSION: u8 = 8;
+
+/// 64-bit FNV-1a, used to identify ROMs and compare frames.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF29CE484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x00000100000001B3))
+}
+
+/// Hashes a frame from `Board::frame`.
+pub fn hash_frame(frame: &[u32]) -> u64 {
+    let bytes: Vec<u8> = frame.iter().flat_map(|pixel| pixel.to_le_bytes().to_vec()).collect();
+    hash(&bytes)
+}
+
+/// Serializes state as little-endian fields.
+#[derive(Default)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    #[inline]
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    #[inline]
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    #[inline]
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[inline]
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[inline]
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Bytes whose length the reader already knows.
+    #[inline]
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Bytes preceded by their length.
+    pub fn blob(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads back what a `Writer` wrote. Running out of data is an `UnexpectedEof` error.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated state"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    #[inline]
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    #[inline]
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    #[inline]
+    pub fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    #[inline]
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    #[inline]
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    #[inline]
+    pub fn bytes(&mut self, bytes: &mut [u8]) -> io::Result<()> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    pub fn blob(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Whether everything has been read.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// Something whose state can be saved and restored exactly.
+pub trait State {
+    fn save(&self, writer: &mut Writer);
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()>;
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Saves the whole machine, tagged with the hash of the ROM it belongs to.
+pub fn save<M: Hardware>(cpu: &Cpu, mmu: &M) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer.bytes(MAGIC);
+    writer.u8(VERSION);
+    writer.u64(hash(mmu.rom()));
+    cpu.save(&mut writer);
+    mmu.save(&mut writer);
+    writer.into_inner()
+}
+
+/// Restores a state from `save`. States made with another ROM are refused, and nothing is
+/// changed unless the whole state loads.
+pub fn load<M: Hardware>(cpu: &mut Cpu, mmu: &mut M, data: &[u8]) -> io::Result<()> {
+    let mut reader = Reader::new(data);
+    let mut magic = [0; 8];
+    reader.bytes(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a save state"));
+    }
+    if reader.u8()? != VERSION {
+        return Err(invalid("unsupported save state version"));
+    }
+    if reader.u64()? != hash(mmu.rom()) {
+        return Err(invalid("save state is for a different ROM"));
+    }
+    let backup = save(cpu, mmu);
+    let result = cpu.load(&mut reader).and_then(|_| mmu.load(&mut reader)).and_then(|_| {
+        if reader.is_empty() { Ok(()) } else { Err(invalid("trailing data after save state")) }
+    });
+    if result.is_err() {
+        let mut reader = Reader::new(&backup[MAGIC.len() + 9..]);
+        // A state we just saved should load back, but if it somehow doesn't, say so
+        cpu.load(&mut reader).and_then(|_| mmu.load(&mut reader))?;
+    }
+    result
+}
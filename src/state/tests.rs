@@ -0,0 +1,74 @@
+use super::*;
+use mmu::{Mbc1, Mmu, Port};
+
+/// An MBC1 cartridge with RAM running a loop that counts in cartridge RAM and the DIV-driven
+/// timer, so every part of the state moves.
+fn machine() -> (Cpu, Mbc1) {
+    let mut rom = vec![0x00; 0x8000];
+    rom[0x0147] = 0x03;
+    rom[0x0149] = 0x02;
+    rom[0x0100..0x0113].copy_from_slice(&[
+        0x3E, 0x0A,         // ld a, $0A
+        0xEA, 0x00, 0x00,   // ld [$0000], a
+        0x3E, 0x05,         // ld a, $05
+        0xE0, 0x07,         // ldh [$FF07], a
+        0x3E, 0x91,         // ld a, $91
+        0xE0, 0x40,         // ldh [$FF40], a
+        0x21, 0x00, 0xA0,   // ld hl, $A000
+        0x34,               // loop: inc [hl]
+        0x18, 0xFD,         // jr loop
+    ]);
+    let mut mmu = Mbc1::new(rom);
+    mmu.io_write(Port::BIOS, 0x01);
+    (Cpu::builder().pc(0x0100).sp(0xFFFE).build(), mmu)
+}
+
+fn run(cpu: &mut Cpu, mmu: &mut Mbc1, cycles: usize) {
+    let mut elapsed = 0;
+    while elapsed < cycles {
+        elapsed += cpu.cycle(mmu);
+    }
+}
+
+#[test]
+fn hashes() {
+    assert_eq!(0xCBF29CE484222325, hash(&[]));
+    assert_eq!(0xAF63DC4C8601EC8C, hash(b"a"));
+    assert_eq!(hash(&[0x04, 0x03, 0x02, 0x01]), hash_frame(&[0x01020304]));
+}
+
+#[test]
+fn round_trip() {
+    let (mut cpu, mut mmu) = machine();
+    run(&mut cpu, &mut mmu, 10_000);
+    let saved = save(&cpu, &mmu);
+    run(&mut cpu, &mut mmu, 50_000);
+    let expected = save(&cpu, &mmu);
+
+    let (mut other_cpu, mut other) = machine();
+    load(&mut other_cpu, &mut other, &saved).unwrap();
+    assert_eq!(saved, save(&other_cpu, &other));
+    run(&mut other_cpu, &mut other, 50_000);
+    assert_eq!(expected, save(&other_cpu, &other));
+    assert_eq!(cpu.registers(), other_cpu.registers());
+}
+
+#[test]
+fn refused() {
+    let (mut cpu, mut mmu) = machine();
+    run(&mut cpu, &mut mmu, 10_000);
+    let saved = save(&cpu, &mmu);
+    let (mut fresh_cpu, mut fresh) = machine();
+    let before = save(&fresh_cpu, &fresh);
+
+    let err = load(&mut fresh_cpu, &mut fresh, &saved[..saved.len() - 1]).unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    assert_eq!(before, save(&fresh_cpu, &fresh));
+
+    let mut other = Mbc1::new(vec![0xFF; 0x8000]);
+    let err = load(&mut fresh_cpu, &mut other, &saved).unwrap_err();
+    assert_eq!("save state is for a different ROM", err.to_string());
+
+    let err = load(&mut fresh_cpu, &mut fresh, b"GB18MOVI").unwrap_err();
+    assert_eq!("not a save state", err.to_string());
+}
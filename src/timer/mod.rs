@@ -0,0 +1,128 @@
+#[cfg(test)]
+mod tests;
+
+use std::io;
+use state::{Reader, State, Writer};
+
+/// The DIV/TIMA/TMA/TAC timer, advanced one M-cycle at a time.
+///
+/// DIV is the upper byte of a free-running 16-bit counter. TIMA increments on the falling edge
+/// of the counter bit selected by TAC (ANDed with the enable bit), so writes to DIV and TAC can
+/// themselves cause an increment. An overflow leaves TIMA at 0x00 for one M-cycle before TMA is
+/// reloaded and the interrupt is requested.
+#[derive(Default)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    overflow: bool,
+    reloading: bool,
+}
+
+impl Timer {
+    #[inline]
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0x00 => 9,
+            0x01 => 3,
+            0x02 => 5,
+            _ => 7,
+        };
+        (self.tac & 0x04) != 0 && (self.counter & (1 << bit)) != 0
+    }
+
+    #[inline]
+    fn falling_edge(&mut self, before: bool) {
+        if before && !self.signal() {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            self.overflow = overflow;
+        }
+    }
+
+    /// Advances the timer by one M-cycle, returning `true` when the timer interrupt is requested.
+    pub fn tick(&mut self) -> bool {
+        self.reloading = false;
+        let mut interrupt = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            interrupt = true;
+        }
+        let before = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.falling_edge(before);
+        interrupt
+    }
+
+    #[inline]
+    pub fn div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+
+    #[inline]
+    pub fn tima(&self) -> u8 {
+        self.tima
+    }
+
+    #[inline]
+    pub fn tma(&self) -> u8 {
+        self.tma
+    }
+
+    #[inline]
+    pub fn tac(&self) -> u8 {
+        self.tac | 0xF8
+    }
+
+    pub fn write_div(&mut self) {
+        let before = self.signal();
+        self.counter = 0;
+        self.falling_edge(before);
+    }
+
+    /// Writing TIMA during the overflow delay cancels the reload; writing it on the cycle TMA is
+    /// reloaded is ignored.
+    pub fn write_tima(&mut self, value: u8) {
+        if !self.reloading {
+            self.tima = value;
+            self.overflow = false;
+        }
+    }
+
+    pub fn write_tma(&mut self, value: u8) {
+        self.tma = value;
+        if self.reloading {
+            self.tima = value;
+        }
+    }
+
+    pub fn write_tac(&mut self, value: u8) {
+        let before = self.signal();
+        self.tac = value & 0x07;
+        self.falling_edge(before);
+    }
+}
+
+impl State for Timer {
+    fn save(&self, writer: &mut Writer) {
+        writer.u16(self.counter);
+        writer.u8(self.tima);
+        writer.u8(self.tma);
+        writer.u8(self.tac);
+        writer.bool(self.overflow);
+        writer.bool(self.reloading);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.counter = reader.u16()?;
+        self.tima = reader.u8()?;
+        self.tma = reader.u8()?;
+        self.tac = reader.u8()?;
+        self.overflow = reader.bool()?;
+        self.reloading = reader.bool()?;
+        Ok(())
+    }
+}
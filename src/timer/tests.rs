@@ -0,0 +1,63 @@
+use super::*;
+
+#[test]
+fn div() {
+    let mut timer = Timer::default();
+    for _ in 0..64 {
+        assert_eq!(false, timer.tick());
+    }
+    assert_eq!(0x01, timer.div());
+    timer.write_div();
+    assert_eq!(0x00, timer.div());
+}
+
+#[test]
+fn tima() {
+    let mut timer = Timer::default();
+    timer.write_tac(0x05);
+    for _ in 0..4 {
+        timer.tick();
+    }
+    assert_eq!(0x01, timer.tima());
+    assert_eq!(0xFD, timer.tac());
+}
+
+#[test]
+fn overflow() {
+    let mut timer = Timer::default();
+    timer.write_tma(0x42);
+    timer.write_tima(0xFF);
+    timer.write_tac(0x05);
+    for _ in 0..4 {
+        assert_eq!(false, timer.tick());
+    }
+    assert_eq!(0x00, timer.tima());
+    assert_eq!(true, timer.tick());
+    assert_eq!(0x42, timer.tima());
+    timer.write_tima(0x00);
+    assert_eq!(0x42, timer.tima());
+}
+
+#[test]
+fn cancel_reload() {
+    let mut timer = Timer::default();
+    timer.write_tima(0xFF);
+    timer.write_tac(0x05);
+    for _ in 0..4 {
+        timer.tick();
+    }
+    timer.write_tima(0x10);
+    assert_eq!(false, timer.tick());
+    assert_eq!(0x10, timer.tima());
+}
+
+#[test]
+fn div_reset_edge() {
+    let mut timer = Timer::default();
+    timer.write_tac(0x05);
+    for _ in 0..2 {
+        timer.tick();
+    }
+    timer.write_div();
+    assert_eq!(0x01, timer.tima());
+}
@@ -0,0 +1,286 @@
+//! Runs the blargg (`cpu_instrs`, `instr_timing`, `mem_timing`, `oam_bug`) and mooneye
+//! acceptance test ROMs found under `tests/roms`, or the directory named by `GB18_TEST_ROMS`,
+//! booting each through the BIOS and reporting a result per ROM.
+//!
+//! ROMs listed (by path relative to the fixtures directory) in `known-failures.txt` there are
+//! still run and reported but don't fail the suite. When no fixtures are present the suite is
+//! skipped.
+
+extern crate gb18;
+
+use std::{env, fs, thread};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use gb18::{Cpu, Mmu, Mbc0, Mbc1};
+
+/// Clock cycles a ROM gets to report a result: two minutes of DMG time.
+const TIMEOUT: usize = 4_194_304 * 120;
+
+/// Clock cycles between checks of the serial output: one frame.
+const POLL: usize = 70_224;
+
+/// Mooneye ROMs send these over serial on success, and 0x42 six times on failure. They also
+/// leave them in B, C, D, E, H and L when they reach their final `ld b,b`.
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+static LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed(String),
+    Timeout(String),
+    Unsupported(u8),
+}
+
+/// Decides a result from the serial output so far, once a complete verdict has been sent.
+fn verdict(output: &[u8]) -> Option<Outcome> {
+    if output.ends_with(&FIBONACCI) {
+        return Some(Outcome::Passed);
+    }
+    if output.ends_with(&[0x42; 6]) {
+        return Some(Outcome::Failed("mooneye failure signature".to_string()));
+    }
+    let text = String::from_utf8_lossy(output);
+    for word in &["Passed", "Failed"] {
+        if let Some(index) = text.find(word) {
+            if !text[index..].contains('\n') {
+                return None;
+            }
+            return Some(if *word == "Passed" {
+                Outcome::Passed
+            } else {
+                Outcome::Failed(text.trim().to_string())
+            });
+        }
+    }
+    None
+}
+
+/// Decides a result from B, C, D, E, H and L at mooneye's `ld b,b` breakpoint.
+fn signature(cpu: &Cpu) -> Option<Outcome> {
+    let registers = cpu.registers();
+    let values = [registers.b(), registers.c(), registers.d(), registers.e(), registers.h(), registers.l()];
+    if values == FIBONACCI {
+        Some(Outcome::Passed)
+    } else if values == [0x42; 6] {
+        Some(Outcome::Failed("mooneye failure signature".to_string()))
+    } else {
+        None
+    }
+}
+
+fn run<M: Mmu>(mut mmu: M, output: fn(&M) -> &[u8]) -> Outcome {
+    let mut cpu = Cpu::default();
+    let mut pc = cpu.registers().pc();
+    let mut elapsed = 0;
+    while elapsed < TIMEOUT {
+        let mut frame = 0;
+        while frame < POLL {
+            let breakpoint = mmu.peek(pc) == 0x40;
+            frame += cpu.cycle(&mut mmu);
+            let next = cpu.registers().pc();
+            // Only once `ld b,b` has run, rather than an interrupt or HALT taking the cycle
+            if breakpoint && next == pc.wrapping_add(1) {
+                if let Some(outcome) = signature(&cpu) {
+                    return outcome;
+                }
+            }
+            pc = next;
+        }
+        elapsed += frame;
+        if let Some(outcome) = verdict(output(&mmu)) {
+            return outcome;
+        }
+    }
+    Outcome::Timeout(String::from_utf8_lossy(output(&mmu)).trim().to_string())
+}
+
+/// Runs a ROM on the memory controller its header asks for.
+fn run_rom(rom: Vec<u8>) -> Outcome {
+    match rom.get(0x0147).cloned().unwrap_or(0x00) {
+        0x00 => run(Mbc0::new(rom), Mbc0::serial_output),
+        0x01 ..= 0x03 => run(Mbc1::new(rom), Mbc1::serial_output),
+        kind => Outcome::Unsupported(kind),
+    }
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+}
+
+fn known_failures(dir: &Path) -> BTreeSet<String> {
+    fs::read_to_string(dir.join("known-failures.txt"))
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn conformance() {
+    let dir = env::var_os("GB18_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms"));
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    if roms.is_empty() {
+        eprintln!("conformance: no test ROMs under {}, skipping", dir.display());
+        return;
+    }
+    roms.sort();
+    let known = known_failures(&dir);
+
+    let queue = Arc::new(Mutex::new(roms.clone()));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let workers = thread::available_parallelism().map_or(1, |count| count.get());
+    let handles: Vec<_> = (0..workers).map(|_| {
+        let queue = queue.clone();
+        let results = results.clone();
+        thread::spawn(move || loop {
+            let path = match queue.lock().unwrap().pop() {
+                Some(path) => path,
+                None => return,
+            };
+            let outcome = match fs::read(&path) {
+                Ok(rom) => run_rom(rom),
+                Err(err) => Outcome::Failed(err.to_string()),
+            };
+            results.lock().unwrap().push((path, outcome));
+        })
+    }).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut regressions = Vec::new();
+    let mut passed = 0;
+    for (path, outcome) in &results {
+        let name = path.strip_prefix(&dir).unwrap_or(path).to_string_lossy().replace('\\', "/");
+        let expected = known.contains(&name);
+        let (status, detail) = match *outcome {
+            Outcome::Passed => ("PASS", String::new()),
+            Outcome::Failed(ref text) => ("FAIL", text.clone()),
+            Outcome::Timeout(ref text) => ("TIMEOUT", text.clone()),
+            Outcome::Unsupported(kind) => ("SKIP", format!("cartridge type ${:02X}", kind)),
+        };
+        match *outcome {
+            Outcome::Passed => {
+                passed += 1;
+                if expected {
+                    eprintln!("{:<8} {} (listed as a known failure)", status, name);
+                    continue;
+                }
+            }
+            Outcome::Failed(_) | Outcome::Timeout(_) if !expected => regressions.push(name.clone()),
+            _ => {}
+        }
+        eprintln!("{:<8} {} {}", status, name, detail.replace('\n', " "));
+    }
+    eprintln!("conformance: {}/{} passed", passed, results.len());
+    assert!(regressions.is_empty(), "unexpected failures: {:?}", regressions);
+}
+
+/// Assembles a ROM-only cartridge whose header passes the BIOS checks, running `program` at
+/// 0x0150.
+fn cartridge(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0x00; 0x8000];
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x0104..0x0134].copy_from_slice(&LOGO);
+    let checksum = rom[0x0134..0x014D].iter().fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+    rom[0x014D] = checksum;
+    rom[0x0150..0x0150 + program.len()].copy_from_slice(program);
+    rom
+}
+
+/// A program sending the zero-terminated string at 0x0200 over serial, then spinning.
+fn serial_program(message: &[u8]) -> Vec<u8> {
+    let mut rom = cartridge(&[
+        0x21, 0x00, 0x02,   // ld hl, $0200
+        0x2A,               // ld a, [hl+]
+        0xB7,               // or a
+        0x28, 0x0E,         // jr z, done
+        0xE0, 0x01,         // ldh [$FF01], a
+        0x3E, 0x81,         // ld a, $81
+        0xE0, 0x02,         // ldh [$FF02], a
+        0xF0, 0x02,         // ldh a, [$FF02]
+        0xCB, 0x7F,         // bit 7, a
+        0x20, 0xFA,         // jr nz, wait
+        0x18, 0xEE,         // jr loop
+        0x18, 0xFE,         // done: jr done
+    ]);
+    rom[0x0200..0x0200 + message.len()].copy_from_slice(message);
+    rom
+}
+
+#[test]
+fn synthetic() {
+    assert_eq!(Outcome::Passed, run_rom(serial_program(b"test\n\nPassed\n\0")));
+    assert_eq!(Outcome::Failed("test\n\nFailed #2".to_string()),
+               run_rom(serial_program(b"test\n\nFailed #2\n\0")));
+    assert_eq!(Outcome::Passed, run_rom(serial_program(&[3, 5, 8, 13, 21, 34, 0])));
+    assert_eq!(Outcome::Failed("mooneye failure signature".to_string()),
+               run_rom(serial_program(&[0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0])));
+}
+
+#[test]
+fn register_signature() {
+    let rom = cartridge(&[
+        0x01, 0x05, 0x03,   // ld bc, $0305
+        0x11, 0x0D, 0x08,   // ld de, $080D
+        0x21, 0x22, 0x15,   // ld hl, $1522
+        0x40,               // ld b, b
+        0x18, 0xFE,         // jr @
+    ]);
+    assert_eq!(Outcome::Passed, run_rom(rom));
+
+    // The signature only counts at the breakpoint, not while it's held on the way there
+    let rom = cartridge(&[
+        0x01, 0x42, 0x42,   // ld bc, $4242
+        0x11, 0x42, 0x42,   // ld de, $4242
+        0x21, 0x42, 0x42,   // ld hl, $4242
+        0x3E, 0x20,         // ld a, $20
+        0xE0, 0x80,         // ldh [$FF80], a
+        0xAF,               // outer: xor a
+        0x3D,               // inner: dec a
+        0x20, 0xFD,         // jr nz, inner
+        0xF0, 0x80,         // ldh a, [$FF80]
+        0x3D,               // dec a
+        0xE0, 0x80,         // ldh [$FF80], a
+        0x20, 0xF5,         // jr nz, outer
+        0x01, 0x05, 0x03,   // ld bc, $0305
+        0x11, 0x0D, 0x08,   // ld de, $080D
+        0x21, 0x22, 0x15,   // ld hl, $1522
+        0x40,               // ld b, b
+        0x18, 0xFE,         // jr @
+    ]);
+    assert_eq!(Outcome::Passed, run_rom(rom));
+}
+
+#[test]
+fn verdicts() {
+    assert_eq!(None, verdict(b"cpu_instrs\n\n01:ok "));
+    assert_eq!(None, verdict(b"Passed all tes"));
+    assert_eq!(Some(Outcome::Passed), verdict(b"cpu_instrs\n\nPassed all tests\n"));
+    assert_eq!(None, verdict(&[3, 5, 8]));
+}